@@ -1,27 +1,99 @@
 use crate::{
-    parser::{Compiler, FieldMap, Value},
-    ui::widgets::{KeyValueView, LineEdit, TableView, WidgetExt},
+    parser::{
+        logdata::{Columns, DedupFields, RawLines, Stats},
+        warnings::warn,
+    },
+    parser::{Compiler, DerivedField, FieldMap, ParseError, Query, Source, Value},
+    state::{default_filter, field_order, save_query, saved_query, UiState},
+    ui::model::DataModel,
+    ui::theme::Theme,
+    ui::widgets::{
+        DensityView, FilterOp, KeyBinding, KeyValueView, LineEdit, TableView, WidgetExt,
+    },
+    util::{format_count, LogZone},
     LogCollection, LogParser,
 };
-use chrono::NaiveDateTime;
+use chrono::{Duration as TimeStep, NaiveDateTime, NaiveTime};
+use cli_clipboard::{ClipboardContext, ClipboardProvider};
 use crossterm::{
     event,
-    event::{Event, KeyCode, KeyModifiers},
+    event::{
+        DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+        Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use regex::Regex;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    io,
+    process::Command,
+    rc::{Rc, Weak},
+    time::Duration,
 };
-use std::{cell::RefCell, error::Error, rc::Rc, time::Duration};
 use tui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::Paragraph,
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
 
-#[derive(Default)]
+/// Сколько символов значения попадает в условие поиска подстроки.
+const CONTAINS_PREFIX: usize = 64;
+
+/// Сколько первых записей просматривается для списка полей (`F2`).
+const FIELDS_SAMPLE: usize = 1000;
+
+/// Клавиши, которые `App::run` обрабатывает при любом активном виджете.
+const GLOBAL_KEYS: &[KeyBinding] = &[
+    KeyBinding::hint("Ctrl+Q", "Quit"),
+    KeyBinding::hint("Ctrl+F", "Search"),
+    KeyBinding::hint("Tab", "Next widget"),
+    KeyBinding::hint("F1", "Info"),
+    KeyBinding::hint("F2", "Fields"),
+    KeyBinding::hint("F5", "Reload"),
+];
+
+/// Клавиши таблицы, которые обрабатывает `App::run`, а не `TableView`.
+const TABLE_KEYS: &[KeyBinding] = &[
+    KeyBinding::hint(":", "Go to row/time"),
+    KeyBinding::hint("/", "Find"),
+    KeyBinding::hint("Y", "Copy as query"),
+    KeyBinding::hint("V", "Raw lines"),
+    KeyBinding::new("R", "Reload"),
+    KeyBinding::new("[/]", "Step back/forward in time"),
+    KeyBinding::hint("?", "Help"),
+];
+
+const INFO_KEYS: &[KeyBinding] = &[KeyBinding::hint("?", "Help")];
+
+const GOTO_KEYS: &[KeyBinding] = &[
+    KeyBinding::hint("Enter", "Run"),
+    KeyBinding::hint("Esc", "Cancel"),
+];
+
+/// Клавиши окна со списком полей (`F2`).
+const FIELDS_KEYS: &[KeyBinding] = &[
+    KeyBinding::new("Up/Down", "Select field"),
+    KeyBinding::new("Enter", "Add to filter"),
+    KeyBinding::new("Esc", "Close"),
+];
+
+#[derive(Clone, Copy, Debug, Default)]
 enum ActiveWidget {
     SearchBox,
 
+    QuickFilter,
+
+    GoTo,
+
+    Find,
+
     #[default]
     LogTable,
 
@@ -31,63 +103,161 @@ enum ActiveWidget {
 pub struct App {
     pub table: Rc<RefCell<TableView>>,
     pub search: Rc<RefCell<LineEdit>>,
+    /// Быстрый фильтр, который объединяется с основным через `AND` и не сохраняется.
+    pub quick: Rc<RefCell<LineEdit>>,
+    pub goto: Rc<RefCell<LineEdit>>,
+    /// Поиск по тексту записей (`/`), который переносит выделение, не скрывая строк.
+    pub find: Rc<RefCell<LineEdit>>,
     pub text: Rc<RefCell<KeyValueView>>,
+    pub density: Rc<RefCell<DensityView>>,
     pub log_data: Rc<RefCell<LogCollection>>,
 
     pub prev_size: (u16, u16),
 
+    density_area: Rect,
+    table_area: Rect,
+    text_area: Rect,
+
     state: ActiveWidget,
+    /// Показано окно со сведениями о загрузке (`F1`).
+    show_info: bool,
+    /// Показано окно справки по клавишам (`?`), закрывается любой клавишей.
+    show_help: bool,
+    /// Окно со списком полей (`F2`): имя, пример значения и выбранная строка.
+    fields: Option<(Vec<(String, String)>, ListState)>,
+    /// Поля, по которым строится фильтр выделенной записи (`Y` в таблице).
+    copy_fields: Vec<String>,
+    /// Таблица показывает исходный текст записей вместо колонок (`V`).
+    raw_lines: bool,
+    /// Шаг перехода по времени клавишами `[` и `]`.
+    time_step: TimeStep,
+    /// Цвета интерфейса, загруженные при запуске.
+    theme: Theme,
+    /// Нажата `Ctrl+Q`: `App::run` сохраняет состояние и завершается.
+    quit: bool,
+    /// Значение из панели полей, которое нужно открыть в `$PAGER` после обработки события.
+    open_value: Rc<RefCell<Option<String>>>,
+    /// Источник и параметры чтения, с которыми логи перечитываются (`F5`).
+    source: Source,
+    date: Option<NaiveDateTime>,
+    assume_hour: Option<NaiveDateTime>,
 }
 
 impl App {
-    pub fn new<T: Into<String>>(dir: T, date: Option<NaiveDateTime>) -> Self {
-        let dir = dir.into();
-        let widths = vec![
-            Constraint::Percentage(20),
-            Constraint::Percentage(20),
-            Constraint::Percentage(20),
-            Constraint::Percentage(20),
-            Constraint::Percentage(20),
-        ];
-
-        let log_data = Rc::new(RefCell::new(LogCollection::new(LogParser::parse(
-            dir, date,
-        ))));
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        source: Source,
+        date: Option<NaiveDateTime>,
+        assume_hour: Option<NaiveDateTime>,
+        time_format: Option<String>,
+        raw_time: bool,
+        event_labels: HashMap<String, String>,
+        dedup: Option<DedupFields>,
+        max_lines: Option<usize>,
+        human_durations: bool,
+        derived: Vec<DerivedField>,
+        columns: Columns,
+        copy_fields: Vec<String>,
+        time_step: TimeStep,
+        zone: LogZone,
+    ) -> Self {
+        let roots = match &source {
+            Source::Directories(dirs) => dirs.clone(),
+            Source::Stdin(_) => vec![],
+        };
+        let log_data = Rc::new(RefCell::new(LogCollection::with_max_lines(
+            LogParser::parse_source(source.clone(), date, assume_hour),
+            max_lines,
+        )));
+        log_data.borrow().set_source(source.to_string());
+        log_data.borrow().set_zone(zone);
+        log_data.borrow().set_time_format(time_format);
+        log_data.borrow().set_raw_time(raw_time);
+        log_data.borrow().set_human_durations(human_durations);
+        log_data.borrow().set_event_labels(event_labels);
+        log_data.borrow().set_derived(derived);
+        log_data.borrow().set_roots(roots);
+        log_data.borrow().set_columns(columns);
+        // Колонки `auto` могут измениться после загрузки, тогда таблица делит ширину поровну
+        let count = log_data.borrow().cols();
+        let widths = vec![Constraint::Percentage(100 / count.max(1) as u16); count];
+        if dedup.is_some() {
+            log_data.borrow().set_dedup(dedup);
+        }
 
+        let theme = Theme::load();
         let mut table_view = TableView::new(widths);
         table_view.set_model(log_data.clone());
+        table_view.set_style(theme.table_style());
+        let mut density = DensityView::new();
+        density.set_model(log_data.clone());
 
         let app = Self {
             table: Rc::new(RefCell::new(table_view)),
             search: Rc::new(RefCell::new(LineEdit::new("Filter".into()))),
+            quick: Rc::new(RefCell::new(LineEdit::new("Quick filter".into()))),
+            goto: Rc::new(RefCell::new(LineEdit::new(
+                "Go to (row or HH:MM:SS) | save <name> | load <name>".into(),
+            ))),
+            find: Rc::new(RefCell::new(LineEdit::new("Find (text or /regex/)".into()))),
             text: Rc::new(RefCell::new(KeyValueView::new())),
+            density: Rc::new(RefCell::new(density)),
             log_data: log_data.clone(),
             prev_size: (0, 0),
+            density_area: Rect::default(),
+            table_area: Rect::default(),
+            text_area: Rect::default(),
             state: ActiveWidget::default(),
+            show_info: false,
+            show_help: false,
+            fields: None,
+            copy_fields,
+            raw_lines: false,
+            time_step,
+            quit: false,
+            theme: theme.clone(),
+            open_value: Rc::new(RefCell::new(None)),
+            source,
+            date,
+            assume_hour,
         };
 
         app.table.borrow_mut().set_focus(true);
+        for edit in [&app.search, &app.quick, &app.goto, &app.find] {
+            edit.borrow_mut().set_focus_style(theme.focus_style());
+        }
+        app.text.borrow_mut().set_style(
+            theme.focus_style(),
+            Style::default().fg(theme.info_selection),
+        );
+        app.text.borrow_mut().set_field_order(field_order());
+
+        for (edit, other, base) in [
+            (&app.search, &app.quick, true),
+            (&app.quick, &app.search, false),
+        ] {
+            let other = Rc::downgrade(other);
+            let log_data = Rc::downgrade(&app.log_data);
+            let table = Rc::downgrade(&app.table);
+            edit.borrow_mut()
+                .on_changed(move |sender| apply_filters(sender, base, &other, &log_data, &table));
+        }
+
+        let open_value = Rc::downgrade(&app.open_value);
+        app.text.borrow_mut().on_open(move |value| {
+            if let Some(open_value) = open_value.upgrade() {
+                *open_value.borrow_mut() = Some(value);
+            }
+        });
 
         let log_data = Rc::downgrade(&app.log_data);
         let table = Rc::downgrade(&app.table);
-        app.search
-            .borrow_mut()
-            .on_changed(move |sender| match log_data.upgrade() {
-                Some(model) => match model.borrow_mut().set_filter(sender.text().to_string()) {
-                    Err(e) => {
-                        sender.set_border_text(e.to_string());
-                        sender.set_style(Style::default().fg(Color::Red));
-                    }
-                    _ => {
-                        sender.set_border_text(String::new());
-                        sender.set_style(Style::default());
-                        if let Some(table) = table.upgrade() {
-                            table.borrow_mut().reset_state();
-                        }
-                    }
-                },
-                None => {}
-            });
+        app.density.borrow_mut().on_bucket_clicked(move |time| {
+            if let (Some(log_data), Some(table)) = (log_data.upgrade(), table.upgrade()) {
+                let row = log_data.borrow().find_time(time);
+                table.borrow_mut().select_row(row);
+            }
+        });
 
         let text = Rc::downgrade(&app.text);
         let log_data = Rc::downgrade(&app.log_data);
@@ -97,133 +267,788 @@ impl App {
                 if let (Some(log_data), Some(text)) = (log_data.upgrade(), text.upgrade()) {
                     if let Some(index) = index {
                         if let Some(line) = log_data.borrow().line(index) {
-                            text.borrow_mut().set_data(line.fields().into());
+                            text.borrow_mut().set_data(line.field_map(), Some(line));
                             return;
                         }
                     }
 
                     // Panic if we can't borrow. Because dont need reset state when filter from info widget.
                     if let Ok(mut borrowed) = text.try_borrow_mut() {
-                        borrowed.set_data(FieldMap::new());
+                        borrowed.set_data(FieldMap::new(), None);
                     }
                 }
             });
 
         let search = Rc::downgrade(&app.search);
-        app.text.borrow_mut().on_add_to_filter(move |(key, value)| {
-            if let Some(search) = search.upgrade() {
-                let value = match value {
-                    Value::String(s) => format!("\"{}\"", s),
-                    Value::Number(n) => n.to_string(),
-                    Value::DateTime(n) => format!("'{}'", n.format("%Y-%m-%d %H:%M:%S%.9f")),
-                    _ => unreachable!(),
-                };
+        let zone = app.log_data.borrow().zone();
+        app.text
+            .borrow_mut()
+            .on_add_to_filter(move |(key, value, op)| {
+                if let Some(search) = search.upgrade() {
+                    let condition = match filter_condition(&key, value, op) {
+                        Some(condition) => condition,
+                        None => return,
+                    };
 
-                let mut search_borrowed = search.borrow_mut();
-                search_borrowed.show();
-                let text = search_borrowed.text().to_string();
-                if text.trim().is_empty() {
-                    search_borrowed.set_text(format!(r#"WHERE {} = {}"#, key, value));
-                } else if let Ok(query) = Compiler::new().compile(text.trim()) {
-                    if !query.is_regex() {
-                        search_borrowed.set_text(format!(r#"{} AND {} = {}"#, text, key, value));
+                    let mut search_borrowed = search.borrow_mut();
+                    search_borrowed.show();
+                    let text = search_borrowed.text().to_string();
+                    if text.trim().is_empty() {
+                        search_borrowed.set_text(format!("WHERE {}", condition));
+                    } else if let Ok(query) = Compiler::with_zone(zone).compile(text.trim()) {
+                        if !query.is_full_text() {
+                            search_borrowed.set_text(format!("{} AND {}", text, condition));
+                        }
                     }
                 }
-            }
-        });
+            });
 
+        app.restore_state(UiState::load());
         app
     }
 
-    pub fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<(), Box<dyn Error>> {
+    /// Восстанавливает фильтр, видимость поиска, выделение и ширины колонок с прошлого запуска.
+    /// Без сохраненного фильтра применяется фильтр по умолчанию (`default_filter`).
+    /// Фильтр, который не компилируется, сбрасывается.
+    fn restore_state(&self, state: UiState) {
+        let mut search = self.search.borrow_mut();
+        search.set_visible(state.search_visible);
+        let filter = match state.filter.is_empty() {
+            false => Some((true, state.filter)),
+            true => default_filter().map(|filter| (false, filter)),
+        };
+        if let Some((saved, filter)) = filter {
+            search.set_text(filter);
+            // Фильтр по умолчанию показывается, чтобы было видно, что записи отфильтрованы
+            search.set_visible(state.search_visible || !saved);
+            let zone = self.log_data.borrow().zone();
+            if let Err(e) = Compiler::with_zone(zone).compile(search.text()) {
+                let origin = if saved { "Saved" } else { "Default" };
+                search.set_text(String::new());
+                search.set_visible(true);
+                search.set_border_text(format!("{} filter dropped: {}", origin, e));
+                search.set_style(Style::default().fg(Color::Red));
+            }
+        }
+        drop(search);
+
+        let mut table = self.table.borrow_mut();
+        if !state.widths.is_empty() {
+            table.set_widths(
+                state
+                    .widths
+                    .into_iter()
+                    .map(Constraint::Percentage)
+                    .collect(),
+            );
+        }
+        if let Some(id) = state.selected {
+            table.select_id(id);
+        }
+    }
+
+    /// Время самой поздней загруженной записи, для `--since-last`.
+    pub fn last_time(&self) -> Option<NaiveDateTime> {
+        self.log_data.borrow().last_time()
+    }
+
+    fn save_state(&self) {
+        let search = self.search.borrow();
+        let state = UiState {
+            filter: search.text().to_string(),
+            search_visible: search.visible(),
+            selected: self.table.borrow().selected_id(),
+            widths: self.table.borrow().widths(),
+        };
+        if let Err(e) = state.save() {
+            warn(format!("can't save ui state: {}", e));
+        }
+    }
+
+    /// Экран перерисовывается после событий и изменений коллекции,
+    /// но не чаще `fps` раз в секунду.
+    pub fn run<B: Backend>(
+        &mut self,
+        terminal: &mut Terminal<B>,
+        fps: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        let frame = Duration::from_secs(1) / fps.max(1);
+        let mut drawn = None;
         loop {
-            terminal.draw(|f| ui(f, self))?;
+            let version = self.log_data.borrow().version();
+            if drawn != Some(version) {
+                self.table.borrow_mut().sync_selection();
+                terminal.draw(|f| ui(f, self))?;
+                drawn = Some(version);
+            }
 
-            if event::poll(Duration::from_millis(100))? {
+            if event::poll(frame)? {
+                drawn = None;
                 let event = event::read()?;
                 match event {
-                    Event::Key(key) => match key.code {
-                        KeyCode::Char('q') if key.modifiers == KeyModifiers::CONTROL => {
-                            return Ok(())
-                        }
-                        KeyCode::Char('f') if key.modifiers == KeyModifiers::CONTROL => {
-                            match self.state {
-                                ActiveWidget::LogTable | ActiveWidget::InfoView => {
-                                    self.search.borrow_mut().set_visible(true);
-                                    self.set_active_widget(ActiveWidget::SearchBox);
-                                }
-                                ActiveWidget::SearchBox => {
-                                    self.search.borrow_mut().set_visible(false);
-                                    self.set_active_widget(ActiveWidget::LogTable);
-                                }
-                            }
+                    Event::Key(key) => {
+                        self.key_press_event(key);
+                        if self.quit {
+                            self.save_state();
+                            return Ok(());
                         }
-                        KeyCode::Tab => {
-                            // Next active widget
-                            match self.state {
-                                ActiveWidget::LogTable => {
-                                    self.set_active_widget(ActiveWidget::InfoView);
-                                }
-                                ActiveWidget::SearchBox => {
-                                    self.set_active_widget(ActiveWidget::LogTable);
-                                }
-                                ActiveWidget::InfoView => {
-                                    if self.search.borrow().visible() {
-                                        self.set_active_widget(ActiveWidget::SearchBox);
-                                    } else {
-                                        self.set_active_widget(ActiveWidget::LogTable);
-                                    }
-                                }
-                            }
-                        }
-                        _ => match self.state {
-                            ActiveWidget::LogTable => self.table.borrow_mut().key_press_event(key),
-                            ActiveWidget::SearchBox => {
-                                self.search.borrow_mut().key_press_event(key)
-                            }
-                            ActiveWidget::InfoView => self.text.borrow_mut().key_press_event(key),
-                        },
+                    }
+                    Event::Mouse(mouse) => self.mouse_event(mouse),
+                    Event::Paste(text) => match self.state {
+                        ActiveWidget::SearchBox => self.search.borrow_mut().paste_event(&text),
+                        ActiveWidget::QuickFilter => self.quick.borrow_mut().paste_event(&text),
+                        ActiveWidget::GoTo => self.goto.borrow_mut().paste_event(&text),
+                        ActiveWidget::Find => self.find.borrow_mut().paste_event(&text),
+                        ActiveWidget::LogTable | ActiveWidget::InfoView => {}
                     },
                     _ => {}
                 }
+
+                let value = self.open_value.borrow_mut().take();
+                if let Some(value) = value {
+                    if let Err(e) = open_external(terminal, &value) {
+                        warn(format!("can't open value: {}", e));
+                    }
+                }
             }
         }
     }
 
-    fn set_active_widget(&mut self, widget: ActiveWidget) {
-        match widget {
-            ActiveWidget::LogTable => {
-                self.table.borrow_mut().set_focus(true);
-                self.search.borrow_mut().set_focus(false);
-                self.text.borrow_mut().set_focus(false)
+    /// Обрабатывает клавишу: сначала общие клавиши, затем клавиши активного виджета.
+    /// Возвращает `false`, если клавиша ничего не делает.
+    fn key_press_event(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            _ if self.show_help => self.show_help = false,
+            KeyCode::Char('q') if key.modifiers == KeyModifiers::CONTROL => self.quit = true,
+            KeyCode::F(2) => self.toggle_fields(),
+            KeyCode::Up | KeyCode::Down if self.fields.is_some() => {
+                if let Some((names, state)) = &mut self.fields {
+                    let selected = state.selected().unwrap_or_default();
+                    state.select(Some(match key.code {
+                        KeyCode::Up => selected.saturating_sub(1),
+                        _ => (selected + 1).min(names.len().saturating_sub(1)),
+                    }));
+                }
+            }
+            KeyCode::Enter if self.fields.is_some() => self.insert_field(),
+            KeyCode::Esc if self.fields.is_some() => self.fields = None,
+            KeyCode::Char('f') if key.modifiers == KeyModifiers::CONTROL => match self.state {
+                ActiveWidget::LogTable
+                | ActiveWidget::InfoView
+                | ActiveWidget::GoTo
+                | ActiveWidget::Find => {
+                    self.goto.borrow_mut().set_visible(false);
+                    self.find.borrow_mut().set_visible(false);
+                    self.search.borrow_mut().set_visible(true);
+                    self.set_active_widget(ActiveWidget::SearchBox);
+                }
+                ActiveWidget::SearchBox | ActiveWidget::QuickFilter => {
+                    self.search.borrow_mut().set_visible(false);
+                    self.set_active_widget(ActiveWidget::LogTable);
+                }
+            },
+            KeyCode::Char('y') if matches!(self.state, ActiveWidget::LogTable) => {
+                self.copy_row_query()
+            }
+            KeyCode::Char('v') if matches!(self.state, ActiveWidget::LogTable) => {
+                self.toggle_raw_lines()
+            }
+            KeyCode::Char(c @ ('[' | ']')) if matches!(self.state, ActiveWidget::LogTable) => {
+                self.step_time(c == ']')
+            }
+            KeyCode::F(5) => self.reload(),
+            KeyCode::Char('r') if matches!(self.state, ActiveWidget::LogTable) => self.reload(),
+            KeyCode::Char(':') if matches!(self.state, ActiveWidget::LogTable) => {
+                self.find.borrow_mut().set_visible(false);
+                let mut goto = self.goto.borrow_mut();
+                goto.set_visible(true);
+                goto.set_text(String::new());
+                drop(goto);
+                self.set_active_widget(ActiveWidget::GoTo);
+            }
+            KeyCode::Char('/') if matches!(self.state, ActiveWidget::LogTable) => {
+                self.goto.borrow_mut().set_visible(false);
+                let mut find = self.find.borrow_mut();
+                find.set_visible(true);
+                find.set_text(String::new());
+                drop(find);
+                self.set_active_widget(ActiveWidget::Find);
+            }
+            KeyCode::Enter if matches!(self.state, ActiveWidget::Find) => {
+                let text = self.find.borrow().text().to_string();
+                match self.find_text(&text) {
+                    Ok(()) => {
+                        self.find.borrow_mut().set_visible(false);
+                        self.set_active_widget(ActiveWidget::LogTable);
+                    }
+                    Err(message) => {
+                        let mut find = self.find.borrow_mut();
+                        find.set_border_text(message);
+                        find.set_style(Style::default().fg(Color::Red));
+                    }
+                }
+            }
+            KeyCode::Enter if matches!(self.state, ActiveWidget::GoTo) => {
+                let text = self.goto.borrow().text().trim().to_string();
+                match self.execute_command(&text) {
+                    Ok(()) => {
+                        self.goto.borrow_mut().set_visible(false);
+                        self.set_active_widget(ActiveWidget::LogTable);
+                    }
+                    Err(message) => {
+                        let mut goto = self.goto.borrow_mut();
+                        goto.set_border_text(message);
+                        goto.set_style(Style::default().fg(Color::Red));
+                    }
+                }
             }
-            ActiveWidget::SearchBox => {
-                self.table.borrow_mut().set_focus(false);
-                self.search.borrow_mut().set_focus(true);
-                self.text.borrow_mut().set_focus(false)
+            KeyCode::F(1) => self.show_info = !self.show_info,
+            KeyCode::Char('?')
+                if matches!(self.state, ActiveWidget::LogTable)
+                    || matches!(self.state, ActiveWidget::InfoView)
+                        && !self.text.borrow().editing_filter() =>
+            {
+                self.show_help = true
             }
-            ActiveWidget::InfoView => {
-                self.table.borrow_mut().set_focus(false);
-                self.search.borrow_mut().set_focus(false);
-                self.text.borrow_mut().set_focus(true)
+            KeyCode::Esc if self.show_info => self.show_info = false,
+            KeyCode::Esc if matches!(self.state, ActiveWidget::GoTo) => {
+                self.goto.borrow_mut().set_visible(false);
+                self.set_active_widget(ActiveWidget::LogTable);
             }
+            KeyCode::Esc if matches!(self.state, ActiveWidget::Find) => {
+                self.find.borrow_mut().set_visible(false);
+                self.set_active_widget(ActiveWidget::LogTable);
+            }
+            KeyCode::Tab => {
+                // Next active widget
+                match self.state {
+                    ActiveWidget::LogTable => {
+                        self.set_active_widget(ActiveWidget::InfoView);
+                    }
+                    ActiveWidget::SearchBox => {
+                        self.set_active_widget(ActiveWidget::QuickFilter);
+                    }
+                    ActiveWidget::QuickFilter | ActiveWidget::GoTo | ActiveWidget::Find => {
+                        self.set_active_widget(ActiveWidget::LogTable);
+                    }
+                    ActiveWidget::InfoView => {
+                        if self.search.borrow().visible() {
+                            self.set_active_widget(ActiveWidget::SearchBox);
+                        } else {
+                            self.set_active_widget(ActiveWidget::LogTable);
+                        }
+                    }
+                }
+            }
+            _ => {
+                return match self.state {
+                    ActiveWidget::LogTable => self.table.borrow_mut().key_press_event(key),
+                    ActiveWidget::SearchBox => self.search.borrow_mut().key_press_event(key),
+                    ActiveWidget::QuickFilter => self.quick.borrow_mut().key_press_event(key),
+                    ActiveWidget::GoTo => {
+                        let mut goto = self.goto.borrow_mut();
+                        let handled = goto.key_press_event(key);
+                        goto.set_border_text(String::new());
+                        goto.set_style(Style::default());
+                        handled
+                    }
+                    ActiveWidget::Find => {
+                        let mut find = self.find.borrow_mut();
+                        let handled = find.key_press_event(key);
+                        find.set_border_text(String::new());
+                        find.set_style(Style::default());
+                        handled
+                    }
+                    ActiveWidget::InfoView => self.text.borrow_mut().key_press_event(key),
+                };
+            }
+        }
+        true
+    }
+
+    fn mouse_event(&mut self, mut event: MouseEvent) {
+        let contains = |area: Rect| {
+            event.column >= area.left()
+                && event.column < area.right()
+                && event.row >= area.top()
+                && event.row < area.bottom()
+        };
+
+        if contains(self.density_area) {
+            event.column = event.column.saturating_sub(self.density_area.left());
+            event.row = event.row.saturating_sub(self.density_area.top());
+            self.density.borrow_mut().mouse_event(event);
+            return;
+        }
+
+        if let MouseEventKind::Down(MouseButton::Left) = event.kind {
+            if contains(self.table_area) {
+                self.set_active_widget(ActiveWidget::LogTable);
+            } else if contains(self.text_area) {
+                self.set_active_widget(ActiveWidget::InfoView);
+            } else {
+                return;
+            }
+        }
+
+        let area = match self.state {
+            ActiveWidget::LogTable => self.table_area,
+            ActiveWidget::InfoView => self.text_area,
+            ActiveWidget::SearchBox
+            | ActiveWidget::QuickFilter
+            | ActiveWidget::GoTo
+            | ActiveWidget::Find => return,
+        };
+        event.column = event.column.saturating_sub(area.left());
+        event.row = event.row.saturating_sub(area.top());
+
+        match self.state {
+            ActiveWidget::LogTable => self.table.borrow_mut().mouse_event(event),
+            ActiveWidget::InfoView => self.text.borrow_mut().mouse_event(event),
+            ActiveWidget::SearchBox
+            | ActiveWidget::QuickFilter
+            | ActiveWidget::GoTo
+            | ActiveWidget::Find => {}
+        }
+    }
+
+    /// Выполняет команду из строки `:`: `save <name>` и `load <name>` сохраняют
+    /// и загружают именованный фильтр, остальное считается переходом к строке или времени.
+    fn execute_command(&mut self, text: &str) -> Result<(), String> {
+        if let Some(name) = text.strip_prefix("save ") {
+            let query = self.search.borrow().text().trim().to_string();
+            if query.is_empty() {
+                return Err(String::from("Filter is empty"));
+            }
+            return save_query(name, &query).map_err(|e| e.to_string());
+        }
+
+        if let Some(name) = text.strip_prefix("load ") {
+            let query = saved_query(name).ok_or(format!("No saved query `{}`", name.trim()))?;
+            let mut search = self.search.borrow_mut();
+            search.show();
+            search.set_text(query);
+            return Ok(());
+        }
+
+        match self.go_to(text) {
+            true => Ok(()),
+            false => Err(String::from("Invalid row or time")),
+        }
+    }
+
+    /// Перемещает выделение таблицы на строку с номером (с 1) или на первую
+    /// запись не раньше указанного времени. Возвращает `false`, если значение не распознано.
+    fn go_to(&mut self, text: &str) -> bool {
+        if let Ok(row) = text.parse::<usize>() {
+            self.table.borrow_mut().select_row(row.saturating_sub(1));
+            return true;
+        }
+
+        let time = match NaiveTime::parse_from_str(text, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(text, "%H:%M"))
+        {
+            Ok(time) => time,
+            Err(_) => return false,
+        };
+
+        let log_data = self.log_data.borrow();
+        let selected = self.table.borrow().selected();
+        let date = match selected
+            .or(Some(0))
+            .and_then(|row| log_data.line(row))
+            .map(|line| line.time().date())
+        {
+            Some(date) => date,
+            None => return true,
+        };
+
+        let row = log_data.find_time(date.and_time(time));
+        drop(log_data);
+        self.table.borrow_mut().select_row(row);
+        true
+    }
+
+    /// Задает образец поиска `/` и переходит к первому совпадению после выделенной строки.
+    /// Пустая строка снимает поиск.
+    fn find_text(&mut self, text: &str) -> Result<(), String> {
+        let pattern = find_pattern(text)?;
+        let mut table = self.table.borrow_mut();
+        let found = pattern.is_none();
+        table.set_find(pattern);
+        if found || table.find_next(true) {
+            return Ok(());
+        }
+
+        table.set_find(None);
+        Err(String::from("No matches"))
+    }
+
+    /// Перемещает выделение на первую запись не раньше, чем через `time_step` после выделенной,
+    /// или на ближайшую запись не позже, чем за `time_step` до нее.
+    fn step_time(&mut self, forward: bool) {
+        let selected = self.table.borrow().selected();
+        let log_data = self.log_data.borrow();
+        let time = match selected.and_then(|row| log_data.line(row)) {
+            Some(line) => line.time(),
+            None => return,
+        };
+
+        let row = match forward {
+            true => log_data.find_time(time + self.time_step),
+            false => log_data.find_time_before(time - self.time_step),
+        };
+        drop(log_data);
+        self.table.borrow_mut().select_row(row);
+    }
+
+    /// Переключает таблицу между колонками и исходным текстом записей.
+    /// Строки у обоих видов одни и те же, поэтому выделенная запись сохраняется.
+    fn toggle_raw_lines(&mut self) {
+        self.raw_lines = !self.raw_lines;
+        let model: Rc<RefCell<dyn DataModel>> = match self.raw_lines {
+            true => Rc::new(RefCell::new(RawLines::new(self.log_data.clone()))),
+            false => self.log_data.clone(),
+        };
+
+        let mut table = self.table.borrow_mut();
+        let id = table.selected_id();
+        table.set_model(model);
+        if let Some(id) = id {
+            table.select_id(id);
+        }
+    }
+
+    /// Копирует в буфер обмена фильтр по полям `copy_fields` выделенной записи.
+    fn copy_row_query(&self) {
+        let line = match self.table.borrow().selected() {
+            Some(index) => self.log_data.borrow().line(index),
+            None => None,
+        };
+        let query = match line {
+            Some(line) => row_query(&line.field_map(), &self.copy_fields),
+            None => None,
+        };
+
+        if let (Some(query), Ok(mut ctx)) = (query, ClipboardContext::new()) {
+            let _ = ctx.set_contents(query);
+        }
+    }
+
+    /// Перечитывает каталоги с теми же параметрами; фильтр применяется заново.
+    /// Записи со стандартного ввода перечитать нельзя.
+    fn reload(&mut self) {
+        if let Source::Stdin(_) = self.source {
+            return;
         }
 
+        let receiver = LogParser::parse_source(self.source.clone(), self.date, self.assume_hour);
+        let log_data = self.log_data.borrow().reload(receiver);
+        *self.log_data.borrow_mut() = log_data;
+        self.table.borrow_mut().reset_state();
+    }
+
+    fn toggle_fields(&mut self) {
+        self.fields = match self.fields {
+            Some(_) => None,
+            None => {
+                let names = self.log_data.borrow().field_names(FIELDS_SAMPLE);
+                let mut state = ListState::default();
+                state.select((!names.is_empty()).then_some(0));
+                Some((names, state))
+            }
+        };
+    }
+
+    /// Добавляет в строку поиска начало условия по выбранному в списке полю.
+    fn insert_field(&mut self) {
+        let key = match self.fields.take() {
+            Some((names, state)) => match state.selected().and_then(|i| names.get(i)) {
+                Some((key, _)) => field_identifier(key),
+                None => return,
+            },
+            None => return,
+        };
+
+        let mut search = self.search.borrow_mut();
+        let text = search.text().trim_end().to_string();
+        search.set_visible(true);
+        search.set_text(match text.is_empty() {
+            true => format!("WHERE {} = ", key),
+            false => format!("{} AND {} = ", text, key),
+        });
+        drop(search);
+        self.set_active_widget(ActiveWidget::SearchBox);
+    }
+
+    /// Клавиши виджета: обрабатываемые в `App::run` и в его `key_press_event`.
+    fn key_bindings(&self, widget: &ActiveWidget) -> Vec<&'static KeyBinding> {
+        let (app, widget): (&'static [KeyBinding], _) = match widget {
+            ActiveWidget::LogTable => (TABLE_KEYS, self.table.borrow().key_bindings()),
+            ActiveWidget::InfoView => (INFO_KEYS, self.text.borrow().key_bindings()),
+            ActiveWidget::SearchBox => (&[], self.search.borrow().key_bindings()),
+            ActiveWidget::QuickFilter => (&[], self.quick.borrow().key_bindings()),
+            ActiveWidget::GoTo => (GOTO_KEYS, self.goto.borrow().key_bindings()),
+            ActiveWidget::Find => (GOTO_KEYS, self.find.borrow().key_bindings()),
+        };
+        widget.iter().chain(app).collect()
+    }
+
+    /// Разделы окна справки. Строка подсказок строится из тех же списков.
+    fn help_sections(&self) -> Vec<(&'static str, Vec<&'static KeyBinding>)> {
+        vec![
+            ("Global", GLOBAL_KEYS.iter().collect()),
+            ("Table", self.key_bindings(&ActiveWidget::LogTable)),
+            ("Details", self.key_bindings(&ActiveWidget::InfoView)),
+            ("Filter", self.key_bindings(&ActiveWidget::SearchBox)),
+            ("Go to", self.key_bindings(&ActiveWidget::GoTo)),
+            ("Find", self.key_bindings(&ActiveWidget::Find)),
+            ("Fields", FIELDS_KEYS.iter().collect()),
+        ]
+    }
+
+    fn set_active_widget(&mut self, widget: ActiveWidget) {
+        self.table
+            .borrow_mut()
+            .set_focus(matches!(widget, ActiveWidget::LogTable));
+        self.search
+            .borrow_mut()
+            .set_focus(matches!(widget, ActiveWidget::SearchBox));
+        self.quick
+            .borrow_mut()
+            .set_focus(matches!(widget, ActiveWidget::QuickFilter));
+        self.goto
+            .borrow_mut()
+            .set_focus(matches!(widget, ActiveWidget::GoTo));
+        self.find
+            .borrow_mut()
+            .set_focus(matches!(widget, ActiveWidget::Find));
+        self.text
+            .borrow_mut()
+            .set_focus(matches!(widget, ActiveWidget::InfoView));
+
         self.state = widget;
     }
 }
 
+/// Образец поиска `/`: `/regex/` - регулярное выражение, иначе подстрока.
+/// Пустая строка - поиск снят.
+fn find_pattern(text: &str) -> Result<Option<Regex>, String> {
+    let pattern = match text
+        .strip_prefix('/')
+        .and_then(|text| text.strip_suffix('/'))
+    {
+        Some(regex) => regex.to_string(),
+        None if text.is_empty() => return Ok(None),
+        None => regex::escape(text),
+    };
+    Regex::new(&pattern).map(Some).map_err(|e| e.to_string())
+}
+
+/// Компилирует фильтр из строки поиска. Пустая строка - отсутствие фильтра.
+fn compile_filter(text: &str, zone: LogZone) -> Result<Option<Query>, ParseError> {
+    match text.trim().is_empty() {
+        true => Ok(None),
+        false => Compiler::with_zone(zone).compile(text).map(Some),
+    }
+}
+
+/// Применяет фильтр измененной строки `sender` вместе с фильтром второй строки `other`.
+/// `base` - изменена основная строка (она идет первой в `AND`).
+/// Ошибка компиляции показывается в рамке строки, а некорректная вторая строка не учитывается.
+fn apply_filters(
+    sender: &mut LineEdit,
+    base: bool,
+    other: &Weak<RefCell<LineEdit>>,
+    log_data: &Weak<RefCell<LogCollection>>,
+    table: &Weak<RefCell<TableView>>,
+) {
+    let zone = log_data
+        .upgrade()
+        .map_or(LogZone::Local, |model| model.borrow().zone());
+    let query = match compile_filter(sender.text(), zone) {
+        Ok(query) => query,
+        Err(e) => {
+            sender.set_border_text(e.to_string());
+            sender.set_style(Style::default().fg(Color::Red));
+            return;
+        }
+    };
+    // Опечатка в имени поля не ошибка: поле может встретиться в следующих записях
+    let warning = match (&query, log_data.upgrade()) {
+        (Some(query), Some(model)) => {
+            let known = model.borrow().known_fields(FIELDS_SAMPLE);
+            query.unknown_field(&known.iter().map(String::as_str).collect::<Vec<_>>())
+        }
+        _ => None,
+    };
+    match warning {
+        Some(warning) => {
+            sender.set_border_text(warning);
+            sender.set_style(Style::default().fg(Color::Yellow));
+        }
+        None => {
+            sender.set_border_text(String::new());
+            sender.set_style(Style::default());
+        }
+    }
+
+    let other = other
+        .upgrade()
+        .and_then(|other| compile_filter(other.borrow().text(), zone).ok().flatten());
+    let query = match base {
+        true => Query::and(query, other),
+        false => Query::and(other, query),
+    };
+
+    if let Some(model) = log_data.upgrade() {
+        model.borrow().set_query(query.clone());
+    }
+    if let Some(table) = table.upgrade() {
+        table.borrow_mut().set_query(query);
+        table.borrow_mut().reset_state();
+    }
+}
+
+/// Возвращает терминал в обычный режим. Выполняет все шаги и отдает первую ошибку.
+pub fn suspend() -> io::Result<()> {
+    let raw = disable_raw_mode();
+    let screen = execute!(
+        io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableBracketedPaste
+    );
+    raw.and(screen)
+}
+
+/// Переводит терминал в режим приложения: сырой ввод, альтернативный экран, мышь и вставка.
+pub fn resume() -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(
+        io::stdout(),
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )
+}
+
+/// Показывает `value` в `$PAGER` (или `$EDITOR`) через временный файл.
+/// На время работы программы терминал возвращается в обычный режим,
+/// после нее экран перерисовывается целиком с прежним состоянием.
+/// Терминал восстанавливается и файл удаляется при любой ошибке.
+fn open_external<B: Backend>(terminal: &mut Terminal<B>, value: &str) -> io::Result<()> {
+    let path = std::env::temp_dir().join(format!("journal1c_value_{}.txt", std::process::id()));
+    let command = pager_command(std::env::var("PAGER").ok(), std::env::var("EDITOR").ok());
+
+    let result = std::fs::write(&path, value).and_then(|_| {
+        let status = suspend().and_then(|_| {
+            Command::new(&command[0])
+                .args(&command[1..])
+                .arg(&path)
+                .status()
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", command[0], e)))
+        });
+        let restored = resume().and_then(|_| terminal.clear());
+        status.and(restored)
+    });
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+/// Команда просмотра: `$PAGER`, иначе `$EDITOR`, иначе `more`. Аргументы разделяются пробелами.
+fn pager_command(pager: Option<String>, editor: Option<String>) -> Vec<String> {
+    [pager, editor]
+        .into_iter()
+        .flatten()
+        .map(|command| {
+            command
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        })
+        .find(|command| !command.is_empty())
+        .unwrap_or_else(|| vec!["more".to_string()])
+}
+
+/// Имя поля для запроса. Имена с символами, недопустимыми в идентификаторе, берутся в скобки.
+fn field_identifier(key: &str) -> String {
+    match key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    {
+        true => key.to_string(),
+        false => format!("[{}]", key),
+    }
+}
+
+/// Фильтр `WHERE a = "x" AND b = "y"` по полям `names` записи. Отсутствующие поля
+/// пропускаются, у повторяющегося поля берется первое значение.
+fn row_query(fields: &FieldMap, names: &[String]) -> Option<String> {
+    let conditions = names
+        .iter()
+        .filter_map(|name| {
+            let value = &fields.get(name)?[0];
+            filter_condition(name, value, FilterOp::Equal)
+        })
+        .collect::<Vec<_>>();
+
+    match conditions.is_empty() {
+        true => None,
+        false => Some(format!("WHERE {}", conditions.join(" AND "))),
+    }
+}
+
+/// Условие фильтра для значения из информационной панели.
+fn filter_condition(key: &str, value: &Value, op: FilterOp) -> Option<String> {
+    let key = field_identifier(key);
+
+    let value = match (value, op) {
+        (Value::String(s), FilterOp::Contains) => {
+            let line = s.lines().next().unwrap_or_default();
+            let prefix = line.chars().take(CONTAINS_PREFIX).collect::<String>();
+            format!("/{}/", regex::escape(&prefix).replace('/', "\\/"))
+        }
+        (Value::String(s), _) => format!("\"{}\"", s),
+        (Value::Number(n), _) => n.to_string(),
+        (Value::DateTime(n), _) => format!("'{}'", n.format("%Y-%m-%d %H:%M:%S%.9f")),
+        (Value::MultiValue(_), _) => return None,
+    };
+
+    let op = match op {
+        FilterOp::Equal | FilterOp::Contains => "=",
+        FilterOp::GreaterOrEqual => ">=",
+    };
+    Some(format!("{} {} {}", key, op, value))
+}
+
 fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     let rects = Layout::default()
         .direction(Direction::Vertical)
-        .constraints(vec![Constraint::Min(1), Constraint::Length(1)])
+        .constraints(vec![
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
         .split(f.size());
 
-    let keys_rect = rects[1];
+    let status_rect = rects[1];
+    let keys_rect = rects[2];
+    // Быстрый фильтр показывается и скрывается вместе с основным
+    let search_visible = app.search.borrow().visible();
+    app.quick.borrow_mut().set_visible(search_visible);
+    // Строки `:` и `/` занимают одно место, открыта может быть только одна
+    let prompt = match app.find.borrow().visible() {
+        true => &app.find,
+        false => &app.goto,
+    };
     let rects = Layout::default()
         .direction(Direction::Vertical)
         .constraints(vec![
-            Constraint::Length(if app.search.borrow().visible() { 3 } else { 0 }),
+            Constraint::Length(if search_visible { 3 } else { 0 }),
+            Constraint::Length(if search_visible { 3 } else { 0 }),
+            Constraint::Length(if prompt.borrow().visible() { 3 } else { 0 }),
+            Constraint::Length(1),
             Constraint::Percentage(60),
             Constraint::Percentage(40),
         ])
@@ -236,85 +1061,457 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             .borrow_mut()
             .resize(rects[0].width, rects[0].height);
     }
-    if rects[1].width != app.table.borrow().width()
-        || rects[1].height != app.table.borrow().height()
+    if rects[1].width != app.quick.borrow().width()
+        || rects[1].height != app.quick.borrow().height()
     {
-        app.table
+        app.quick
             .borrow_mut()
             .resize(rects[1].width, rects[1].height);
     }
-    if rects[2].width != app.text.borrow().width() || rects[2].height != app.text.borrow().height()
+    if rects[2].width != prompt.borrow().width() || rects[2].height != prompt.borrow().height() {
+        prompt.borrow_mut().resize(rects[2].width, rects[2].height);
+    }
+    if rects[3].width != app.density.borrow().width() {
+        app.density
+            .borrow_mut()
+            .resize(rects[3].width, rects[3].height);
+    }
+    if rects[4].width != app.table.borrow().width()
+        || rects[4].height != app.table.borrow().height()
+    {
+        app.table
+            .borrow_mut()
+            .resize(rects[4].width, rects[4].height);
+    }
+    if rects[5].width != app.text.borrow().width() || rects[5].height != app.text.borrow().height()
     {
         app.text
             .borrow_mut()
-            .resize(rects[2].width, rects[2].height);
+            .resize(rects[5].width, rects[5].height);
     }
 
     app.prev_size = (f.size().width, f.size().height);
+    app.density_area = rects[3];
+    app.table_area = rects[4];
+    app.text_area = rects[5];
     if app.search.borrow().visible() {
         f.render_widget(app.search.borrow_mut().widget(), rects[0]);
     }
+    if search_visible {
+        f.render_widget(app.quick.borrow_mut().widget(), rects[1]);
+    }
+    if prompt.borrow().visible() {
+        f.render_widget(prompt.borrow_mut().widget(), rects[2]);
+    }
 
-    f.render_widget(app.table.borrow_mut().widget(), rects[1]);
-    f.render_widget(app.text.borrow_mut().widget(), rects[2]);
+    f.render_widget(app.density.borrow().widget(), rects[3]);
+    f.render_widget(app.table.borrow_mut().widget(), rects[4]);
+    f.render_widget(app.text.borrow_mut().widget(), rects[5]);
 
-    let mut common_keys = vec![
-        Span::styled("Ctrl+Q", Style::default().fg(Color::White)),
-        Span::raw(" "),
-        Span::styled("Quit", Style::default().fg(Color::LightCyan)),
-        Span::raw(" | "),
-        Span::styled("Ctrl+F", Style::default().fg(Color::White)),
-        Span::raw(" "),
-        Span::styled("Search", Style::default().fg(Color::LightCyan)),
+    let mut common_keys = vec![];
+    for binding in GLOBAL_KEYS
+        .iter()
+        .chain(app.key_bindings(&app.state))
+        .filter(|binding| binding.hint)
+    {
+        if !common_keys.is_empty() {
+            common_keys.push(Span::raw(" | "));
+        }
+        common_keys.extend_from_slice(&[
+            Span::styled(binding.keys, Style::default().fg(app.theme.label_fg)),
+            Span::raw(" "),
+            Span::styled(binding.action, Style::default().fg(app.theme.value_fg)),
+        ]);
+    }
+
+    f.render_widget(
+        Paragraph::new(Text::from(Spans::from(common_keys))),
+        keys_rect,
+    );
+    f.render_widget(
+        status_line(&app.log_data.borrow().stats(), &app.theme),
+        status_rect,
+    );
+
+    if app.show_info {
+        render_info(f, app);
+    }
+    if app.show_help {
+        render_help(f, app);
+    }
+    if let Some((names, state)) = &mut app.fields {
+        render_fields(f, names, state, &app.theme);
+    }
+}
+
+/// Список полей первых записей с примером значения. `Enter` добавляет поле в фильтр.
+fn render_fields<B: Backend>(
+    f: &mut Frame<B>,
+    names: &[(String, String)],
+    state: &mut ListState,
+    theme: &Theme,
+) {
+    let width = names
+        .iter()
+        .map(|(key, _)| key.chars().count())
+        .max()
+        .unwrap_or_default();
+    let items = names
+        .iter()
+        .map(|(key, value)| {
+            ListItem::new(Spans::from(vec![
+                Span::styled(
+                    format!("{:width$}  ", key, width = width),
+                    Style::default().fg(theme.value_fg),
+                ),
+                Span::raw(value.as_str()),
+            ]))
+        })
+        .collect::<Vec<_>>();
+
+    let size = f.size();
+    let (width, height) = (
+        size.width * 3 / 4,
+        (names.len() as u16 + 2).min(size.height * 3 / 4),
+    );
+    let area = Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + (size.height - height) / 2,
+        width,
+        height,
+    );
+
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(
+        List::new(items)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "Fields of first {} records | Enter Add to filter | Esc Close",
+                FIELDS_SAMPLE
+            ))),
+        area,
+        state,
+    );
+}
+
+/// Строка с примененным фильтром и долей записей, прошедших его.
+fn status_line(stats: &Stats, theme: &Theme) -> Paragraph<'static> {
+    let percent = match stats.records {
+        0 => 0.0,
+        records => stats.matched as f64 * 100.0 / records as f64,
+    };
+    let filter = stats
+        .filter
+        .as_ref()
+        .map_or(String::from("none"), ToString::to_string);
+
+    Paragraph::new(Spans::from(vec![
+        Span::styled("Filter: ", Style::default().fg(theme.label_fg)),
+        Span::styled(filter, Style::default().fg(theme.value_fg)),
         Span::raw(" | "),
-        Span::styled("Tab", Style::default().fg(Color::White)),
-        Span::raw(" "),
-        Span::styled("Next widget", Style::default().fg(Color::LightCyan)),
-    ];
+        Span::styled("matched ", Style::default().fg(theme.label_fg)),
+        Span::styled(
+            format!(
+                "{} / {} ({:.0}%)",
+                format_count(stats.matched),
+                format_count(stats.records),
+                percent
+            ),
+            Style::default().fg(theme.value_fg),
+        ),
+    ]))
+}
 
-    match app.state {
-        ActiveWidget::LogTable => {
-            common_keys.extend_from_slice(&[
-                Span::raw(" | "),
-                Span::styled("PageUp", Style::default().fg(Color::White)),
-                Span::raw(" "),
-                Span::styled("Go to begin", Style::default().fg(Color::LightCyan)),
-                Span::raw(" | "),
-                Span::styled("PageDown", Style::default().fg(Color::White)),
-                Span::raw(" "),
-                Span::styled("Go to end", Style::default().fg(Color::LightCyan)),
-            ]);
+/// Окно справки со всеми клавишами, по разделам для каждого виджета.
+fn render_help<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let sections = app.help_sections();
+    let width = sections
+        .iter()
+        .flat_map(|(_, bindings)| bindings.iter().map(|binding| binding.keys.chars().count()))
+        .max()
+        .unwrap_or_default();
+
+    let mut lines = vec![];
+    for (title, bindings) in sections {
+        if !lines.is_empty() {
+            lines.push(Spans::default());
         }
-        ActiveWidget::SearchBox => common_keys.extend_from_slice(&[
-            Span::raw(" | "),
-            Span::styled("Ctrl-Bckspc", Style::default().fg(Color::White)),
-            Span::raw(" "),
-            Span::styled("Clear", Style::default().fg(Color::LightCyan)),
-        ]),
-        ActiveWidget::InfoView => {
-            common_keys.extend_from_slice(&[
-                Span::raw(" | "),
-                Span::styled("C", Style::default().fg(Color::White)),
-                Span::raw(" "),
-                Span::styled("Copy", Style::default().fg(Color::LightCyan)),
-                Span::raw(" | "),
-                Span::styled("F", Style::default().fg(Color::White)),
-                Span::raw(" "),
-                Span::styled("Add to filter", Style::default().fg(Color::LightCyan)),
-                Span::raw(" | "),
-                Span::styled("PageUp", Style::default().fg(Color::White)),
-                Span::raw(" "),
-                Span::styled("Go to begin", Style::default().fg(Color::LightCyan)),
-                Span::raw(" | "),
-                Span::styled("PageDown", Style::default().fg(Color::White)),
-                Span::raw(" "),
-                Span::styled("Go to end", Style::default().fg(Color::LightCyan)),
-            ]);
+        lines.push(Spans::from(Span::styled(
+            title,
+            Style::default().add_modifier(Modifier::BOLD),
+        )));
+        for binding in bindings {
+            lines.push(Spans::from(vec![
+                Span::styled(
+                    format!("  {:width$}  ", binding.keys, width = width),
+                    Style::default().fg(app.theme.label_fg),
+                ),
+                Span::styled(binding.action, Style::default().fg(app.theme.value_fg)),
+            ]));
         }
+    }
+
+    let size = f.size();
+    let (width, height) = (
+        size.width * 3 / 4,
+        (lines.len() as u16 + 2).min(size.height),
+    );
+    let area = Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + (size.height - height) / 2,
+        width,
+        height,
+    );
+
+    f.render_widget(Clear, area);
+    f.render_widget(
+        Paragraph::new(Text::from(lines)).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Keys | any key Close"),
+        ),
+        area,
+    );
+}
+
+/// Окно со сведениями о загрузке и разобранным фильтром для сообщений об ошибках.
+fn render_info<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let stats = app.log_data.borrow().stats();
+    let field = |name: &'static str, value: String| {
+        Spans::from(vec![
+            Span::styled(name, Style::default().fg(app.theme.value_fg)),
+            Span::raw(value),
+        ])
     };
+    let lines = vec![
+        field("Source:  ", stats.source),
+        field("Files:   ", stats.files.to_string()),
+        field("Zone:    ", app.log_data.borrow().zone().to_string()),
+        field(
+            "Records: ",
+            format!("{} (matched {})", stats.records, stats.matched),
+        ),
+        field(
+            "Memory:  ",
+            format!(
+                "index ~{:.1} MiB, {} parsed rows cached",
+                stats.index_bytes as f64 / (1024.0 * 1024.0),
+                stats.cached
+            ),
+        ),
+        field(
+            "Filter:  ",
+            stats
+                .filter
+                .map_or(String::from("none"), |query| query.to_string()),
+        ),
+    ];
+
+    let size = f.size();
+    let (width, height) = (
+        size.width * 3 / 4,
+        (lines.len() as u16 + 2).min(size.height),
+    );
+    let area = Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + (size.height - height) / 2,
+        width,
+        height,
+    );
 
+    f.render_widget(Clear, area);
     f.render_widget(
-        Paragraph::new(Text::from(Spans::from(common_keys))),
-        keys_rect,
-    )
+        Paragraph::new(Text::from(lines))
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "journal1c {} | Esc Close",
+                env!("CARGO_PKG_VERSION")
+            ))),
+        area,
+    );
+}
+
+#[test]
+fn test_row_query() {
+    let mut fields = FieldMap::new();
+    fields.insert("event", Value::from("EXCP"));
+    fields.insert("process", Value::from("rphost"));
+    fields.insert("Context", Value::from("first"));
+    fields.insert("Context", Value::from("second"));
+
+    let names = ["process", "event", "Usr", "Context"].map(String::from);
+    assert_eq!(
+        row_query(&fields, &names).as_deref(),
+        Some(r#"WHERE process = "rphost" AND event = "EXCP" AND Context = "first""#)
+    );
+    assert_eq!(row_query(&fields, &[String::from("Usr")]), None);
+}
+
+#[test]
+fn test_pager_command() {
+    let command = |pager: Option<&str>, editor: Option<&str>| {
+        pager_command(pager.map(String::from), editor.map(String::from))
+    };
+    assert_eq!(command(Some("less -R"), Some("vim")), ["less", "-R"]);
+    assert_eq!(command(Some("  "), Some("vim")), ["vim"]);
+    assert_eq!(command(None, None), ["more"]);
+}
+
+#[test]
+fn test_key_bindings_unique() {
+    let widgets: [(&[KeyBinding], &[KeyBinding]); 3] = [
+        (TABLE_KEYS, TableView::new(vec![]).key_bindings()),
+        (INFO_KEYS, KeyValueView::new().key_bindings()),
+        (GOTO_KEYS, LineEdit::new(String::new()).key_bindings()),
+    ];
+    for (app, widget) in widgets {
+        let mut keys = GLOBAL_KEYS
+            .iter()
+            .chain(app)
+            .chain(widget)
+            .map(|binding| binding.keys)
+            .collect::<Vec<_>>();
+        let count = keys.len();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), count);
+    }
+}
+
+/// Нажатия для подписи клавиши из `KeyBinding`: `Ctrl+D/Ctrl+U`, `N/Shift+N`, `[/]`, `F1`.
+#[cfg(test)]
+fn binding_events(keys: &str) -> Vec<KeyEvent> {
+    let alternatives = match keys {
+        "/" => vec!["/"],
+        keys => keys.split('/').collect(),
+    };
+    alternatives
+        .into_iter()
+        .map(|key| {
+            let (modifiers, key) = match (key.strip_prefix("Ctrl+"), key.strip_prefix("Shift+")) {
+                (Some(key), _) => (KeyModifiers::CONTROL, key),
+                (_, Some(key)) => (KeyModifiers::SHIFT, key),
+                _ => (KeyModifiers::NONE, key),
+            };
+            let code = match key {
+                "Up" => KeyCode::Up,
+                "Down" => KeyCode::Down,
+                "Left" => KeyCode::Left,
+                "Right" => KeyCode::Right,
+                "PageUp" => KeyCode::PageUp,
+                "PageDown" => KeyCode::PageDown,
+                "Home" => KeyCode::Home,
+                "End" => KeyCode::End,
+                "Enter" => KeyCode::Enter,
+                "Esc" => KeyCode::Esc,
+                "Tab" => KeyCode::Tab,
+                "Backspace" => KeyCode::Backspace,
+                "Delete" => KeyCode::Delete,
+                "Space" => KeyCode::Char(' '),
+                key if key.len() > 1 && key.starts_with('F') => {
+                    KeyCode::F(key[1..].parse().expect(keys))
+                }
+                key => {
+                    let mut chars = key.chars();
+                    let c = chars.next().expect(keys);
+                    assert!(chars.next().is_none(), "unknown key `{}`", keys);
+                    // Буквы в подписях заглавные, без `Shift` нажимается строчная
+                    match modifiers {
+                        KeyModifiers::SHIFT => KeyCode::Char(c),
+                        _ => KeyCode::Char(c.to_ascii_lowercase()),
+                    }
+                }
+            };
+            KeyEvent::new(code, modifiers)
+        })
+        .collect()
+}
+
+#[test]
+fn test_hinted_keys_are_handled() {
+    let dir = std::env::temp_dir().join(format!("journal1c_keys_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let app = || {
+        App::new(
+            Source::Directories(vec![dir.to_string_lossy().into_owned()]),
+            None,
+            None,
+            None,
+            false,
+            HashMap::new(),
+            None,
+            None,
+            false,
+            vec![],
+            Columns::Auto,
+            vec![],
+            TimeStep::minutes(1),
+            LogZone::Local,
+        )
+    };
+
+    let widgets = [
+        ActiveWidget::LogTable,
+        ActiveWidget::InfoView,
+        ActiveWidget::SearchBox,
+        ActiveWidget::QuickFilter,
+        ActiveWidget::GoTo,
+        ActiveWidget::Find,
+    ];
+    for widget in widgets {
+        let bindings = app().key_bindings(&widget);
+        for binding in GLOBAL_KEYS.iter().chain(bindings).filter(|b| b.hint) {
+            for key in binding_events(binding.keys) {
+                // Каждое нажатие проверяется в новом окне: клавиши меняют активный виджет
+                let mut app = app();
+                app.state = widget;
+                assert!(
+                    app.key_press_event(key),
+                    "`{}` ({}) is not handled in {:?}",
+                    binding.keys,
+                    binding.action,
+                    widget
+                );
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_find_pattern() {
+    let pattern = find_pattern("a.b").unwrap().unwrap();
+    assert!(pattern.is_match("xa.by") && !pattern.is_match("axb"));
+    let pattern = find_pattern("/a.b/").unwrap().unwrap();
+    assert!(pattern.is_match("axb"));
+    assert!(find_pattern("").unwrap().is_none());
+    assert!(find_pattern("/").unwrap().is_some());
+    assert!(find_pattern("/(/").is_err());
+}
+
+#[test]
+fn test_add_time_to_filter_from_info() {
+    use chrono::NaiveDate;
+
+    let hour = NaiveDate::from_ymd_opt(2025, 1, 15)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap();
+    let input = std::io::Cursor::new("05:12.345000-1,EXCP,1,process=rphost\r\n");
+    let line = LogParser::parse_reader(input, hour, None).recv().unwrap();
+
+    let condition = Rc::new(RefCell::new(None));
+    let mut view = KeyValueView::new();
+    let emitted = condition.clone();
+    view.on_add_to_filter(move |(key, value, op)| {
+        *emitted.borrow_mut() = filter_condition(&key, value, op)
+    });
+    view.set_data(line.field_map(), Some(line));
+    view.key_press_event(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::NONE));
+
+    let condition = condition.borrow_mut().take().unwrap();
+    assert_eq!(condition, "time >= '2025-01-15 14:05:12.345000000'");
+    assert!(Compiler::new()
+        .compile(&format!("WHERE {}", condition))
+        .is_ok());
 }
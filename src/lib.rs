@@ -0,0 +1,15 @@
+//! Разбор технологического журнала 1С.
+//!
+//! ```no_run
+//! for record in journal1c::parse_file("22080214.log")? {
+//!     let record = record?;
+//!     println!("{} {}", record.time, record.fields.get("event").unwrap());
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+pub mod parser;
+pub mod ui;
+pub mod util;
+
+pub use parser::{parse_file, FieldMap, LogRecords, Record, Value};
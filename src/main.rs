@@ -1,64 +1,366 @@
 mod app;
-mod parser;
-mod ui;
-mod util;
+mod state;
 
 /// TODO:
 /// 1. Добить запрос с разными типами
 /// 2. Индексация по полям
 /// 3. Читать файлы и запоминать только байты конкретных данных
-use crate::parser::LogParser;
 use app::App;
 use clap::Parser;
-use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture},
-    execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-};
-use std::error::Error;
+use state::{load_watermark, save_watermark};
+use std::{collections::HashMap, io};
+use thiserror::Error;
 use tui::{backend::CrosstermBackend, Terminal};
 
-use crate::util::parse_date;
-use parser::logdata::LogCollection;
+use journal1c::{
+    parser::{
+        self,
+        logdata::{Columns, DedupFields, LogCollection},
+        set_syntax,
+        warnings::{take_warnings, warn},
+        Compiler, DerivedField, LogParser, ParseError, Source, Syntax,
+    },
+    ui,
+    util::{
+        self, load_event_labels, parse_date, parse_hour, parse_interval, validate_time_format,
+        DateError, LogZone,
+    },
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None, verbatim_doc_comment)]
 struct Args {
     /// Путь к директории с файлами логов
-    /// (Также ищет файлы в поддиректориях)
-    #[clap(short, long, value_parser, verbatim_doc_comment)]
-    directory: String,
+    /// (Также ищет файлы в поддиректориях).
+    /// Можно указать несколько раз: записи каталогов сливаются по времени
+    #[clap(
+        short,
+        long,
+        value_parser,
+        required_unless_present = "stdin",
+        verbatim_doc_comment
+    )]
+    directory: Vec<String>,
+
+    /// Читать записи со стандартного ввода вместо директории.
+    /// Час записей задается параметром --hour
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    stdin: bool,
 
     /// Временая точка начала чтения логов.
-    /// Формат: now-{digit}{s/m/h/d/w}
-    /// Пример: now-1d или now-30s
+    /// Формат: now[+-]{digit}{s/m/h/d/w}...
+    /// Пример: now-1d, now-1h30m или now+30s
     #[clap(long, value_parser, verbatim_doc_comment)]
     from: Option<String>,
+
+    /// Показать только записи новее последней записи прошлого запуска с --since-last
+    /// для тех же каталогов. Без сохраненной отметки читаются все записи.
+    /// Время последней загруженной записи сохраняется при выходе
+    #[clap(long, value_parser, conflicts_with = "from", verbatim_doc_comment)]
+    since_last: bool,
+
+    /// Формат отображения времени в таблице (strftime).
+    /// Пример: "%H:%M:%S%.6f" или "%Y-%m-%dT%H:%M:%S%.f"
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    time_format: Option<String>,
+
+    /// Показывать в колонке time час, взятый из имени файла, и время из записи
+    /// как есть: 2025-01-15 14h 05:12.345000 (для поиска файлов с неверным часом в имени)
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    raw_time: bool,
+
+    /// Час, к которому относятся записи файлов, имя которых
+    /// не в формате ггммддчч.log (по умолчанию - час изменения файла).
+    /// Пример: "2025-01-15 14" или 2025011514
+    #[clap(long, visible_alias = "hour", value_parser, verbatim_doc_comment)]
+    assume_hour: Option<String>,
+
+    /// Файл с подписями событий для колонки event.
+    /// Формат строк: код=подпись, например EXCP=Exception
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    event_labels: Option<String>,
+
+    /// Схлопывать подряд идущие одинаковые записи в одну строку
+    /// (количество повторов показывается в колонке event).
+    /// По умолчанию сравниваются все поля, кроме time и duration
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    dedup: bool,
+
+    /// Поля, по которым сравниваются записи при схлопывании (включает --dedup).
+    /// Пример: event,Context
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    dedup_fields: Option<String>,
+
+    /// Максимальное количество загружаемых записей.
+    /// После него чтение логов останавливается
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    max_lines: Option<usize>,
+
+    /// Показывать длительность в таблице как 4.5s, 250ms или 900µs
+    /// вместо числа микросекунд (фильтры сравнивают микросекунды)
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    human_durations: bool,
+
+    /// Часовой пояс, в котором записаны логи: +03:00 или Europe/Moscow.
+    /// Относительные даты (now-1h) в --from и запросах отсчитываются в нем.
+    /// Время в таблице и абсолютные даты - время логов как есть, без пересчета
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    tz: Option<String>,
+
+    /// Колонки таблицы вместо time,event,duration,process,OSThread.
+    /// С + в начале - дополнительно к ним: +Usr,__file (__file - путь файла записи).
+    /// auto - time, event и самые частые поля первых записей
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    columns: Option<String>,
+
+    /// Поля, по которым `Y` в таблице копирует фильтр выделенной записи.
+    /// По умолчанию: process,event
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    copy_fields: Option<String>,
+
+    /// Виртуальное поле: первая группа регулярного выражения по значению поля.
+    /// Показывается колонкой и доступно в запросах. Можно указать несколько раз.
+    /// Пример: rows=Sql:/rows=(\d+)/
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    derive: Vec<String>,
+
+    /// Шаг перехода по времени клавишами [ и ] в таблице.
+    /// Формат: {digit}{s/m/h/d/w}..., например 30s или 5m
+    #[clap(long, value_parser, default_value = "1m", verbatim_doc_comment)]
+    time_step: String,
+
+    /// Разделитель полей записи, для логов, выгруженных с другим разделителем
+    #[clap(long, value_parser, default_value = ",", verbatim_doc_comment)]
+    delimiter: String,
+
+    /// Одна или две кавычки, в которые берутся значения с разделителями и переводами строк
+    #[clap(long, value_parser, default_value = "'\"", verbatim_doc_comment)]
+    quote: String,
+
+    /// Максимальное количество перерисовок экрана в секунду.
+    /// Без событий и новых записей экран не перерисовывается
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..=60),
+        default_value_t = 10,
+        verbatim_doc_comment
+    )]
+    fps: u32,
+
+    /// Проверить запрос на первых записях (1000 или --max-lines) и вывести по каждому условию,
+    /// сколько раз оно проверялось и совпало, сколько прочитано полей и вычислено
+    /// регулярных выражений. Интерфейс не запускается.
+    /// Пример: --explain 'WHERE Descr = /timeout/ AND event = "EXCP"'
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    explain: Option<String>,
+
+    /// Не выводить предупреждения при выходе (ошибки выводятся всегда)
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    quiet: bool,
+}
+
+/// Ошибка запуска. Класс ошибки определяет код выхода, чтобы его можно было
+/// проверить в скриптах.
+#[derive(Error, Debug)]
+enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0}: {1}")]
+    Time(String, DateError),
+    /// Запрос `--explain` не компилируется.
+    #[error("{0}")]
+    Filter(ParseError),
+    #[error("{0}: {1}")]
+    Path(String, io::Error),
+    #[error("{0}")]
+    Terminal(String),
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+impl CliError {
+    fn class(&self) -> &'static str {
+        match self {
+            CliError::Usage(_) => "usage",
+            CliError::Time(..) => "time",
+            CliError::Filter(_) => "filter",
+            CliError::Path(..) => "path",
+            CliError::Terminal(_) => "terminal",
+        }
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => 2,
+            CliError::Time(..) => 3,
+            CliError::Filter(_) => 4,
+            CliError::Path(..) => 5,
+            CliError::Terminal(_) => 1,
+        }
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(e: io::Error) -> Self {
+        CliError::Terminal(e.to_string())
+    }
+}
+
+/// Сколько первых записей проверяет `--explain` без `--max-lines`.
+const EXPLAIN_RECORDS: usize = 1000;
+
+/// Список полей через запятую: `event, Context`.
+fn split_fields(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn main() {
     let args = Args::parse();
-    let date = match &args.from {
-        Some(value) => Some(parse_date(value.as_str())?),
+    let quiet = args.quiet;
+    let result = run(args);
+
+    if !quiet {
+        for warning in take_warnings() {
+            eprintln!("warning: {}", warning);
+        }
+    }
+    if let Err(e) = result {
+        eprintln!("error[{}]: {}", e.class(), e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn run(args: Args) -> Result<(), CliError> {
+    let time = |value: &String, e| CliError::Time(value.clone(), e);
+    let syntax = Syntax::parse(&args.delimiter, &args.quote)
+        .map_err(|e| CliError::Usage(format!("--delimiter/--quote: {}", e)))?;
+    set_syntax(syntax);
+    let zone = match &args.tz {
+        Some(tz) => tz.parse::<LogZone>().map_err(|e| time(tz, e))?,
+        None => LogZone::Local,
+    };
+    let assume_hour = match &args.assume_hour {
+        Some(value) => Some(parse_hour(value.as_str()).map_err(|e| time(value, e))?),
         None => None,
     };
+    let source = match args.stdin {
+        true => Source::Stdin(
+            assume_hour.ok_or_else(|| CliError::Usage("--stdin requires --hour".into()))?,
+        ),
+        false if args.directory.is_empty() => {
+            return Err(CliError::Usage("--directory is required".into()))
+        }
+        false => {
+            for dir in &args.directory {
+                match std::fs::metadata(dir) {
+                    Ok(meta) if meta.is_dir() => {}
+                    Ok(_) => {
+                        let e = io::Error::new(io::ErrorKind::InvalidInput, "not a directory");
+                        return Err(CliError::Path(dir.clone(), e));
+                    }
+                    Err(e) => return Err(CliError::Path(dir.clone(), e)),
+                }
+            }
+            Source::Directories(args.directory)
+        }
+    };
+    let watermark = args.since_last.then(|| source.to_string());
+    let date = match (&args.from, &watermark) {
+        (Some(value), _) => Some(parse_date(value.as_str(), zone).map_err(|e| time(value, e))?),
+        // Запись с временем отметки уже была показана в прошлый раз
+        (None, Some(source)) => {
+            load_watermark(source).map(|time| time + chrono::Duration::microseconds(1))
+        }
+        (None, None) => None,
+    };
+    let event_labels = match &args.event_labels {
+        Some(path) => load_event_labels(path).map_err(|e| CliError::Path(path.clone(), e))?,
+        None => HashMap::new(),
+    };
+    let dedup = match (&args.dedup_fields, args.dedup) {
+        (Some(fields), _) => Some(DedupFields::Only(split_fields(fields))),
+        (None, true) => Some(DedupFields::AllExceptTiming),
+        (None, false) => None,
+    };
+    let copy_fields = split_fields(args.copy_fields.as_deref().unwrap_or("process,event"));
+    let columns = args
+        .columns
+        .as_deref()
+        .map(Columns::parse)
+        .unwrap_or_default();
+    let derived = args
+        .derive
+        .iter()
+        .map(|value| {
+            value
+                .parse::<DerivedField>()
+                .map_err(|e| CliError::Usage(format!("--derive {}: {}", value, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let time_step = parse_interval(&args.time_step).map_err(|e| time(&args.time_step, e))?;
+    if time_step.is_zero() {
+        return Err(CliError::Usage("--time-step must be positive".into()));
+    }
+    if let Some(format) = &args.time_format {
+        validate_time_format(format).map_err(|e| time(format, e))?;
+    }
+    if let Some(program) = &args.explain {
+        let query = Compiler::with_zone(zone)
+            .compile(program)
+            .map_err(CliError::Filter)?;
+        let roots = match &source {
+            Source::Directories(dirs) => dirs.clone(),
+            Source::Stdin(_) => vec![],
+        };
+        let collection = LogCollection::with_max_lines(
+            LogParser::parse_source(source, date, assume_hour),
+            Some(args.max_lines.unwrap_or(EXPLAIN_RECORDS)),
+        );
+        collection.set_derived(derived);
+        collection.set_roots(roots);
+        print!("{}", collection.profile(&query));
+        return Ok(());
+    }
 
-    enable_raw_mode()?;
-    let mut stdout = std::io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    // Если терминал не удалось настроить, он возвращается в обычный режим
+    let terminal = app::resume().and_then(|_| Terminal::new(CrosstermBackend::new(io::stdout())));
+    let mut terminal = match terminal {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            let _ = app::suspend();
+            return Err(e.into());
+        }
+    };
 
-    App::new(args.directory.as_str(), date).run(&mut terminal)?;
+    let mut app = App::new(
+        source,
+        date,
+        assume_hour,
+        args.time_format,
+        args.raw_time,
+        event_labels,
+        dedup,
+        args.max_lines,
+        args.human_durations,
+        derived,
+        columns,
+        copy_fields,
+        time_step,
+        zone,
+    );
+    let result = app.run(&mut terminal, args.fps);
+    if let (Some(source), Some(time)) = (&watermark, app.last_time()) {
+        if let Err(e) = save_watermark(source, time) {
+            warn(format!("can't save the --since-last watermark: {}", e));
+        }
+    }
 
     // restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
+    app::suspend()?;
     terminal.show_cursor()?;
 
-    Ok(())
+    result.map_err(|e| CliError::Terminal(e.to_string()))
 }
@@ -1,22 +1,107 @@
 use std::{
-    fs::File,
-    io::BufReader,
-    sync::{Arc, Mutex, RwLock},
+    collections::HashSet,
+    io,
+    io::{Read, Seek},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
 };
 
+/// Источник записей для чтения по смещению: файл или данные stdin в памяти.
+pub(super) trait Reader: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> Reader for T {}
+
+type Buffer = Arc<Mutex<Box<dyn Reader>>>;
+
+/// Открытый файл лога.
+struct Entry {
+    /// `None`, когда файл закрыт вместе с группой (`release_group`).
+    buffer: Option<Buffer>,
+    /// Группа файлов одного разбора: файлы закрываются вместе с коллекцией, которой он принадлежит.
+    group: usize,
+    /// Номер каталога (`-d`), в котором найден файл.
+    root: usize,
+    path: Arc<str>,
+}
+
 lazy_static::lazy_static! {
-    static ref BUFFERS: RwLock<Vec<Arc<Mutex<BufReader<File>>>>> = RwLock::new(Vec::new());
+    static ref BUFFERS: RwLock<Vec<Entry>> = RwLock::new(Vec::new());
+    /// Группы, файлы которых уже закрыты.
+    static ref RELEASED: RwLock<HashSet<usize>> = RwLock::new(HashSet::new());
 }
 
+/// Новая группа файлов для очередного разбора логов.
+pub(super) fn new_group() -> usize {
+    static GROUPS: AtomicUsize = AtomicUsize::new(0);
+    GROUPS.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Добавляет файл `path` из каталога с номером `root` в группу `group`.
+/// Файл закрытой группы сразу закрывается, номер остается действительным.
 #[inline]
-pub(super) fn add_buffer(buffer: BufReader<File>) -> usize {
+pub(super) fn add_buffer(
+    group: usize,
+    buffer: impl Reader + 'static,
+    root: usize,
+    path: &Path,
+) -> usize {
+    let released = RELEASED.read().unwrap().contains(&group);
     let mut lock = BUFFERS.write().unwrap();
-    lock.push(Arc::new(Mutex::new(buffer)));
+    lock.push(Entry {
+        buffer: (!released).then(|| Arc::new(Mutex::new(Box::new(buffer) as Box<dyn Reader>))),
+        group,
+        root,
+        path: path.to_string_lossy().into(),
+    });
     lock.len() - 1
 }
 
+/// Закрывает файлы группы. Файлы, добавленные в нее позже, тоже закрываются.
+pub(super) fn release_group(group: usize) {
+    RELEASED.write().unwrap().insert(group);
+    for entry in BUFFERS.write().unwrap().iter_mut() {
+        if entry.group == group {
+            entry.buffer = None;
+        }
+    }
+}
+
+/// Группа, в которую добавлен файл `index`.
+pub(super) fn buffer_group(index: usize) -> Option<usize> {
+    BUFFERS.read().unwrap().get(index).map(|entry| entry.group)
+}
+
+/// Номер каталога, из которого открыт файл `index`.
+pub(super) fn buffer_root(index: usize) -> usize {
+    BUFFERS
+        .read()
+        .unwrap()
+        .get(index)
+        .map_or(0, |entry| entry.root)
+}
+
+/// Путь файла `index`.
+pub(super) fn buffer_path(index: usize) -> Arc<str> {
+    BUFFERS.read().unwrap()[index].path.clone()
+}
+
+/// Количество открытых файлов логов в группах `groups`.
+pub(super) fn buffers_count(groups: &[usize]) -> usize {
+    BUFFERS
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry.buffer.is_some() && groups.contains(&entry.group))
+        .count()
+}
+
 #[inline]
-pub(super) fn get_buffer(index: usize) -> Arc<Mutex<BufReader<File>>> {
+pub(super) fn get_buffer(index: usize) -> io::Result<Buffer> {
     let lock = BUFFERS.read().unwrap();
-    lock.get(index).cloned().unwrap()
+    lock.get(index)
+        .and_then(|entry| entry.buffer.clone())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "log file is closed"))
 }
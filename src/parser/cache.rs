@@ -0,0 +1,95 @@
+use crate::parser::FieldMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+/// Ограниченный кэш разобранных строк лога. При переполнении
+/// вытесняются записи, к которым дольше всего не обращались.
+pub(super) struct FieldsCache {
+    capacity: usize,
+    /// Строка, ее поля и номер последнего обращения.
+    values: HashMap<usize, (u64, Arc<FieldMap<'static>>)>,
+    /// Строки по номеру последнего обращения, от давних к недавним.
+    order: BTreeMap<u64, usize>,
+    tick: u64,
+}
+
+impl FieldsCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            values: HashMap::with_capacity(capacity),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn get(&mut self, row: usize) -> Option<Arc<FieldMap<'static>>> {
+        let tick = self.next_tick();
+        let (used, fields) = self.values.get_mut(&row)?;
+        self.order.remove(used);
+        self.order.insert(tick, row);
+        *used = tick;
+        Some(fields.clone())
+    }
+
+    pub fn insert(&mut self, row: usize, fields: Arc<FieldMap<'static>>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let tick = self.next_tick();
+        if let Some((used, _)) = self.values.insert(row, (tick, fields)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(tick, row);
+
+        while self.order.len() > self.capacity {
+            if let Some((_, row)) = self.order.pop_first() {
+                self.values.remove(&row);
+            }
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.order.clear();
+    }
+}
+
+#[test]
+fn test_cache_capacity() {
+    let mut cache = FieldsCache::new(2);
+    cache.insert(0, Arc::new(FieldMap::new()));
+    cache.insert(1, Arc::new(FieldMap::new()));
+    cache.insert(2, Arc::new(FieldMap::new()));
+
+    assert!(cache.get(0).is_none());
+    assert!(cache.get(1).is_some());
+    assert!(cache.get(2).is_some());
+}
+
+#[test]
+fn test_cache_keeps_recently_read() {
+    let mut cache = FieldsCache::new(2);
+    cache.insert(0, Arc::new(FieldMap::new()));
+    cache.insert(1, Arc::new(FieldMap::new()));
+    // Обращение к строке 0 делает вытесняемой строку 1
+    assert!(cache.get(0).is_some());
+    cache.insert(2, Arc::new(FieldMap::new()));
+
+    assert!(cache.get(0).is_some());
+    assert!(cache.get(1).is_none());
+    assert!(cache.get(2).is_some());
+    assert_eq!(cache.len(), 2);
+}
@@ -1,12 +1,17 @@
-use crate::parser::{FieldMap, Value};
-use chrono::{Duration, NaiveDateTime};
-use regex::Regex;
+use crate::{
+    parser::{FieldMap, Value},
+    util::{self, parse_relative_date, DateError, LogZone},
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use regex::{Regex, RegexBuilder};
 use std::{
+    collections::HashMap,
     fmt::{Display, Formatter},
     iter::Peekable,
     ops::Deref,
     slice::Iter,
     str::Chars,
+    sync::Arc,
 };
 use thiserror::Error;
 
@@ -14,17 +19,72 @@ use thiserror::Error;
 pub struct RegexCmp {
     inner: Regex,
     value: String,
+    all_values: bool,
+    case_insensitive: bool,
+    dot_matches_new_line: bool,
 }
 
 impl RegexCmp {
-    pub fn new<T: Into<String>>(value: T) -> Result<Self, regex::Error> {
+    /// Компилирует регулярное выражение с флагами, указанными после него (`/regex/ia`).
+    /// `i` - без учета регистра, `s` - `.` совпадает с переводом строки,
+    /// `a` - сопоставлять также нестроковые значения (время, числа).
+    pub fn new<T: Into<String>>(value: T, flags: &str) -> Result<Self, ParseError> {
         let value = value.into();
+        let (mut all_values, mut case_insensitive, mut dot_matches_new_line) =
+            (false, false, false);
+        for flag in flags.chars() {
+            match flag {
+                'a' => all_values = true,
+                'i' => case_insensitive = true,
+                's' => dot_matches_new_line = true,
+                c => return Err(ParseError::UnexpectedChar(c)),
+            }
+        }
 
         Ok(RegexCmp {
-            inner: regex::Regex::new(value.as_str())?,
+            inner: RegexBuilder::new(value.as_str())
+                .case_insensitive(case_insensitive)
+                .dot_matches_new_line(dot_matches_new_line)
+                .build()?,
             value,
+            all_values,
+            case_insensitive,
+            dot_matches_new_line,
         })
     }
+
+    fn is_match_value(&self, value: &Value) -> bool {
+        match value {
+            Value::String(s) => self.is_match(s.as_ref()),
+            value if self.all_values => self.is_match(value.to_string().as_str()),
+            _ => false,
+        }
+    }
+
+    /// Значение проверяется выражением (как в `is_match_value`), но совпадения нет.
+    fn is_mismatch_value(&self, value: &Value) -> bool {
+        match value {
+            Value::String(s) => !self.is_match(s.as_ref()),
+            value if self.all_values => !self.is_match(value.to_string().as_str()),
+            _ => false,
+        }
+    }
+}
+
+impl Display for RegexCmp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "/{}/", self.value.replace('/', "\\/"))?;
+        for (flag, set) in [
+            ('a', self.all_values),
+            ('i', self.case_insensitive),
+            ('s', self.dot_matches_new_line),
+        ] {
+            if set {
+                write!(f, "{}", flag)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Deref for RegexCmp {
@@ -38,17 +98,27 @@ impl Deref for RegexCmp {
 impl PartialEq for RegexCmp {
     fn eq(&self, other: &Self) -> bool {
         self.value == other.value
+            && self.all_values == other.all_values
+            && self.case_insensitive == other.case_insensitive
+            && self.dot_matches_new_line == other.dot_matches_new_line
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum Token {
     WHERE,
+    SEARCH,
+    RAW,
     AND,
     OR,
+    HAS,
+    MISSING,
+    LIKE,
     OpenBrace,
     CloseBrace,
     Identifier(String),
+    /// Одно из значений повторяющегося поля: `Context[0]`.
+    IndexedIdentifier(String, usize),
     String(String),
     Number(f64),
     Regex(RegexCmp),
@@ -68,11 +138,17 @@ impl Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Token::WHERE => write!(f, "WHERE"),
+            Token::SEARCH => write!(f, "SEARCH"),
+            Token::RAW => write!(f, "RAW"),
             Token::AND => write!(f, "AND"),
             Token::OR => write!(f, "OR"),
+            Token::HAS => write!(f, "HAS"),
+            Token::MISSING => write!(f, "MISSING"),
+            Token::LIKE => write!(f, "LIKE"),
             Token::OpenBrace => write!(f, "{{"),
             Token::CloseBrace => write!(f, "}}"),
             Token::Identifier(s) => write!(f, "{}", s),
+            Token::IndexedIdentifier(s, index) => write!(f, "{}[{}]", s, index),
             Token::String(s) => write!(f, "{}", s),
             Token::Number(s) => write!(f, "{}", s),
             Token::Regex(s) => write!(f, "{}", s.value),
@@ -93,11 +169,19 @@ impl PartialEq for Token {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Token::WHERE, Token::WHERE) => true,
+            (Token::SEARCH, Token::SEARCH) => true,
+            (Token::RAW, Token::RAW) => true,
             (Token::AND, Token::AND) => true,
             (Token::OR, Token::OR) => true,
+            (Token::HAS, Token::HAS) => true,
+            (Token::MISSING, Token::MISSING) => true,
+            (Token::LIKE, Token::LIKE) => true,
             (Token::OpenBrace, Token::OpenBrace) => true,
             (Token::CloseBrace, Token::CloseBrace) => true,
             (Token::Identifier(s1), Token::Identifier(s2)) => s1 == s2,
+            (Token::IndexedIdentifier(s1, i1), Token::IndexedIdentifier(s2, i2)) => {
+                s1 == s2 && i1 == i2
+            }
             (Token::String(s1), Token::String(s2)) => s1 == s2,
             (Token::Number(s1), Token::Number(s2)) => s1 == s2,
             //(Token::Regex(s1), Token::Regex(s2)) => s1 == s2,
@@ -124,6 +208,19 @@ pub enum ParseError {
     FloatParseError(#[from] std::num::ParseFloatError),
     InvalidDate,
     UnexpectedEndOfInput,
+    UnterminatedRegex,
+}
+
+impl From<DateError> for ParseError {
+    fn from(e: DateError) -> Self {
+        match e {
+            DateError::UnexpectedChar(c) => ParseError::UnexpectedChar(c),
+            DateError::UnexpectedEndOfInput => ParseError::UnexpectedEndOfInput,
+            DateError::InvalidDate | DateError::InvalidFormat(_) | DateError::InvalidZone => {
+                ParseError::InvalidDate
+            }
+        }
+    }
 }
 
 impl Display for ParseError {
@@ -136,6 +233,7 @@ impl Display for ParseError {
             ParseError::FloatParseError(e) => write!(f, "float parse error: {}", e),
             ParseError::InvalidDate => write!(f, "Invalid date"),
             ParseError::UnexpectedEndOfInput => write!(f, "Unexpected end of input"),
+            ParseError::UnterminatedRegex => write!(f, "Unterminated regex: missing closing /"),
         }
     }
 }
@@ -144,8 +242,19 @@ impl Display for ParseError {
 pub enum Query {
     Expr(Option<Box<Query>>, Option<Box<Query>>),
     Regex(RegexCmp),
+    /// `SEARCH "a" "b"` - любая из подстрок (без учета регистра) в любом строковом поле.
+    AnySubstring(Vec<String>),
+    /// `RAW "a"` - подстрока (с учетом регистра) в исходном тексте записи, без разбора полей.
+    Raw(String),
     And(Box<Query>, Box<Query>),
     Or(Box<Query>, Box<Query>),
+    /// `HAS field` - в записи есть поле (возможно, с пустым значением).
+    Has(String),
+    /// `MISSING field` - в записи нет поля.
+    Missing(String),
+    /// `field LIKE "1cv8%"` - шаблон SQL и регулярное выражение на все значение,
+    /// в которое он скомпилирован. Сравнивается текстовое представление значения.
+    Like(Token, String, RegexCmp),
 
     Equal(Token, Token),
     GE(Token, Token),
@@ -153,164 +262,710 @@ pub enum Query {
     Greater(Token, Token),
     Less(Token, Token),
     NE(Token, Token),
+    /// `field >= low AND field <= high` после упрощения запроса: значения поля ищутся один раз.
+    Between(Token, Token, Token),
+}
+
+/// Операнд условия в синтаксисе запроса: строки в кавычках, даты в апострофах.
+struct Operand<'a>(&'a Token);
+
+impl<'a> Display for Operand<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Token::Identifier(name) => write!(f, "{}", Identifier(name)),
+            Token::IndexedIdentifier(name, index) => {
+                write!(f, "{}[{}]", Identifier(name), index)
+            }
+            Token::String(s) => write!(f, "\"{}\"", s),
+            Token::Regex(regex) => write!(f, "{}", regex),
+            Token::Date(date) => write!(f, "'{}'", date.format("%Y-%m-%d %H:%M:%S%.f")),
+            token => write!(f, "{}", token),
+        }
+    }
+}
+
+/// Имя поля; имена с символами вне идентификатора берутся в квадратные скобки.
+struct Identifier<'a>(&'a str);
+
+impl<'a> Display for Identifier<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let plain = self.0.chars().enumerate().all(|(index, c)| {
+            c.is_ascii_alphabetic() || c == '_' || (index > 0 && (c.is_ascii_digit() || c == ':'))
+        });
+        match plain && !self.0.is_empty() {
+            true => write!(f, "{}", self.0),
+            false => write!(f, "[{}]", self.0),
+        }
+    }
+}
+
+/// Запрос в синтаксисе фильтра. Каждое `AND`/`OR` берется в скобки,
+/// чтобы была видна группировка условий.
+impl Display for Query {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let condition = |f: &mut Formatter<'_>, left: &Token, op: &str, right: &Token| {
+            write!(f, "{} {} {}", Operand(left), op, Operand(right))
+        };
+
+        match self {
+            Query::Expr(Some(where_expr), _) => write!(f, "WHERE {}", where_expr),
+            Query::Expr(None, _) => Ok(()),
+            Query::Regex(regex) => write!(f, "{}", regex),
+            Query::AnySubstring(needles) => {
+                write!(f, "SEARCH")?;
+                for needle in needles {
+                    write!(f, " \"{}\"", needle)?;
+                }
+                Ok(())
+            }
+            Query::Raw(needle) => write!(f, "RAW \"{}\"", needle),
+            Query::And(left, right) => write!(f, "({} AND {})", left, right),
+            Query::Or(left, right) => write!(f, "({} OR {})", left, right),
+            Query::Has(name) => write!(f, "HAS {}", Identifier(name)),
+            Query::Missing(name) => write!(f, "MISSING {}", Identifier(name)),
+            Query::Equal(left, right) => condition(f, left, "=", right),
+            Query::GE(left, right) => condition(f, left, ">=", right),
+            Query::LE(left, right) => condition(f, left, "<=", right),
+            Query::Greater(left, right) => condition(f, left, ">", right),
+            Query::Less(left, right) => condition(f, left, "<", right),
+            Query::Between(field, low, high) => {
+                write!(f, "(")?;
+                condition(f, field, ">=", low)?;
+                write!(f, " AND ")?;
+                condition(f, field, "<=", high)?;
+                write!(f, ")")
+            }
+            Query::NE(left, right) => condition(f, left, "!=", right),
+            Query::Like(left, pattern, _) => {
+                condition(f, left, "LIKE", &Token::String(pattern.clone()))
+            }
+        }
+    }
 }
 
 impl Query {
+    /// Проверяет запись по исходному тексту `raw` и полям `fields`. Текст нужен только
+    /// для `RAW`, поля - только для остальных условий, поэтому оба получаются по требованию.
+    pub fn accept_line<'a>(
+        &self,
+        raw: &dyn Fn() -> String,
+        fields: &dyn Fn() -> Arc<FieldMap<'a>>,
+    ) -> bool {
+        self.accept_line_with(raw, fields, &mut ())
+    }
+
+    /// `accept_line` с наблюдателем, как в `accept_with`.
+    pub fn accept_line_with<'a>(
+        &self,
+        raw: &dyn Fn() -> String,
+        fields: &dyn Fn() -> Arc<FieldMap<'a>>,
+        probe: &mut impl Probe,
+    ) -> bool {
+        let accepted = match self {
+            Query::Raw(needle) => {
+                probe.enter(self);
+                raw().contains(needle.as_str())
+            }
+            Query::Expr(Some(where_expr), _) => {
+                return where_expr.accept_line_with(raw, fields, probe)
+            }
+            Query::And(left, right) => {
+                probe.enter(self);
+                left.accept_line_with(raw, fields, probe)
+                    && right.accept_line_with(raw, fields, probe)
+            }
+            Query::Or(left, right) => {
+                probe.enter(self);
+                left.accept_line_with(raw, fields, probe)
+                    || right.accept_line_with(raw, fields, probe)
+            }
+            query => return query.accept_with(&fields(), probe),
+        };
+        probe.leave(accepted);
+        accepted
+    }
+
+    /// Проверяет запись, поля которой уже разобраны в `log_data`.
+    /// Условия только читают карту и не разбирают строку повторно.
+    /// `RAW` по одним полям не проверить: такие записи не принимаются, см. `accept_line`.
     pub fn accept<'a>(&self, log_data: &FieldMap<'a>) -> bool {
-        match self {
+        self.accept_with(log_data, &mut ())
+    }
+
+    /// `accept`, сообщающий `probe` о проверке каждого условия, чтении полей
+    /// и вычислении регулярных выражений.
+    pub fn accept_with<'a>(&self, log_data: &FieldMap<'a>, probe: &mut impl Probe) -> bool {
+        probe.enter(self);
+        let accepted = match self {
             Query::Expr(where_expr, _) => {
                 if let Some(where_expr) = where_expr {
-                    if !where_expr.accept(log_data) {
+                    if !where_expr.accept_with(log_data, probe) {
                         return false;
                     }
                 }
                 true
             }
             Query::Regex(regex) => {
-                // if let Value::String(s) = fields.get("event").unwrap() {
-                //     if regex.is_match(&s) {
-                //         return true
-                //     }
-                // }
-                //
-                // if let Value::String(s) = fields.get("process").unwrap() {
-                //     if regex.is_match(&s) {
-                //         return true
-                //     }
-                // }
-
-                for (_, field) in log_data.iter() {
-                    if let Value::String(s) = field {
-                        if regex.is_match(s.as_ref()) {
-                            return true;
-                        }
+                // `event`, `process` и остальные поля лежат в одной FieldMap,
+                // поэтому достаточно перебрать все ее значения.
+                log_data.iter().any(|(_, field)| {
+                    probe.regex();
+                    regex.is_match_value(field)
+                })
+            }
+            Query::AnySubstring(needles) => log_data.iter().any(|(_, field)| {
+                field.iter().any(|value| match value {
+                    Value::String(s) => {
+                        let s = s.to_lowercase();
+                        needles.iter().any(|needle| s.contains(needle.as_str()))
                     }
-                }
-
-                false
+                    _ => false,
+                })
+            }),
+            Query::Raw(_) => false,
+            Query::And(left, right) => {
+                left.accept_with(log_data, probe) && right.accept_with(log_data, probe)
+            }
+            Query::Or(left, right) => {
+                left.accept_with(log_data, probe) || right.accept_with(log_data, probe)
+            }
+            Query::Has(name) => {
+                probe.lookup();
+                log_data.get(name).is_some()
+            }
+            Query::Missing(name) => {
+                probe.lookup();
+                log_data.get(name).is_none()
             }
-            Query::And(left, right) => left.accept(log_data) && right.accept(log_data),
-            Query::Or(left, right) => left.accept(log_data) || right.accept(log_data),
+            Query::Like(left, _, right) => probed_values(left, log_data, probe).iter().any(|x| {
+                probe.regex();
+                right.is_match_value(x)
+            }),
             Query::Equal(left, right) => match (left, right) {
-                (Token::Identifier(left), Token::String(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x == right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Number(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x == right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Regex(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| right.is_match(x.to_string().as_str())))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Date(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x == right))
-                    .unwrap_or(false),
-                _ => false,
-            },
-            Query::GE(left, right) => match (left, right) {
-                (Token::Identifier(left), Token::String(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x >= right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Number(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x >= right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Date(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x >= right))
-                    .unwrap_or(false),
-                _ => false,
-            },
-            Query::LE(left, right) => match (left, right) {
-                (Token::Identifier(left), Token::String(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x <= right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Number(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x <= right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Date(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x <= right))
-                    .unwrap_or(false),
+                (left, Token::String(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x == right),
+                (left, Token::Number(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x == right),
+                // Как и `/regex/`, проверяет только строковые значения (время и числа - с флагом `a`)
+                (left, Token::Regex(right)) => {
+                    probed_values(left, log_data, probe).iter().any(|x| {
+                        probe.regex();
+                        right.is_match_value(x)
+                    })
+                }
+                (left, Token::Date(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x == right),
                 _ => false,
             },
+            Query::GE(left, right) => any_ge(probed_values(left, log_data, probe), right),
+            Query::LE(left, right) => any_le(probed_values(left, log_data, probe), right),
+            Query::Between(field, low, high) => {
+                let values = probed_values(field, log_data, probe);
+                any_ge(values, low) && any_le(values, high)
+            }
             Query::Greater(left, right) => match (left, right) {
-                (Token::Identifier(left), Token::String(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x > right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Number(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x > right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Date(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x > right))
-                    .unwrap_or(false),
+                (left, Token::String(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x > right),
+                (left, Token::Number(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x > right),
+                (left, Token::Date(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x > right),
                 _ => false,
             },
             Query::Less(left, right) => match (left, right) {
-                (Token::Identifier(left), Token::String(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x < right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Number(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x < right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Date(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x < right))
-                    .unwrap_or(false),
+                (left, Token::String(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x < right),
+                (left, Token::Number(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x < right),
+                (left, Token::Date(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x < right),
                 _ => false,
             },
             Query::NE(left, right) => match (left, right) {
-                (Token::Identifier(left), Token::String(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x != right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Number(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x != right))
-                    .unwrap_or(false),
-                (Token::Identifier(left), Token::Date(right)) => log_data
-                    .get(left)
-                    .map(|x| x.iter().any(|x| x != right))
-                    .unwrap_or(false),
+                (left, Token::String(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x != right),
+                // Как и для `>` и `<`, значение, которое не приводится к числу, не подходит
+                (left, Token::Number(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x.as_number().is_some_and(|x| x != *right)),
+                (left, Token::Date(right)) => probed_values(left, log_data, probe)
+                    .iter()
+                    .any(|x| x != right),
+                // Строковое значение, в котором нет совпадения (время и числа - с флагом `a`)
+                (left, Token::Regex(right)) => {
+                    probed_values(left, log_data, probe).iter().any(|x| {
+                        probe.regex();
+                        right.is_mismatch_value(x)
+                    })
+                }
                 _ => false,
             },
+        };
+        probe.leave(accepted);
+        accepted
+    }
+
+    /// Упрощает запрос: в цепочке `AND` условия `field >= a` и `field <= b`
+    /// одного поля сворачиваются в `Between`.
+    fn simplify(self) -> Query {
+        match self {
+            Query::Expr(left, right) => Query::Expr(
+                left.map(|query| Box::new(query.simplify())),
+                right.map(|query| Box::new(query.simplify())),
+            ),
+            Query::Or(left, right) => {
+                Query::Or(Box::new(left.simplify()), Box::new(right.simplify()))
+            }
+            Query::And(..) => {
+                let mut terms = vec![];
+                self.flatten_and(&mut terms);
+
+                let mut folded: Vec<Query> = Vec::with_capacity(terms.len());
+                for term in terms {
+                    if !folded.iter_mut().any(|prev| prev.merge_range(&term)) {
+                        folded.push(term);
+                    }
+                }
+                folded
+                    .into_iter()
+                    .reduce(|left, right| Query::And(Box::new(left), Box::new(right)))
+                    .unwrap()
+            }
+            query => query,
+        }
+    }
+
+    /// Упрощенные условия цепочки `a AND b AND ...` по порядку.
+    fn flatten_and(self, terms: &mut Vec<Query>) {
+        match self {
+            Query::And(left, right) => {
+                left.flatten_and(terms);
+                right.flatten_and(terms);
+            }
+            query => terms.push(query.simplify()),
+        }
+    }
+
+    /// Заменяет `self` на `Between`, если `self` и `other` - нижняя и верхняя граница одного поля.
+    fn merge_range(&mut self, other: &Query) -> bool {
+        let between = match (&*self, other) {
+            (Query::GE(field, low), Query::LE(other_field, high))
+            | (Query::LE(other_field, high), Query::GE(field, low))
+                if field == other_field =>
+            {
+                Query::Between(field.clone(), low.clone(), high.clone())
+            }
+            _ => return false,
+        };
+        *self = between;
+        true
+    }
+
+    /// Объединяет два необязательных запроса через `AND`.
+    pub fn and(left: Option<Query>, right: Option<Query>) -> Option<Query> {
+        match (left, right) {
+            (Some(left), Some(right)) => Some(Query::And(Box::new(left), Box::new(right))),
+            (left, right) => left.or(right),
         }
     }
 
-    pub fn is_regex(&self) -> bool {
-        matches!(self, Query::Regex(_))
+    /// Полнотекстовый запрос (`/regex/`, `SEARCH` или `RAW`), к которому нельзя добавить условие.
+    pub fn is_full_text(&self) -> bool {
+        matches!(
+            self,
+            Query::Regex(_) | Query::AnySubstring(_) | Query::Raw(_)
+        )
+    }
+
+    /// Возвращает `true`, если запрос принимает только строки, принятые `other`
+    /// (например, `other AND ...`).
+    pub fn is_refinement_of(&self, other: &Query) -> bool {
+        if self == other {
+            return true;
+        }
+
+        match (self, other) {
+            (Query::Expr(Some(new), _), Query::Expr(Some(old), _)) => new.is_refinement_of(old),
+            (Query::Expr(..), Query::Expr(None, _)) => true,
+            (Query::And(left, right), Query::And(old_left, old_right))
+                if left.is_refinement_of(old_left) && right.is_refinement_of(old_right) =>
+            {
+                true
+            }
+            (Query::And(left, right), _) => {
+                left.is_refinement_of(other) || right.is_refinement_of(other)
+            }
+            (Query::Between(field, low, high), _) => {
+                Query::GE(field.clone(), low.clone()).is_refinement_of(other)
+                    || Query::LE(field.clone(), high.clone()).is_refinement_of(other)
+            }
+            _ => false,
+        }
+    }
+
+    /// Имена полей, которые проверяет запрос, в порядке появления.
+    pub fn fields(&self) -> Vec<&str> {
+        fn name(token: &Token) -> Option<&str> {
+            match token {
+                Token::Identifier(name) | Token::IndexedIdentifier(name, _) => Some(name),
+                _ => None,
+            }
+        }
+
+        match self {
+            Query::Expr(left, right) => left
+                .iter()
+                .chain(right.iter())
+                .flat_map(|query| query.fields())
+                .collect(),
+            Query::And(left, right) | Query::Or(left, right) => {
+                let mut fields = left.fields();
+                fields.extend(right.fields());
+                fields
+            }
+            Query::Has(field) | Query::Missing(field) => vec![field.as_str()],
+            Query::Like(left, ..)
+            | Query::Between(left, ..)
+            | Query::Equal(left, _)
+            | Query::GE(left, _)
+            | Query::LE(left, _)
+            | Query::Greater(left, _)
+            | Query::Less(left, _)
+            | Query::NE(left, _) => name(left).into_iter().collect(),
+            Query::Regex(_) | Query::AnySubstring(_) | Query::Raw(_) => vec![],
+        }
+    }
+
+    /// Предупреждение о первом поле запроса, которого нет среди `known`
+    /// (например, имен полей первых записей), с самым похожим известным именем.
+    /// Пустой `known` не проверяется: записи еще не загружены.
+    pub fn unknown_field(&self, known: &[&str]) -> Option<String> {
+        if known.is_empty() {
+            return None;
+        }
+
+        let field = self
+            .fields()
+            .into_iter()
+            .find(|field| !known.contains(field))?;
+        let closest = known
+            .iter()
+            .map(|name| (util::edit_distance(field, name), name))
+            .filter(|(distance, _)| *distance <= (field.chars().count() / 3).max(1))
+            .min_by_key(|(distance, _)| *distance);
+        Some(match closest {
+            Some((_, name)) => format!("unknown field '{}', did you mean '{}'?", field, name),
+            None => format!("unknown field '{}'", field),
+        })
+    }
+
+    /// Регулярные выражения, совпадения с которыми нужно подсветить в поле `field`.
+    pub fn highlights(&self, field: &str) -> Vec<&Regex> {
+        match self {
+            Query::Expr(Some(where_expr), _) => where_expr.highlights(field),
+            Query::Regex(regex) => vec![&regex.inner],
+            Query::And(left, right) | Query::Or(left, right) => {
+                let mut regexes = left.highlights(field);
+                regexes.extend(right.highlights(field));
+                regexes
+            }
+            Query::Equal(Token::Identifier(name), Token::Regex(regex))
+            | Query::Equal(Token::IndexedIdentifier(name, _), Token::Regex(regex))
+            | Query::Like(Token::Identifier(name), _, regex)
+            | Query::Like(Token::IndexedIdentifier(name, _), _, regex)
+                if name == field =>
+            {
+                vec![&regex.inner]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Есть ли значение не меньше операнда `right`.
+fn any_ge(values: &[Value], right: &Token) -> bool {
+    match right {
+        Token::String(right) => values.iter().any(|x| x >= right),
+        Token::Number(right) => values.iter().any(|x| x >= right),
+        Token::Date(right) => values.iter().any(|x| x >= right),
+        _ => false,
+    }
+}
+
+/// Есть ли значение не больше операнда `right`.
+fn any_le(values: &[Value], right: &Token) -> bool {
+    match right {
+        Token::String(right) => values.iter().any(|x| x <= right),
+        Token::Number(right) => values.iter().any(|x| x <= right),
+        Token::Date(right) => values.iter().any(|x| x <= right),
+        _ => false,
+    }
+}
+
+/// Наблюдатель за проверкой запроса (`Query::accept_with`). Для `()` ничего не делает,
+/// и обычная проверка после подстановки не замедляется.
+pub trait Probe {
+    /// Начата проверка условия `query`.
+    fn enter(&mut self, _query: &Query) {}
+    /// Закончена проверка последнего начатого условия.
+    fn leave(&mut self, _accepted: bool) {}
+    /// Прочитано значение поля.
+    fn lookup(&mut self) {}
+    /// Вычислено регулярное выражение.
+    fn regex(&mut self) {}
+}
+
+impl Probe for () {}
+
+/// Счетчики одного условия запроса.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConditionStats {
+    pub condition: String,
+    /// Вложенность условия в `AND`/`OR`.
+    pub depth: usize,
+    pub evaluated: usize,
+    pub accepted: usize,
+    pub lookups: usize,
+    pub regexes: usize,
+}
+
+/// Сколько раз проверялось каждое условие запроса и во что это обошлось.
+/// Условия перечислены в порядке записи в запросе, `AND` и `OR` - перед своими операндами.
+/// Чтения полей и регулярные выражения засчитываются условию, которое их выполнило.
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub records: usize,
+    pub accepted: usize,
+    pub conditions: Vec<ConditionStats>,
+    // Условия запроса ищутся по адресу, поэтому профиль действителен, пока жив запрос
+    index: HashMap<*const Query, usize>,
+    stack: Vec<Option<usize>>,
+}
+
+impl Profile {
+    pub fn new(query: &Query) -> Profile {
+        fn walk(query: &Query, depth: usize, profile: &mut Profile) {
+            let (label, children) = match query {
+                Query::Expr(Some(where_expr), _) => return walk(where_expr, depth, profile),
+                Query::Expr(None, _) => return,
+                Query::And(left, right) => ("AND".to_string(), vec![left, right]),
+                Query::Or(left, right) => ("OR".to_string(), vec![left, right]),
+                query => (query.to_string(), vec![]),
+            };
+            profile
+                .index
+                .insert(query as *const Query, profile.conditions.len());
+            profile.conditions.push(ConditionStats {
+                condition: label,
+                depth,
+                ..Default::default()
+            });
+            for child in children {
+                walk(child, depth + 1, profile);
+            }
+        }
+
+        let mut profile = Profile::default();
+        walk(query, 0, &mut profile);
+        profile
+    }
+
+    /// Учитывает результат проверки одной записи.
+    pub fn record(&mut self, accepted: bool) {
+        self.records += 1;
+        self.accepted += accepted as usize;
+    }
+
+    fn current(&mut self) -> Option<&mut ConditionStats> {
+        let index = (*self.stack.last()?)?;
+        self.conditions.get_mut(index)
+    }
+}
+
+impl Probe for Profile {
+    fn enter(&mut self, query: &Query) {
+        let index = self.index.get(&(query as *const Query)).copied();
+        self.stack.push(index);
+        if let Some(stats) = self.current() {
+            stats.evaluated += 1;
+        }
+    }
+
+    fn leave(&mut self, accepted: bool) {
+        if let Some(stats) = self.current() {
+            stats.accepted += accepted as usize;
+        }
+        self.stack.pop();
+    }
+
+    fn lookup(&mut self) {
+        if let Some(stats) = self.current() {
+            stats.lookups += 1;
+        }
+    }
+
+    fn regex(&mut self) {
+        if let Some(stats) = self.current() {
+            stats.regexes += 1;
+        }
     }
 }
 
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "records: {}, matched: {}", self.records, self.accepted)?;
+        writeln!(
+            f,
+            "{:>10} {:>10} {:>10} {:>10}  condition",
+            "evaluated", "matched", "lookups", "regexes"
+        )?;
+        for stats in &self.conditions {
+            writeln!(
+                f,
+                "{:>10} {:>10} {:>10} {:>10}  {}{}",
+                stats.evaluated,
+                stats.accepted,
+                stats.lookups,
+                stats.regexes,
+                "  ".repeat(stats.depth),
+                stats.condition
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `field_values` с отметкой о чтении поля для `probe`.
+fn probed_values<'b, 'a>(
+    token: &Token,
+    log_data: &'b FieldMap<'a>,
+    probe: &mut impl Probe,
+) -> &'b [Value<'a>] {
+    probe.lookup();
+    field_values(token, log_data)
+}
+
+/// Значения поля из условия: все значения `name` или одно значение `name[index]`.
+/// Индекс за пределами списка дает пустой срез, и условие не выполняется.
+fn field_values<'b, 'a>(token: &Token, log_data: &'b FieldMap<'a>) -> &'b [Value<'a>] {
+    let (name, index) = match token {
+        Token::Identifier(name) => (name, None),
+        Token::IndexedIdentifier(name, index) => (name, Some(*index)),
+        _ => return &[],
+    };
+
+    let values = match log_data.get(name) {
+        Some(Value::MultiValue(values)) => values.as_slice(),
+        Some(value) => std::slice::from_ref(value),
+        None => return &[],
+    };
+    match index {
+        Some(index) => values.get(index..=index).unwrap_or_default(),
+        None => values,
+    }
+}
+
+/// Единица длительности сразу после числа (`5s`, `250ms`, `900us`, `2m`):
+/// множитель для перевода в микросекунды. Без единицы ничего не читается.
+fn parse_duration_unit(iter: &mut Peekable<Chars>) -> f64 {
+    let mut lookahead = iter.clone();
+    let mut unit = String::new();
+    while let Some(c) = lookahead.next_if(|c| c.is_alphabetic()) {
+        unit.push(c);
+    }
+
+    let factor = match unit.as_str() {
+        "us" | "µs" => 1.0,
+        "ms" => 1_000.0,
+        "s" => 1_000_000.0,
+        "m" => 60_000_000.0,
+        _ => return 1.0,
+    };
+    *iter = lookahead;
+    factor
+}
+
+/// Индекс `[n]` сразу после имени поля. Если за `[` не следует число, ничего не читается.
+fn parse_index(iter: &mut Peekable<Chars>) -> Option<usize> {
+    let mut lookahead = iter.clone();
+    if lookahead.next() != Some('[') {
+        return None;
+    }
+
+    let mut digits = String::new();
+    while let Some(c) = lookahead.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    if lookahead.next() != Some(']') {
+        return None;
+    }
+
+    let index = digits.parse().ok()?;
+    *iter = lookahead;
+    Some(index)
+}
+
+/// Переводит шаблон SQL `LIKE` в регулярное выражение на все значение:
+/// `%` - любая последовательность символов, `_` - один символ.
+fn like_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    let mut literal = String::new();
+    for c in pattern.chars() {
+        match c {
+            '%' | '_' => {
+                regex.push_str(&regex::escape(&literal));
+                literal.clear();
+                regex.push_str(if c == '%' { ".*" } else { "." });
+            }
+            c => literal.push(c),
+        }
+    }
+    regex.push_str(&regex::escape(&literal));
+    regex.push('$');
+    regex
+}
+
 pub struct Compiler {
     now: NaiveDateTime,
 }
 
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
 impl Compiler {
     pub fn new() -> Self {
-        Self {
-            now: chrono::Local::now().naive_local(),
-        }
+        Compiler::with_zone(LogZone::Local)
+    }
+
+    /// Компилятор, для которого `now` - текущее время в поясе логов `zone`.
+    pub fn with_zone(zone: LogZone) -> Self {
+        Self { now: zone.now() }
     }
 
     fn parse_numeric<T: Iterator<Item = char>>(
         &self,
         iter: &mut Peekable<T>,
     ) -> Result<f64, ParseError> {
+        // Все точки подряд идущего числа читаются, чтобы `1.2.3` было ошибкой, а не `1.2`
         let mut tmp = String::new();
-        while iter.peek().is_some() && iter.peek().unwrap().is_numeric() {
-            tmp.push(iter.next().unwrap());
+        if let Some(c) = iter.next_if_eq(&'-') {
+            tmp.push(c);
+        }
+        while let Some(c) = iter.next_if(|c| c.is_numeric() || *c == '.') {
+            tmp.push(c);
         }
         Ok(tmp.parse::<f64>()?)
     }
@@ -323,28 +978,27 @@ impl Compiler {
         }
         iter.next();
         if tmp.starts_with("now") {
-            match tmp.chars().nth(3) {
-                Some('-') => {
-                    let mut str_iter = tmp.chars().skip(4).peekable();
-                    let offset = self.parse_numeric(&mut str_iter)?;
-                    match str_iter.next() {
-                        Some('s') => Ok(Token::Date(self.now - Duration::seconds(offset as i64))),
-                        Some('m') => Ok(Token::Date(self.now - Duration::minutes(offset as i64))),
-                        Some('h') => Ok(Token::Date(self.now - Duration::hours(offset as i64))),
-                        Some('d') => Ok(Token::Date(self.now - Duration::days(offset as i64))),
-                        Some('w') => Ok(Token::Date(self.now - Duration::weeks(offset as i64))),
-                        Some(c) => return Err(ParseError::UnexpectedChar(c)),
-                        _ => return Err(ParseError::UnexpectedEndOfInput),
-                    }
-                }
-                Some(_) => return Err(ParseError::InvalidDate),
-                None => Ok(Token::Date(self.now)),
-            }
+            Ok(Token::Date(parse_relative_date(self.now, &tmp)?))
         } else {
-            Ok(Token::Date(NaiveDateTime::parse_from_str(
-                &tmp,
-                "%Y-%m-%d %H:%M:%S%.9f",
-            )?))
+            Ok(Token::Date(Self::parse_absolute_date(&tmp)?))
+        }
+    }
+
+    /// Дата без долей секунды означает начало секунды: `'... 14:00:00'` равно `14:00:00.000000`,
+    /// поэтому `time > '... 14:00:00'` принимает записи `14:00:00.500000`.
+    fn parse_absolute_date(value: &str) -> Result<NaiveDateTime, ParseError> {
+        let error = match NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f") {
+            Ok(date) => return Ok(date),
+            Err(e) => e,
+        };
+
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M") {
+            return Ok(date);
+        }
+
+        match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            Ok(date) => date.and_hms_opt(0, 0, 0).ok_or(ParseError::InvalidDate),
+            Err(_) => Err(ParseError::TimeParseError(error)),
         }
     }
 
@@ -354,7 +1008,7 @@ impl Compiler {
         loop {
             match iter.peek() {
                 Some(&c) => match c {
-                    'a'..='z' | 'A'..='Z' => {
+                    'a'..='z' | 'A'..='Z' | '_' => {
                         let mut tmp = String::new();
                         while let Some(&peek) = iter.peek() {
                             match peek {
@@ -374,16 +1028,24 @@ impl Compiler {
 
                         match tmp.as_str() {
                             "WHERE" => tokens.push(Token::WHERE),
+                            "SEARCH" => tokens.push(Token::SEARCH),
+                            "RAW" => tokens.push(Token::RAW),
                             "AND" => tokens.push(Token::AND),
                             "OR" => tokens.push(Token::OR),
+                            "HAS" => tokens.push(Token::HAS),
+                            "MISSING" => tokens.push(Token::MISSING),
+                            "LIKE" => tokens.push(Token::LIKE),
                             "DESC" => tokens.push(Token::DESC),
                             "ASC" => tokens.push(Token::ASC),
-                            _ => tokens.push(Token::Identifier(tmp)),
+                            _ => tokens.push(match parse_index(&mut iter) {
+                                Some(index) => Token::IndexedIdentifier(tmp, index),
+                                None => Token::Identifier(tmp),
+                            }),
                         }
                     }
-                    '0'..='9' => {
-                        tokens.push(Token::Number(self.parse_numeric(&mut iter)?));
-                        iter.next();
+                    '0'..='9' | '-' => {
+                        let number = self.parse_numeric(&mut iter)?;
+                        tokens.push(Token::Number(number * parse_duration_unit(&mut iter)));
                     }
                     '"' => {
                         let mut tmp = String::new();
@@ -398,14 +1060,46 @@ impl Compiler {
                         tokens.push(self.parse_date(&mut iter)?);
                     }
                     '/' => {
-                        //regex
+                        //regex, `\/` внутри выражения - символ `/`
                         let mut tmp = String::new();
                         iter.next();
-                        while iter.peek().is_some() && iter.peek().unwrap().ne(&'/') {
-                            tmp.push(iter.next().unwrap());
+                        loop {
+                            match iter.next() {
+                                Some('/') => break,
+                                Some('\\') if iter.peek() == Some(&'/') => {
+                                    tmp.push('/');
+                                    iter.next();
+                                }
+                                Some(c) => tmp.push(c),
+                                None => return Err(ParseError::UnterminatedRegex),
+                            }
+                        }
+
+                        let mut flags = String::new();
+                        while let Some(&c) = iter.peek() {
+                            if !c.is_ascii_alphabetic() {
+                                break;
+                            }
+                            flags.push(c);
+                            iter.next();
                         }
+                        tokens.push(Token::Regex(RegexCmp::new(tmp, &flags)?));
+                    }
+                    '[' => {
+                        // Имя поля с произвольными символами: `[p:processName]`
+                        let mut tmp = String::new();
                         iter.next();
-                        tokens.push(Token::Regex(RegexCmp::new(&tmp)?));
+                        loop {
+                            match iter.next() {
+                                Some(']') => break,
+                                Some(c) => tmp.push(c),
+                                None => return Err(ParseError::UnexpectedEndOfInput),
+                            }
+                        }
+                        tokens.push(match parse_index(&mut iter) {
+                            Some(index) => Token::IndexedIdentifier(tmp, index),
+                            None => Token::Identifier(tmp),
+                        });
                     }
                     '(' => {
                         tokens.push(Token::OpenBrace);
@@ -497,8 +1191,17 @@ impl Compiler {
                 iter.next();
                 expr
             }
-            Some(Token::Identifier(ident)) => {
-                let left = Token::Identifier(ident.clone());
+            Some(Token::HAS) | Some(Token::MISSING) => {
+                let presence = iter.next() == Some(&Token::HAS);
+                match iter.next() {
+                    Some(Token::Identifier(name)) if presence => Ok(Query::Has(name.clone())),
+                    Some(Token::Identifier(name)) => Ok(Query::Missing(name.clone())),
+                    Some(t) => Err(ParseError::UnexpectedToken(t.clone())),
+                    None => Err(ParseError::UnexpectedEndOfInput),
+                }
+            }
+            Some(left @ (Token::Identifier(_) | Token::IndexedIdentifier(..))) => {
+                let left = (*left).clone();
                 iter.next();
                 match iter.peek() {
                     Some(Token::Equal) => {
@@ -523,7 +1226,18 @@ impl Compiler {
                     }
                     Some(Token::NE) => {
                         iter.next();
-                        Ok(Query::NE(left, self.compile_value(iter, false)?))
+                        Ok(Query::NE(left, self.compile_value(iter, true)?))
+                    }
+                    Some(Token::LIKE) => {
+                        iter.next();
+                        match iter.next() {
+                            Some(Token::String(pattern)) => {
+                                let regex = RegexCmp::new(like_to_regex(pattern), "as")?;
+                                Ok(Query::Like(left, pattern.clone(), regex))
+                            }
+                            Some(t) => Err(ParseError::UnexpectedToken(t.clone())),
+                            None => Err(ParseError::UnexpectedEndOfInput),
+                        }
                     }
                     Some(&t) => Err(ParseError::UnexpectedToken(t.clone())),
                     _ => Err(ParseError::UnexpectedEndOfInput),
@@ -552,29 +1266,79 @@ impl Compiler {
         Ok(ast)
     }
 
-    pub(crate) fn compile(&self, program: &str) -> Result<Query, ParseError> {
+    pub fn compile(&self, program: &str) -> Result<Query, ParseError> {
         let tokens = self.tokenize(program)?;
         let mut iter = tokens.iter().peekable();
         let mut ast = Query::Expr(None, None);
         while iter.peek().is_some() {
             match iter.next() {
                 Some(Token::WHERE) => {
-                    if let Query::Expr(left, _) = &mut ast {
-                        *left = Some(Box::new(self.compile_expression(&mut iter)?));
+                    let where_expr = Box::new(self.compile_expression(&mut iter)?);
+                    match &mut ast {
+                        Query::Expr(left, _) => *left = Some(where_expr),
+                        // `/regex/ WHERE ...` - регулярное выражение И условие.
+                        Query::Regex(_) => {
+                            ast = Query::And(
+                                Box::new(ast.clone()),
+                                Box::new(Query::Expr(Some(where_expr), None)),
+                            );
+                        }
+                        _ => return Err(ParseError::UnexpectedToken(Token::WHERE)),
                     }
                 }
-                Some(Token::Regex(regex)) => {
-                    ast = Query::Regex(regex.clone());
-                    if let Some(token) = iter.next() {
-                        return Err(ParseError::UnexpectedToken(token.clone()));
+                Some(token @ Token::Regex(regex)) => {
+                    let regex = Query::Regex(regex.clone());
+                    ast = match ast {
+                        Query::Expr(None, None) => regex,
+                        // `WHERE ... /regex/` приводится к тому же виду, что и `/regex/ WHERE ...`.
+                        expr @ Query::Expr(Some(_), _) => {
+                            Query::And(Box::new(regex), Box::new(expr))
+                        }
+                        _ => return Err(ParseError::UnexpectedToken(token.clone())),
+                    };
+                    match iter.peek() {
+                        None | Some(Token::WHERE) => {}
+                        Some(token) => return Err(ParseError::UnexpectedToken((*token).clone())),
                     }
                 }
+                Some(Token::SEARCH) => {
+                    if ast != Query::Expr(None, None) {
+                        return Err(ParseError::UnexpectedToken(Token::SEARCH));
+                    }
+
+                    let mut needles = vec![];
+                    for token in iter.by_ref() {
+                        match token {
+                            Token::String(s) => needles.push(s.to_lowercase()),
+                            other => return Err(ParseError::UnexpectedToken(other.clone())),
+                        }
+                    }
+                    if needles.is_empty() {
+                        return Err(ParseError::UnexpectedEndOfInput);
+                    }
+                    ast = Query::AnySubstring(needles);
+                }
+                Some(Token::RAW) => {
+                    if ast != Query::Expr(None, None) {
+                        return Err(ParseError::UnexpectedToken(Token::RAW));
+                    }
+
+                    ast = match (iter.next(), iter.next()) {
+                        (Some(Token::String(needle)), None) if !needle.is_empty() => {
+                            Query::Raw(needle.clone())
+                        }
+                        (Some(Token::String(_)), Some(other)) | (Some(other), _) => {
+                            return Err(ParseError::UnexpectedToken(other.clone()))
+                        }
+                        (None, _) => return Err(ParseError::UnexpectedEndOfInput),
+                    };
+                }
                 Some(other) => return Err(ParseError::UnexpectedToken(other.clone())),
                 None => return Err(ParseError::UnexpectedEndOfInput),
             }
         }
 
-        Ok(ast)
+        Ok(ast.simplify())
     }
 }
 
@@ -602,3 +1366,724 @@ fn test_regex_tokenize() {
         .unwrap();
     assert!(matches!(tokens[3], Token::Regex(_)));
 }
+
+#[test]
+fn test_compound_relative_date() {
+    let compiler = Compiler::new();
+    let tokens = compiler
+        .tokenize("WHERE time > 'now-1h30m' AND time < 'now+5m'")
+        .unwrap();
+    assert_eq!(
+        tokens[3],
+        Token::Date(compiler.now - chrono::Duration::minutes(90))
+    );
+    assert_eq!(
+        tokens[7],
+        Token::Date(compiler.now + chrono::Duration::minutes(5))
+    );
+}
+
+#[test]
+fn test_now_in_log_zone() {
+    use chrono::{FixedOffset, Utc};
+
+    let zone = |hours| LogZone::Fixed(FixedOffset::east_opt(hours * 3600).unwrap());
+    let mut map = FieldMap::new();
+    map.insert("time", Value::DateTime(Utc::now().naive_utc()));
+
+    // Запись записана сейчас по UTC: в поясе -10:00 это будущее, в +10:00 - прошлое
+    let query = "WHERE time >= 'now-1h'";
+    assert!(Compiler::with_zone(zone(-10))
+        .compile(query)
+        .unwrap()
+        .accept(&map));
+    assert!(!Compiler::with_zone(zone(10))
+        .compile(query)
+        .unwrap()
+        .accept(&map));
+}
+
+#[test]
+fn test_date_only_literal() {
+    let compiler = Compiler::new();
+    let query = compiler.compile("WHERE time > '2025-01-15'").unwrap();
+
+    let mut map = FieldMap::new();
+    let time = NaiveDate::from_ymd_opt(2025, 1, 15)
+        .unwrap()
+        .and_hms_opt(0, 0, 1)
+        .unwrap();
+    map.insert("time", Value::DateTime(time));
+    assert!(query.accept(&map));
+
+    let mut map = FieldMap::new();
+    let time = NaiveDate::from_ymd_opt(2025, 1, 14)
+        .unwrap()
+        .and_hms_opt(23, 59, 59)
+        .unwrap();
+    map.insert("time", Value::DateTime(time));
+    assert!(!query.accept(&map));
+}
+
+#[test]
+fn test_date_with_minutes_literal() {
+    let compiler = Compiler::new();
+    let tokens = compiler
+        .tokenize("WHERE time > '2025-01-15 10:30'")
+        .unwrap();
+    assert_eq!(
+        tokens[3],
+        Token::Date(
+            NaiveDate::from_ymd_opt(2025, 1, 15)
+                .unwrap()
+                .and_hms_opt(10, 30, 0)
+                .unwrap()
+        )
+    );
+}
+
+#[test]
+fn test_highlights() {
+    let compiler = Compiler::new();
+    let query = compiler
+        .compile(r#"WHERE event = /CALL|EXCP/ AND (process = "rphost" OR Txt = /ping/)"#)
+        .unwrap();
+    assert_eq!(query.highlights("event").len(), 1);
+    assert_eq!(query.highlights("Txt").len(), 1);
+    assert!(query.highlights("process").is_empty());
+
+    let query = compiler.compile("/ping/").unwrap();
+    assert_eq!(query.highlights("process").len(), 1);
+}
+
+#[test]
+fn test_refinement() {
+    let compiler = Compiler::new();
+    let old = compiler.compile(r#"WHERE event = "CALL""#).unwrap();
+    let new = compiler
+        .compile(r#"WHERE event = "CALL" AND process = "rphost""#)
+        .unwrap();
+    assert!(new.is_refinement_of(&old));
+    assert!(!old.is_refinement_of(&new));
+
+    let wider = compiler
+        .compile(r#"WHERE event = "CALL" OR process = "rphost""#)
+        .unwrap();
+    assert!(!wider.is_refinement_of(&old));
+
+    let regex = compiler.compile("/CALL/").unwrap();
+    assert!(!regex.is_refinement_of(&old));
+}
+
+#[test]
+fn test_regex_matches_parsed_line() {
+    use crate::parser::Fields;
+
+    let fields = Fields::new(
+        "05:12.345000-1,EXCP,2,process=rphost,OSThread=1234,Descr='connection lost'\r\n"
+            .to_string(),
+    );
+    let mut map = FieldMap::new();
+    while let Some((k, v)) = fields.parse_field() {
+        map.insert(k, Value::from(v));
+    }
+
+    let compiler = Compiler::new();
+    assert!(compiler.compile("/EXCP/").unwrap().accept(&map));
+    assert!(compiler.compile("/rphost/").unwrap().accept(&map));
+    assert!(compiler.compile("/connection/").unwrap().accept(&map));
+    assert!(!compiler.compile("/CALL/").unwrap().accept(&map));
+}
+
+#[test]
+fn test_regex_all_values_flag() {
+    let mut map = FieldMap::new();
+    let time = NaiveDate::from_ymd_opt(2022, 8, 2)
+        .unwrap()
+        .and_hms_opt(14, 23, 5)
+        .unwrap();
+    map.insert("time", Value::DateTime(time));
+    map.insert("duration", Value::Number(15001.0));
+    map.insert("event", Value::from("CALL"));
+
+    let compiler = Compiler::new();
+    assert!(!compiler.compile("/14:23:/").unwrap().accept(&map));
+    assert!(compiler.compile("/14:23:/a").unwrap().accept(&map));
+    assert!(!compiler.compile("/15001/").unwrap().accept(&map));
+    assert!(compiler.compile("/15001/a").unwrap().accept(&map));
+    assert!(compiler.compile("/x/q").is_err());
+}
+
+#[test]
+fn test_regex_flags() {
+    let query = Compiler::new().compile("/EXCP/i").unwrap();
+    let mut fields = FieldMap::new();
+    fields.insert("event", Value::from("excp"));
+    assert!(query.accept(&fields));
+
+    let query = Compiler::new().compile(r"/a\/b.c/s").unwrap();
+    let mut fields = FieldMap::new();
+    fields.insert("path", Value::from("a/b\nc"));
+    assert!(query.accept(&fields));
+
+    assert!(matches!(
+        Compiler::new().compile("/EXCP"),
+        Err(ParseError::UnterminatedRegex)
+    ));
+}
+
+#[test]
+fn test_search_any_substring() {
+    let compiler = Compiler::new();
+    let query = compiler.compile(r#"SEARCH "deadlock" "Timeout""#).unwrap();
+
+    let mut map = FieldMap::new();
+    map.insert("Descr", Value::from("Lock TIMEOUT exceeded"));
+    assert!(query.accept(&map));
+
+    let mut map = FieldMap::new();
+    map.insert("Descr", Value::from("ok"));
+    assert!(!query.accept(&map));
+
+    assert!(compiler.compile("SEARCH").is_err());
+    assert!(compiler.compile(r#"SEARCH "a" WHERE b = 1"#).is_err());
+    assert!(compiler.compile(r#"WHERE b = 1 SEARCH "a""#).is_err());
+    assert!(compiler.compile(r#"/a/ SEARCH "a""#).is_err());
+}
+
+#[test]
+fn test_bracketed_identifier() {
+    let query = Compiler::new()
+        .compile(r#"WHERE [Usr.Name] = "admin" AND [p:processName] = "base""#)
+        .unwrap();
+
+    let mut map = FieldMap::new();
+    map.insert("Usr.Name", Value::from("admin"));
+    map.insert("p:processName", Value::from("base"));
+    assert!(query.accept(&map));
+
+    let mut map = FieldMap::new();
+    map.insert("Usr", Value::from("admin"));
+    map.insert("p:processName", Value::from("base"));
+    assert!(!query.accept(&map));
+}
+
+#[test]
+fn test_field_presence() {
+    let compiler = Compiler::new();
+    let has = compiler.compile("WHERE HAS Sql").unwrap();
+    let missing = compiler.compile("WHERE MISSING Sql").unwrap();
+    let empty = compiler.compile(r#"WHERE Sql = """#).unwrap();
+
+    let mut with_field = FieldMap::new();
+    with_field.insert("event", Value::from("DBMSSQL"));
+    with_field.insert("Sql", Value::from(""));
+    assert!(has.accept(&with_field));
+    assert!(!missing.accept(&with_field));
+    assert!(empty.accept(&with_field));
+
+    let mut without_field = FieldMap::new();
+    without_field.insert("event", Value::from("EXCP"));
+    assert!(!has.accept(&without_field));
+    assert!(missing.accept(&without_field));
+    assert!(!empty.accept(&without_field));
+
+    let query = compiler
+        .compile(r#"WHERE event = "EXCP" AND MISSING [Usr.Name]"#)
+        .unwrap();
+    assert!(query.accept(&without_field));
+    assert!(compiler.compile("WHERE HAS").is_err());
+    assert!(compiler.compile(r#"WHERE HAS "Sql""#).is_err());
+}
+
+#[test]
+fn test_and_combines_optional_queries() {
+    let compiler = Compiler::new();
+    let base = compiler.compile(r#"WHERE process = "rphost""#).unwrap();
+    let quick = compiler.compile("/timeout/i").unwrap();
+
+    assert_eq!(Query::and(None, None), None);
+    assert_eq!(Query::and(Some(base.clone()), None), Some(base.clone()));
+    assert_eq!(Query::and(None, Some(quick.clone())), Some(quick.clone()));
+
+    let combined = Query::and(Some(base.clone()), Some(quick)).unwrap();
+    let mut map = FieldMap::new();
+    map.insert("process", Value::from("rphost"));
+    map.insert("Descr", Value::from("Lock TIMEOUT"));
+    assert!(combined.accept(&map));
+
+    let mut map = FieldMap::new();
+    map.insert("process", Value::from("rmngr"));
+    map.insert("Descr", Value::from("Lock TIMEOUT"));
+    assert!(!combined.accept(&map));
+
+    assert!(combined.is_refinement_of(&base));
+    let narrower = Query::and(
+        Some(base.clone()),
+        Some(
+            compiler
+                .compile(r#"WHERE event = "EXCP" AND Descr = "x""#)
+                .unwrap(),
+        ),
+    )
+    .unwrap();
+    let wider = Query::and(
+        Some(base),
+        Some(compiler.compile(r#"WHERE event = "EXCP""#).unwrap()),
+    )
+    .unwrap();
+    assert!(narrower.is_refinement_of(&wider));
+    assert!(!wider.is_refinement_of(&narrower));
+}
+
+#[test]
+fn test_field_regex_matches_strings_only() {
+    let compiler = Compiler::new();
+    let time = NaiveDate::from_ymd_opt(2022, 8, 2)
+        .unwrap()
+        .and_hms_opt(14, 23, 5)
+        .unwrap();
+    let mut map = FieldMap::new();
+    map.insert("time", Value::DateTime(time));
+    map.insert("duration", Value::Number(15001.0));
+    map.insert("process", Value::from("rphost1"));
+
+    assert!(!compiler
+        .compile(r"WHERE duration = /\d+/")
+        .unwrap()
+        .accept(&map));
+    assert!(compiler
+        .compile(r"WHERE duration = /\d+/a")
+        .unwrap()
+        .accept(&map));
+    assert!(!compiler
+        .compile("WHERE time = /14:23/")
+        .unwrap()
+        .accept(&map));
+    assert!(compiler
+        .compile("WHERE time = /14:23/a")
+        .unwrap()
+        .accept(&map));
+    assert!(compiler
+        .compile(r"WHERE process = /\d+/")
+        .unwrap()
+        .accept(&map));
+    assert!(compiler.compile(r"WHERE duration > /\d+/").is_err());
+}
+
+#[test]
+fn test_query_display() {
+    let compiler = Compiler::new();
+    let query = compiler
+        .compile(
+            r#"WHERE event = "EXCP" AND (process = /rph\/ost/i OR [Usr.Name] != "x") AND HAS Sql"#,
+        )
+        .unwrap();
+    assert_eq!(
+        query.to_string(),
+        r#"WHERE ((event = "EXCP" AND (process = /rph\/ost/i OR [Usr.Name] != "x")) AND HAS Sql)"#
+    );
+    assert_eq!(
+        compiler.compile(&query.to_string()).unwrap().to_string(),
+        query.to_string()
+    );
+
+    let query = compiler
+        .compile("WHERE time >= '2022-08-02 14:00:00' AND duration > 15")
+        .unwrap();
+    assert_eq!(
+        query.to_string(),
+        "WHERE (time >= '2022-08-02 14:00:00' AND duration > 15)"
+    );
+    assert_eq!(
+        compiler.compile(r#"SEARCH "a" "b""#).unwrap().to_string(),
+        r#"SEARCH "a" "b""#
+    );
+}
+
+#[test]
+fn test_query_display_precedence() {
+    let compiler = Compiler::new();
+    let query = compiler
+        .compile(r#"WHERE a = "1" OR b = "2" AND c = "3""#)
+        .unwrap();
+    assert_eq!(
+        query.to_string(),
+        r#"WHERE ((a = "1" OR b = "2") AND c = "3")"#
+    );
+
+    let query = compiler
+        .compile(r#"WHERE a = "1" OR (b = "2" AND c = "3")"#)
+        .unwrap();
+    assert_eq!(
+        query.to_string(),
+        r#"WHERE (a = "1" OR (b = "2" AND c = "3"))"#
+    );
+    assert_eq!(compiler.compile("").unwrap().to_string(), "");
+}
+
+#[test]
+fn test_like() {
+    let compiler = Compiler::new();
+    let matches = |query: &str, value: &str| {
+        let mut map = FieldMap::new();
+        map.insert("process", Value::from(value));
+        compiler.compile(query).unwrap().accept(&map)
+    };
+
+    assert!(matches(r#"WHERE process LIKE "1cv8%""#, "1cv8c"));
+    assert!(!matches(r#"WHERE process LIKE "1cv8%""#, "x1cv8c"));
+    assert!(matches(r#"WHERE process LIKE "_cv8""#, "1cv8"));
+    assert!(!matches(r#"WHERE process LIKE "_cv8""#, "11cv8"));
+    assert!(matches(r#"WHERE process LIKE "a.b%""#, "a.b(1)"));
+    assert!(!matches(r#"WHERE process LIKE "a.b%""#, "axb"));
+    assert!(matches(r#"WHERE process LIKE "12%""#, "1234"));
+    assert!(compiler.compile(r#"WHERE process LIKE /1cv8/"#).is_err());
+
+    // Выводится исходный шаблон, а не регулярное выражение
+    let query = compiler.compile(r#"WHERE process LIKE "1cv8%""#).unwrap();
+    assert_eq!(query.to_string(), r#"WHERE process LIKE "1cv8%""#);
+    assert_eq!(
+        compiler.compile(&query.to_string()).unwrap().to_string(),
+        query.to_string()
+    );
+}
+
+#[test]
+fn test_indexed_identifier() {
+    let mut map = FieldMap::new();
+    map.insert("Context", Value::from("first"));
+    map.insert("Context", Value::from("second"));
+    map.insert("Usr", Value::from("admin"));
+
+    let compiler = Compiler::new();
+    let accept = |filter: &str| compiler.compile(filter).unwrap().accept(&map);
+    assert!(accept(r#"WHERE Context[0] = "first""#));
+    assert!(!accept(r#"WHERE Context[1] = "first""#));
+    assert!(accept(r#"WHERE Context[1] = /sec/"#));
+    assert!(!accept(r#"WHERE Context[2] != "first""#));
+    assert!(accept(r#"WHERE Usr[0] = "admin" AND [Usr][0] LIKE "adm%""#));
+    assert!(!accept(r#"WHERE Usr[1] = "admin""#));
+
+    let query = compiler.compile(r#"WHERE [Usr.Name][3] = "x""#).unwrap();
+    assert_eq!(query.to_string(), r#"WHERE [Usr.Name][3] = "x""#);
+}
+
+#[test]
+fn test_repeated_field_any_value() {
+    let mut map = FieldMap::new();
+    map.insert("t:connectID", Value::from("3"));
+    map.insert("t:connectID", Value::from("5"));
+    map.insert("Usr", Value::from("admin"));
+    map.insert("Usr", Value::from("guest"));
+
+    // Условие выполняется, если ему удовлетворяет любое из значений поля
+    let compiler = Compiler::new();
+    let accept = |filter: &str| compiler.compile(filter).unwrap().accept(&map);
+    assert!(accept(r#"WHERE Usr = "guest""#));
+    assert!(!accept(r#"WHERE Usr = "root""#));
+    assert!(accept(r#"WHERE t:connectID = 5"#));
+    assert!(accept(r#"WHERE t:connectID = 3"#));
+    assert!(!accept(r#"WHERE t:connectID = 4"#));
+    assert!(accept(r#"WHERE t:connectID > 4"#));
+    assert!(accept(r#"WHERE t:connectID < 4"#));
+    assert!(!accept(r#"WHERE t:connectID > 5"#));
+    assert!(accept(r#"WHERE t:connectID >= 5"#));
+}
+
+#[test]
+fn test_duration_literal() {
+    let compiler = Compiler::new();
+    let tokens = compiler.tokenize("5s 250ms 900us 2m 7").unwrap();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Number(5_000_000.0),
+            Token::Number(250_000.0),
+            Token::Number(900.0),
+            Token::Number(120_000_000.0),
+            Token::Number(7.0),
+        ]
+    );
+
+    let mut map = FieldMap::new();
+    map.insert("duration", Value::from("1500000"));
+    let accept = |filter: &str| compiler.compile(filter).unwrap().accept(&map);
+    assert!(accept("WHERE duration > 1s"));
+    assert!(!accept("WHERE (duration > 2s)"));
+    assert!(accept("WHERE duration >= 1500ms AND duration < 1m"));
+}
+
+#[test]
+fn test_regex_not_equal() {
+    let compiler = Compiler::new();
+    let query = compiler.compile("WHERE stack != /foo/").unwrap();
+    let record = |stack: Option<&str>| {
+        let mut map = FieldMap::new();
+        map.insert("event", Value::from("EXCP"));
+        if let Some(stack) = stack {
+            map.insert("stack", Value::from(stack));
+        }
+        query.accept(&map)
+    };
+
+    assert!(record(Some("bar.baz")));
+    assert!(!record(Some("bar.foo")));
+    assert!(!record(None));
+
+    let mut map = FieldMap::new();
+    map.insert("duration", Value::from("15001"));
+    assert!(!compiler
+        .compile("WHERE duration != /9/")
+        .unwrap()
+        .accept(&map));
+    assert!(compiler
+        .compile("WHERE duration != /9/a")
+        .unwrap()
+        .accept(&map));
+}
+
+#[test]
+fn test_decimal_and_negative_numbers() {
+    let compiler = Compiler::new();
+    let tokens = compiler.tokenize("0.5 -3 10.25 -1.5s").unwrap();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Number(0.5),
+            Token::Number(-3.0),
+            Token::Number(10.25),
+            Token::Number(-1_500_000.0),
+        ]
+    );
+
+    let mut map = FieldMap::new();
+    map.insert("ratio", Value::from("0.75"));
+    map.insert("delta", Value::from("-2"));
+    map.insert("rows", Value::from("n/a"));
+    let accept = |filter: &str| compiler.compile(filter).unwrap().accept(&map);
+    assert!(accept("WHERE ratio > 0.5"));
+    assert!(!accept("WHERE ratio > 10.25"));
+    assert!(accept("WHERE delta > -3"));
+    assert!(!accept("WHERE (delta < -3)"));
+
+    // Нечисловое значение не проходит ни одно числовое сравнение, в том числе `!=`
+    assert!(!accept("WHERE rows != 5"));
+    assert!(!accept("WHERE rows > 5"));
+    assert!(!accept("WHERE rows < 5"));
+    assert!(accept("WHERE delta != 5"));
+
+    assert!(matches!(
+        compiler.compile("WHERE ratio > 1.2.3"),
+        Err(ParseError::FloatParseError(_))
+    ));
+    assert!(compiler.compile("WHERE ratio > -").is_err());
+}
+
+#[test]
+fn test_fractional_seconds_literal() {
+    let compiler = Compiler::new();
+    let time = NaiveDate::from_ymd_opt(2022, 8, 2)
+        .unwrap()
+        .and_hms_micro_opt(14, 0, 0, 500_000)
+        .unwrap();
+    let mut map = FieldMap::new();
+    map.insert("time", Value::DateTime(time));
+    let accept = |filter: &str| compiler.compile(filter).unwrap().accept(&map);
+
+    assert!(accept("WHERE time = '2022-08-02 14:00:00.500000'"));
+    assert!(accept("WHERE time = '2022-08-02 14:00:00.5'"));
+    assert!(accept("WHERE time > '2022-08-02 14:00:00.499999'"));
+    assert!(!accept("WHERE time > '2022-08-02 14:00:00.5'"));
+    // Целая секунда - ее начало, а не вся секунда
+    assert!(accept("WHERE time > '2022-08-02 14:00:00'"));
+    assert!(!accept("WHERE time = '2022-08-02 14:00:00'"));
+
+    let query = compiler
+        .compile("WHERE time >= '2022-08-02 14:00:00.25'")
+        .unwrap();
+    assert_eq!(query.to_string(), "WHERE time >= '2022-08-02 14:00:00.250'");
+}
+
+#[test]
+fn test_regex_and_where() {
+    let compiler = Compiler::new();
+    let mut map = FieldMap::new();
+    map.insert("event", Value::String("CALL".into()));
+    map.insert("Txt", Value::String("connection timeout".into()));
+
+    let leading = compiler
+        .compile(r#"/timeout/ WHERE event = "CALL""#)
+        .unwrap();
+    let trailing = compiler
+        .compile(r#"WHERE event = "CALL" /timeout/"#)
+        .unwrap();
+    assert!(matches!(&leading, Query::And(left, right)
+        if matches!(**left, Query::Regex(_)) && matches!(**right, Query::Expr(Some(_), _))));
+    assert_eq!(leading, trailing);
+    assert!(leading.accept(&map));
+    assert!(!compiler
+        .compile(r#"/timeout/ WHERE event = "EXCP""#)
+        .unwrap()
+        .accept(&map));
+    assert!(!compiler
+        .compile(r#"WHERE event = "CALL" /refused/"#)
+        .unwrap()
+        .accept(&map));
+    assert_eq!(leading.highlights("Txt").len(), 1);
+
+    assert!(compiler.compile(r#"/a/ /b/"#).is_err());
+    assert!(compiler.compile(r#"/a/ WHERE event = "CALL" /b/"#).is_err());
+    assert!(compiler.compile(r#"/a/ event = "CALL""#).is_err());
+}
+
+#[test]
+fn test_unknown_field() {
+    let compiler = Compiler::new();
+    let known = ["time", "event", "process", "Context"];
+    let warning = |query: &str| compiler.compile(query).unwrap().unknown_field(&known);
+
+    assert_eq!(
+        warning(r#"WHERE evnt = "EXCP""#).as_deref(),
+        Some("unknown field 'evnt', did you mean 'event'?")
+    );
+    assert_eq!(
+        warning(r#"WHERE event = "EXCP" AND (HAS context OR process = "rphost")"#).as_deref(),
+        Some("unknown field 'context', did you mean 'Context'?")
+    );
+    assert_eq!(
+        warning("WHERE Usr = 1").as_deref(),
+        Some("unknown field 'Usr'")
+    );
+    assert_eq!(warning(r#"/x/ WHERE Context[1] = "a""#), None);
+    assert_eq!(
+        compiler
+            .compile("WHERE evnt = 1")
+            .unwrap()
+            .unknown_field(&[]),
+        None
+    );
+}
+
+#[test]
+fn test_fold_range() {
+    let compiler = Compiler::new();
+    let folded = compiler
+        .compile(r#"WHERE event = "CALL" AND duration >= 1000 AND duration <= 5000"#)
+        .unwrap();
+    assert_eq!(
+        folded.to_string(),
+        r#"WHERE (event = "CALL" AND (duration >= 1000 AND duration <= 5000))"#
+    );
+    assert!(matches!(
+        compiler.compile("WHERE duration <= 5000 AND duration >= 1000").unwrap(),
+        Query::Expr(Some(query), _) if matches!(*query, Query::Between(..))
+    ));
+    // Разные поля и условия в разных ветках OR не сворачиваются
+    for query in [
+        "WHERE duration >= 1000 AND depth <= 5000",
+        "WHERE duration >= 1000 OR duration <= 5000",
+    ] {
+        let query = compiler.compile(query).unwrap();
+        assert!(matches!(query, Query::Expr(Some(query), _)
+            if matches!(*query, Query::And(..) | Query::Or(..))));
+    }
+
+    let unfolded = Query::And(
+        Box::new(Query::GE(
+            Token::Identifier("duration".into()),
+            Token::Number(1000.0),
+        )),
+        Box::new(Query::LE(
+            Token::Identifier("duration".into()),
+            Token::Number(5000.0),
+        )),
+    );
+    let folded = compiler
+        .compile("WHERE duration >= 1000 AND duration <= 5000")
+        .unwrap();
+    for values in [
+        vec!["500"],
+        vec!["1000"],
+        vec!["3000"],
+        vec!["5000"],
+        vec!["7000"],
+        vec!["500", "7000"],
+        vec![],
+    ] {
+        let mut map = FieldMap::new();
+        for value in values {
+            map.insert("duration", Value::from(value.to_string()));
+        }
+        assert_eq!(folded.accept(&map), unfolded.accept(&map));
+    }
+
+    let old = compiler.compile("WHERE duration >= 1000").unwrap();
+    assert!(folded.is_refinement_of(&old));
+}
+
+#[test]
+fn test_raw_substring() {
+    let compiler = Compiler::new();
+    let query = compiler.compile(r#"RAW "Descr='a""#).unwrap();
+    assert_eq!(query.to_string(), r#"RAW "Descr='a""#);
+    assert!(query.is_full_text());
+
+    // Поля не разбираются: незакрытая кавычка не мешает найти запись
+    let fields = || -> Arc<FieldMap> { unreachable!() };
+    assert!(query.accept_line(&|| String::from("00:01.0-0,EXCP,1,Descr='abc"), &fields));
+    assert!(!query.accept_line(&|| String::from("00:01.0-0,EXCP,1,descr='abc"), &fields));
+
+    let mut map = FieldMap::new();
+    map.insert("event", Value::from("EXCP"));
+    let map = Arc::new(map);
+    let query = Query::and(
+        Some(query),
+        Some(compiler.compile(r#"WHERE event = "EXCP""#).unwrap()),
+    )
+    .unwrap();
+    assert!(query.accept_line(&|| String::from("Descr='a"), &|| map.clone()));
+    assert!(!query.accept_line(&|| String::from("Descr=a"), &|| map.clone()));
+
+    assert!(compiler.compile("RAW").is_err());
+    assert!(compiler.compile(r#"RAW """#).is_err());
+    assert!(compiler.compile(r#"RAW "a" "b""#).is_err());
+    assert!(compiler.compile(r#"RAW "a" WHERE b = 1"#).is_err());
+    assert!(compiler.compile(r#"WHERE b = 1 RAW "a""#).is_err());
+}
+
+#[test]
+fn test_profile() {
+    let query = Compiler::new()
+        .compile(r#"WHERE Descr = /timeout/ AND event = "EXCP""#)
+        .unwrap();
+    let mut profile = Profile::new(&query);
+    for (event, descr) in [("EXCP", "timeout 1"), ("CALL", "timeout"), ("EXCP", "ok")] {
+        let mut map = FieldMap::new();
+        map.insert("event", Value::from(event));
+        map.insert("Descr", Value::from(descr));
+        let accepted = query.accept_with(&map, &mut profile);
+        assert_eq!(accepted, query.accept(&map));
+        profile.record(accepted);
+    }
+
+    let stats = |condition: &str, evaluated, accepted, lookups, regexes| ConditionStats {
+        condition: condition.to_string(),
+        depth: (condition != "AND") as usize,
+        evaluated,
+        accepted,
+        lookups,
+        regexes,
+    };
+    assert_eq!((profile.records, profile.accepted), (3, 1));
+    assert_eq!(
+        profile.conditions,
+        vec![
+            stats("AND", 3, 1, 0, 0),
+            stats("Descr = /timeout/", 3, 2, 3, 3),
+            // После несовпадения первого условия второе не проверяется
+            stats(r#"event = "EXCP""#, 2, 1, 2, 0),
+        ]
+    );
+    assert!(profile.to_string().contains("  Descr = /timeout/\n"));
+}
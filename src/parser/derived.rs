@@ -0,0 +1,81 @@
+use crate::parser::{FieldMap, Value};
+use regex::Regex;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DeriveError {
+    #[error("expected name=field:/regex/")]
+    Syntax,
+    #[error("{0}")]
+    Regex(#[from] regex::Error),
+    #[error("regex must have exactly one capture group")]
+    Captures,
+}
+
+/// Виртуальное поле: первая группа регулярного выражения по значению другого поля.
+/// Задается строкой `name=field:/regex/`, например `rows=Sql:/rows=(\d+)/`.
+#[derive(Debug, Clone)]
+pub struct DerivedField {
+    pub name: String,
+    pub source: String,
+    regex: Regex,
+}
+
+impl DerivedField {
+    /// Значение поля для записи. Без совпадения поле отсутствует.
+    pub fn extract(&self, fields: &FieldMap) -> Option<Value<'static>> {
+        let value = fields.get(&self.source)?.iter().next()?.to_string();
+        let captures = self.regex.captures(&value)?;
+        Some(Value::from(captures.get(1)?.as_str().to_string()))
+    }
+}
+
+impl FromStr for DerivedField {
+    type Err = DeriveError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = value.split_once('=').ok_or(DeriveError::Syntax)?;
+        let (source, regex) = rest.split_once(':').ok_or(DeriveError::Syntax)?;
+        let regex = regex
+            .strip_prefix('/')
+            .and_then(|regex| regex.strip_suffix('/'))
+            .ok_or(DeriveError::Syntax)?;
+        let (name, source) = (name.trim(), source.trim());
+        if name.is_empty() || source.is_empty() {
+            return Err(DeriveError::Syntax);
+        }
+
+        let regex = Regex::new(&regex.replace("\\/", "/"))?;
+        if regex.captures_len() != 2 {
+            return Err(DeriveError::Captures);
+        }
+        Ok(DerivedField {
+            name: name.to_string(),
+            source: source.to_string(),
+            regex,
+        })
+    }
+}
+
+#[test]
+fn test_derived_field() {
+    let field = "rows=Sql:/rows=(\\d+)/".parse::<DerivedField>().unwrap();
+    let mut fields = FieldMap::new();
+    fields.insert("Sql", Value::from("SELECT, rows=120"));
+    assert_eq!(field.extract(&fields), Some(Value::Number(120.0)));
+
+    fields = FieldMap::new();
+    fields.insert("Sql", Value::from("SELECT"));
+    assert_eq!(field.extract(&fields), None);
+    assert_eq!(field.extract(&FieldMap::new()), None);
+
+    assert!(matches!(
+        "rows=Sql:/rows=\\d+/".parse::<DerivedField>(),
+        Err(DeriveError::Captures)
+    ));
+    assert!(matches!(
+        "rows=Sql:rows".parse::<DerivedField>(),
+        Err(DeriveError::Syntax)
+    ));
+}
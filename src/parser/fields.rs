@@ -1,5 +1,79 @@
 use crate::parser::{FieldMap, Value};
-use std::{borrow::Cow, cell::Cell};
+use std::{borrow::Cow, cell::Cell, sync::RwLock};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SyntaxError {
+    #[error("{0:?} must be one ASCII character")]
+    Delimiter(String),
+    #[error("{0:?} must be one or two ASCII characters")]
+    Quote(String),
+    #[error("{0:?} can't be used as a delimiter or a quote")]
+    Reserved(char),
+    #[error("{0:?} is both the delimiter and a quote")]
+    Conflict(char),
+}
+
+/// Разделитель полей и кавычки значений. По умолчанию как в технологическом журнале: `,`, `'` и `"`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Syntax {
+    pub delimiter: u8,
+    pub quotes: [u8; 2],
+}
+
+impl Syntax {
+    /// Разделитель из одного символа и одна или две кавычки (`--delimiter`, `--quote`).
+    pub fn parse(delimiter: &str, quotes: &str) -> Result<Syntax, SyntaxError> {
+        let ascii = |value: &str| value.is_ascii().then(|| value.as_bytes().to_vec());
+        let delimiter = match ascii(delimiter).as_deref() {
+            Some(&[byte]) => byte,
+            _ => return Err(SyntaxError::Delimiter(delimiter.to_string())),
+        };
+        let quotes = match ascii(quotes).as_deref() {
+            Some(&[quote]) => [quote, quote],
+            Some(&[first, second]) => [first, second],
+            _ => return Err(SyntaxError::Quote(quotes.to_string())),
+        };
+
+        // `=` отделяет ключ от значения, `-` - время от длительности
+        for byte in [delimiter, quotes[0], quotes[1]] {
+            if matches!(byte, b'=' | b'-' | b'\r' | b'\n') {
+                return Err(SyntaxError::Reserved(byte as char));
+            }
+        }
+        if quotes.contains(&delimiter) {
+            return Err(SyntaxError::Conflict(delimiter as char));
+        }
+
+        Ok(Syntax { delimiter, quotes })
+    }
+
+    pub fn is_quote(&self, byte: u8) -> bool {
+        self.quotes.contains(&byte)
+    }
+}
+
+impl Default for Syntax {
+    fn default() -> Self {
+        Syntax {
+            delimiter: b',',
+            quotes: [b'\'', b'"'],
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref SYNTAX: RwLock<Syntax> = RwLock::new(Syntax::default());
+}
+
+/// Задает разделитель и кавычки для `Fields::new` и чтения записей из файлов.
+pub fn set_syntax(syntax: Syntax) {
+    *SYNTAX.write().unwrap() = syntax;
+}
+
+pub fn syntax() -> Syntax {
+    *SYNTAX.read().unwrap()
+}
 
 #[derive(Clone, Copy)]
 enum ParseState {
@@ -24,14 +98,24 @@ pub struct Fields {
     reader: String,
     state: Cell<ParseState>,
     index: Cell<usize>,
+    // Сбрасывается, если данные закончились посреди ключа или значения
+    complete: Cell<bool>,
+    syntax: Syntax,
 }
 
 impl Fields {
+    /// Поля записи с разделителем и кавычками из `set_syntax`.
     pub fn new(reader: String) -> Self {
+        Fields::with_syntax(reader, syntax())
+    }
+
+    pub fn with_syntax(reader: String, syntax: Syntax) -> Self {
         Fields {
             reader,
             state: Cell::new(ParseState::StartLogLine),
             index: Cell::new(0),
+            complete: Cell::new(true),
+            syntax,
         }
     }
 
@@ -39,21 +123,31 @@ impl Fields {
         self.index.get()
     }
 
+    /// Запись до сих пор разбиралась без ошибок: нет незакрытой кавычки,
+    /// ключа без `=` или `=` без значения в конце данных.
+    pub fn is_complete(&self) -> bool {
+        self.complete.get()
+    }
+
     fn read_until(&self, find: u8) -> Option<&str> {
+        self.read_until_any(&[find]).map(|(value, _)| value)
+    }
+
+    /// Читает до первого из байтов `find`; возвращает значение и найденный байт (`None` - конец строки).
+    fn read_until_any(&self, find: &[u8]) -> Option<(&str, Option<u8>)> {
         let begin = self.index.get();
-        let mut size = 0 as usize;
+        let mut stop = None;
         while let Some(byte) = self.read_byte() {
-            size += 1;
-
-            if byte == find {
+            if find.contains(&byte) {
+                stop = Some(byte);
                 break;
             }
         }
 
-        let size = size.saturating_sub(1);
-        match size {
+        let end = self.index.get() - stop.map_or(0, |_| 1);
+        match end - begin {
             0 => None,
-            _ => Some(&self.reader[begin..(begin + size)]),
+            _ => Some((&self.reader[begin..end], stop)),
         }
     }
 
@@ -74,48 +168,59 @@ impl Fields {
         loop {
             match value_state {
                 ParseValueState::BeginParse => match self.read_byte() {
-                    Some(char) if char == b'\r' || char == b'\n' || char == b',' => {
+                    Some(char)
+                        if char == b'\r' || char == b'\n' || char == self.syntax.delimiter =>
+                    {
                         value = "";
                         value_state = ParseValueState::Finish(char);
                     }
-                    Some(char) if char == b'\'' || char == b'"' => {
+                    Some(char) if self.syntax.is_quote(char) => {
                         value_state = ParseValueState::ReadValueUntil(char);
                     }
                     Some(_) => {
                         value_state = ParseValueState::ReadValueToNext;
                     }
-                    None => unreachable!(),
+                    None => {
+                        self.complete.set(false);
+                        value = "";
+                        value_state = ParseValueState::Finish(b'\n');
+                    }
                 },
                 ParseValueState::ReadValueUntil(quote) => {
                     let begin = self.current();
+                    // Данные закончились раньше закрывающей кавычки - это конец записи
+                    value = &self.reader[begin..];
+                    value_state = ParseValueState::Finish(b'\n');
+                    let mut closed = false;
                     while let Some(char) = self.read_byte() {
-                        match char {
-                            b'\'' | b'"' if char == quote => {
-                                let end = self.current().saturating_sub(1);
-                                let read = self.read_byte();
-                                match read {
-                                    Some(byte) if char == byte => continue,
-                                    _ => {}
-                                };
-
-                                value = &self.reader[begin..end];
-                                value_state = ParseValueState::Finish(read.unwrap());
-                                break;
-                            }
-                            _ => {}
+                        if char == quote {
+                            let end = self.current().saturating_sub(1);
+                            let read = self.read_byte();
+                            match read {
+                                Some(byte) if char == byte => continue,
+                                _ => {}
+                            };
+
+                            value = &self.reader[begin..end];
+                            closed = true;
+                            // Кавычка в самом конце данных завершает запись
+                            value_state = ParseValueState::Finish(read.unwrap_or(b'\n'));
+                            break;
                         }
                     }
+                    if !closed {
+                        self.complete.set(false);
+                    }
                 }
                 ParseValueState::ReadValueToNext => {
                     let begin = self.current().saturating_sub(1);
+                    value = &self.reader[begin..];
+                    value_state = ParseValueState::Finish(b'\n');
                     while let Some(char) = self.read_byte() {
-                        match char {
-                            b'\r' | b'\n' | b',' => {
-                                value = &self.reader[begin..self.current().saturating_sub(1)];
-                                value_state = ParseValueState::Finish(char);
-                                break;
-                            }
-                            _ => {}
+                        if char == b'\r' || char == b'\n' || char == self.syntax.delimiter {
+                            value = &self.reader[begin..self.current().saturating_sub(1)];
+                            value_state = ParseValueState::Finish(char);
+                            break;
                         }
                     }
                 }
@@ -128,10 +233,14 @@ impl Fields {
                         b'\n' => {
                             self.state.set(ParseState::Finish);
                         }
-                        b',' => {
+                        _ if char == self.syntax.delimiter => {
                             self.state.set(ParseState::Key);
                         }
-                        _ => unreachable!(),
+                        // Текст сразу после закрывающей кавычки: дальше поля не разобрать
+                        _ => {
+                            self.complete.set(false);
+                            self.state.set(ParseState::Finish);
+                        }
                     }
                     break;
                 }
@@ -148,26 +257,39 @@ impl Fields {
         loop {
             match self.state.get() {
                 ParseState::StartLogLine => {
-                    let value = self.read_until(b'-')?;
-                    self.state.set(ParseState::Duration);
+                    // Строка без длительности: `мм:сс.дробь,СОБЫТИЕ,...`
+                    let delimiter = self.syntax.delimiter;
+                    let (value, stop) = self.read_until_any(&[b'-', delimiter])?;
+                    self.state.set(match stop {
+                        Some(byte) if byte == delimiter => ParseState::EventField,
+                        _ => ParseState::Duration,
+                    });
                     return Some((Cow::Borrowed("time"), value));
                 }
                 ParseState::Duration => {
-                    let value = self.read_until(b',')?;
+                    let value = self.read_until(self.syntax.delimiter)?;
                     self.state.set(ParseState::EventField);
                     return Some((Cow::Borrowed("duration"), value));
                 }
                 ParseState::EventField => {
-                    let value = self.read_until(b',')?;
+                    let value = self.read_until(self.syntax.delimiter)?;
                     self.state.set(ParseState::Undefined);
                     return Some((Cow::Borrowed("event"), value));
                 }
                 ParseState::Undefined => {
-                    let _ = self.read_until(b',')?;
+                    let _ = self.read_until(self.syntax.delimiter)?;
                     self.state.set(ParseState::Key);
                 }
                 ParseState::Key => {
-                    key = self.read_until(b'=')?;
+                    let (name, stop) = self.read_until_any(b"=")?;
+                    if stop.is_none() {
+                        // Хвост без `=`: перевод строки после разделителя не ошибка
+                        if !name.trim().is_empty() {
+                            self.complete.set(false);
+                        }
+                        return None;
+                    }
+                    key = name;
                     self.state.set(ParseState::Value);
                 }
                 ParseState::Value => {
@@ -201,15 +323,113 @@ impl<'a> Iterator for Iter<'a> {
     }
 }
 
-impl From<Fields> for FieldMap<'static> {
-    fn from(iter: Fields) -> Self {
-        let mut map = FieldMap::new();
-        while let Some((k, v)) = iter.parse_field() {
+impl FieldMap<'static> {
+    /// Добавляет поля записи `fields`, кроме времени без часа (`mm:ss.ffffff`).
+    pub fn insert_fields(&mut self, fields: Fields) {
+        while let Some((k, v)) = fields.parse_field() {
             if k == "time" {
                 continue;
             }
-            map.insert(k.to_string(), Value::from(v.to_string()))
+            self.insert(k.to_string(), Value::from(v.to_string()))
         }
-        map
     }
 }
+
+#[test]
+fn test_quoted_multiline_value() {
+    let fields = Fields::new(
+        "05:12.345000-1,DBMSSQL,2,Sql='SELECT 1\r\nFROM T',Context=\"Форма.Вызов\nМодуль\"\r\n"
+            .to_string(),
+    );
+    let values = fields.iter().collect::<Vec<_>>();
+    assert_eq!(values[3], (Cow::Borrowed("Sql"), "SELECT 1\r\nFROM T"));
+    assert_eq!(values[4], (Cow::Borrowed("Context"), "Форма.Вызов\nМодуль"));
+}
+
+#[test]
+fn test_escaped_quote_at_end_of_data() {
+    let fields = Fields::new("05:12.345000-1,EXCP,2,Descr=\"say \"\"hi\"\"\"".to_string());
+    let values = fields.iter().collect::<Vec<_>>();
+    assert_eq!(values.len(), 4);
+    assert_eq!(values[3], (Cow::Borrowed("Descr"), "say \"\"hi\"\""));
+
+    let fields = Fields::new("05:12.345000-1,EXCP,2,Descr='a''".to_string());
+    let values = fields.iter().collect::<Vec<_>>();
+    assert_eq!(values[3], (Cow::Borrowed("Descr"), "a''"));
+
+    let fields = Fields::new("05:12.345000-1,EXCP,2,process=rphost".to_string());
+    let values = fields.iter().collect::<Vec<_>>();
+    assert_eq!(values[3], (Cow::Borrowed("process"), "rphost"));
+}
+
+#[test]
+fn test_time_without_duration() {
+    let fields = Fields::new("00:00,EXCP,2,process=rphost".to_string());
+    let values = fields.iter().collect::<Vec<_>>();
+    assert_eq!(values[0], (Cow::Borrowed("time"), "00:00"));
+    assert_eq!(values[1], (Cow::Borrowed("event"), "EXCP"));
+    assert_eq!(values[2], (Cow::Borrowed("process"), "rphost"));
+
+    let fields = Fields::new("05:12.345000-1,EXCP".to_string());
+    let values = fields.iter().collect::<Vec<_>>();
+    assert_eq!(values[0], (Cow::Borrowed("time"), "05:12.345000"));
+    assert_eq!(values[1], (Cow::Borrowed("duration"), "1"));
+    assert_eq!(values[2], (Cow::Borrowed("event"), "EXCP"));
+}
+
+#[test]
+fn test_incomplete_records() {
+    let complete = |text: &str| {
+        let fields = Fields::new(text.to_string());
+        let count = fields.iter().count();
+        (count, fields.is_complete())
+    };
+
+    assert_eq!(complete("05:12.345000-1,EXCP,2,Descr='a,b'\r\n"), (4, true));
+    assert_eq!(
+        complete("05:12.345000-1,EXCP,2,process=rphost,\r\n"),
+        (4, true)
+    );
+    assert_eq!(complete("05:12.345000-1,EXCP,2,Descr='a,b"), (4, false));
+    assert_eq!(complete("05:12.345000-1,EXCP,2,Descr="), (4, false));
+    assert_eq!(
+        complete("05:12.345000-1,EXCP,2,process=rphost,garbage"),
+        (4, false)
+    );
+    assert_eq!(
+        complete("05:12.345000-1,EXCP,2,Descr='a'b,process=rphost"),
+        (4, false)
+    );
+}
+
+#[test]
+fn test_custom_syntax() {
+    let syntax = Syntax::parse(";", "|").unwrap();
+    let fields = Fields::with_syntax(
+        "05:12.345000-1;EXCP;2;Descr=|a;b, ||c||\r\nd|;process=rphost\r\n".to_string(),
+        syntax,
+    );
+    let values = fields.iter().collect::<Vec<_>>();
+    assert_eq!(values[1], (Cow::Borrowed("duration"), "1"));
+    assert_eq!(values[2], (Cow::Borrowed("event"), "EXCP"));
+    assert_eq!(values[3], (Cow::Borrowed("Descr"), "a;b, ||c||\r\nd"));
+    assert_eq!(values[4], (Cow::Borrowed("process"), "rphost"));
+    assert!(fields.is_complete());
+
+    let fields = Fields::with_syntax("00:00;EXCP;2;Usr='a,b'".to_string(), syntax);
+    let values = fields.iter().collect::<Vec<_>>();
+    assert_eq!(values[1], (Cow::Borrowed("event"), "EXCP"));
+    assert_eq!(values[2], (Cow::Borrowed("Usr"), "'a,b'"));
+
+    assert_eq!(Syntax::parse(",", "'\""), Ok(Syntax::default()));
+    assert_eq!(
+        Syntax::parse(";;", "'"),
+        Err(SyntaxError::Delimiter(String::from(";;")))
+    );
+    assert_eq!(
+        Syntax::parse("\t", ""),
+        Err(SyntaxError::Quote(String::new()))
+    );
+    assert_eq!(Syntax::parse("=", "'"), Err(SyntaxError::Reserved('=')));
+    assert_eq!(Syntax::parse(";", "';"), Err(SyntaxError::Conflict(';')));
+}
@@ -0,0 +1,150 @@
+use crate::parser::LogString;
+use std::{
+    ops::Index,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock, RwLock,
+    },
+};
+
+/// Количество записей в одном куске.
+const CHUNK: usize = 4096;
+
+/// Кусок записей фиксированной емкости `CHUNK`. Ячейки заполняются один раз.
+type Chunk = Arc<[OnceLock<LogString>]>;
+
+/// Загруженные записи. Хранятся кусками по `CHUNK` записей, которые заполняются на месте:
+/// поток загрузки записывает новые ячейки и публикует только количество записей,
+/// а читатели работают со снимком и не ждут загрузку.
+#[derive(Default)]
+pub(super) struct Lines {
+    /// Список кусков меняется только при добавлении нового куска.
+    chunks: RwLock<Arc<Vec<Chunk>>>,
+    len: AtomicUsize,
+}
+
+/// Записи, загруженные к моменту `Lines::snapshot`.
+#[derive(Clone, Default)]
+pub(super) struct Snapshot {
+    chunks: Arc<Vec<Chunk>>,
+    len: usize,
+}
+
+impl Lines {
+    pub fn snapshot(&self) -> Snapshot {
+        // Количество читается до списка кусков: куски для опубликованных записей уже в списке
+        let len = self.len.load(Ordering::Acquire);
+        Snapshot {
+            chunks: self.chunks.read().unwrap().clone(),
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, row: usize) -> Option<LogString> {
+        self.snapshot().get(row).cloned()
+    }
+
+    /// Добавляет записи `lines`. Вызывается только из потока загрузки.
+    pub fn append(&self, lines: &mut Vec<LogString>) {
+        let mut len = self.len.load(Ordering::Relaxed);
+        let mut chunks = self.chunks.read().unwrap().clone();
+        for line in lines.drain(..) {
+            if len / CHUNK == chunks.len() {
+                let chunk = (0..CHUNK).map(|_| OnceLock::new()).collect::<Chunk>();
+                let mut lock = self.chunks.write().unwrap();
+                Arc::make_mut(&mut lock).push(chunk);
+                chunks = lock.clone();
+            }
+            let _ = chunks[len / CHUNK][len % CHUNK].set(line);
+            len += 1;
+        }
+        self.len.store(len, Ordering::Release);
+    }
+
+    /// Примерный объем записей в байтах.
+    pub fn bytes(&self) -> usize {
+        self.chunks.read().unwrap().len() * CHUNK * std::mem::size_of::<OnceLock<LogString>>()
+    }
+}
+
+impl Snapshot {
+    pub fn get(&self, row: usize) -> Option<&LogString> {
+        match row < self.len {
+            true => self.chunks[row / CHUNK][row % CHUNK].get(),
+            false => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LogString> {
+        (0..self.len).filter_map(|row| self.get(row))
+    }
+}
+
+impl Index<usize> for Snapshot {
+    type Output = LogString;
+
+    fn index(&self, row: usize) -> &Self::Output {
+        self.get(row).expect("row is not loaded")
+    }
+}
+
+#[test]
+fn test_snapshot_is_not_changed_by_append() {
+    let time = chrono::NaiveDate::from_ymd_opt(2022, 8, 2)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap();
+    let records = |from: usize, count: usize| {
+        (from..from + count)
+            .map(|begin| LogString::new(0, time, begin as u64, 1))
+            .collect::<Vec<_>>()
+    };
+
+    let lines = Lines::default();
+    lines.append(&mut records(0, CHUNK - 1));
+    let before = lines.snapshot();
+    lines.append(&mut records(CHUNK - 1, CHUNK + 2));
+
+    // Прежний снимок не видит новых записей, новый видит все по порядку
+    assert_eq!(before.iter().count(), CHUNK - 1);
+    assert!(before.get(CHUNK - 1).is_none());
+    let after = lines.snapshot();
+    assert_eq!(after.iter().count(), 2 * CHUNK + 1);
+    assert!(after
+        .iter()
+        .enumerate()
+        .all(|(row, line)| line.begin() == row as u64 && after[row].begin() == row as u64));
+    assert_eq!(
+        lines.get(2 * CHUNK).map(|line| line.begin()),
+        Some(2 * CHUNK as u64)
+    );
+}
+
+#[test]
+fn test_single_appends_are_linear() {
+    use std::time::{Duration, Instant};
+
+    let time = chrono::NaiveDate::from_ymd_opt(2022, 8, 2)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap();
+    let lines = Lines::default();
+
+    // Парсер обычно отдает по одной записи: добавление не копирует уже загруженные записи.
+    // С копированием неполного куска на каждое добавление это заняло бы десятки секунд
+    let started = Instant::now();
+    for begin in 0..200_000 {
+        lines.append(&mut vec![LogString::new(0, time, begin, 1)]);
+    }
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(lines.len(), 200_000);
+    assert_eq!(lines.get(199_999).map(|line| line.begin()), Some(199_999));
+}
@@ -1,26 +1,233 @@
 use crate::{
-    parser::LogString,
-    ui::{index::ModelIndex, model::DataModel},
+    parser::{
+        buffers::{buffer_group, buffer_root, buffers_count, release_group},
+        cache::FieldsCache,
+        lines::Lines,
+        LogString,
+    },
+    ui::{
+        index::ModelIndex,
+        model::{DataModel, Density, Progress},
+    },
+    util::{format_duration, single_line, LogZone},
 };
+use chrono::NaiveDateTime;
+use indexmap::IndexMap;
 use std::{
     borrow::Cow,
-    sync::{mpsc::Receiver, Arc, RwLock},
-};
-
-use crate::parser::{compiler::ParseError, value::Value, Compiler, FieldMap, Fields, Query};
-use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
     sync::{
-        mpsc::{Sender, TryRecvError},
-        Mutex, RwLockReadGuard, RwLockWriteGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::Receiver,
+        Arc, RwLock,
     },
-    time::Duration,
 };
 
+use crate::parser::{
+    compiler::ParseError, value::Value, Compiler, DerivedField, FieldMap, Fields, Profile, Query,
+    PARSE_OK_FIELD, RECORD_FIELDS, UNAVAILABLE,
+};
+use std::sync::{
+    mpsc::{Sender, TryRecvError},
+    Condvar, Mutex, RwLockReadGuard, RwLockWriteGuard,
+};
+
+/// Максимальное количество разобранных строк в кэше.
+const CACHE_CAPACITY: usize = 16 * 1024;
+
+/// Максимальное количество разобранных строк в кэше отображения.
+const VIEW_CACHE_CAPACITY: usize = 1024;
+
+/// Состояние загрузки и фильтрации коллекции.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// Файлы еще читаются или фильтр еще проверяет строки.
+    Loading,
+    /// Загрузка завершена, но ни одной записи не найдено.
+    NoFiles,
+    /// Записи есть, но ни одна не подходит под фильтр.
+    NoMatches,
+    Ready,
+}
+
+/// Основные колонки таблицы, если `--columns` не указан.
+pub const DEFAULT_COLUMNS: [&str; 5] = ["time", "event", "duration", "process", "OSThread"];
+
+/// По скольким первым записям `Columns::Auto` выбирает колонки.
+const COLUMNS_SAMPLE: usize = 1000;
+
+/// Сколько пришедших записей поток загрузки публикует за раз.
+const INGEST_BATCH: usize = 4096;
+
+/// Сколько проверенных строк поток сканирования добавляет под одной блокировкой записи.
+/// Смена фильтра прерывает пачку, не дожидаясь ее конца.
+const SCAN_BATCH: usize = 256;
+
+/// Сколько колонок выбирает `Columns::Auto`.
+const AUTO_COLUMNS: usize = 5;
+
+/// Основные колонки таблицы (`--columns`). Виртуальные поля и каталог записи
+/// показываются после них.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Columns {
+    Fixed(Vec<String>),
+    /// `time`, `event` и самые частые поля первых `COLUMNS_SAMPLE` записей.
+    /// До их загрузки показываются `DEFAULT_COLUMNS`.
+    Auto,
+}
+
+impl Columns {
+    /// `auto`, список полей вместо основных (`time,event,Usr`)
+    /// или с `+` - дополнительно к основным (`+Usr,__file`).
+    pub fn parse(value: &str) -> Columns {
+        let split = |value: &str| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        match value.trim() {
+            "auto" => Columns::Auto,
+            value => match value.strip_prefix('+') {
+                Some(extra) => {
+                    let mut columns = Columns::default_names();
+                    columns.extend(split(extra));
+                    Columns::Fixed(columns)
+                }
+                None => Columns::Fixed(split(value)),
+            },
+        }
+    }
+
+    fn default_names() -> Vec<String> {
+        DEFAULT_COLUMNS
+            .iter()
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Columns::Fixed(Columns::default_names())
+    }
+}
+
+/// Поля, по которым сравниваются соседние записи при схлопывании повторов.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DedupFields {
+    /// Все поля, кроме `time` и `duration`: повторы одного вызова отличаются
+    /// только временем и длительностью.
+    AllExceptTiming,
+    Only(Vec<String>),
+}
+
+/// Счетчик изменений коллекции. Поток сканирования ждет его увеличения вместо опроса,
+/// интерфейс по нему решает, нужно ли перерисовывать экран.
+#[derive(Default)]
+struct Changes {
+    version: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl Changes {
+    fn notify(&self) {
+        *self.version.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+
+    fn version(&self) -> usize {
+        *self.version.lock().unwrap()
+    }
+
+    /// Ждет изменения после версии `seen`.
+    fn wait(&self, seen: usize) {
+        let version = self.version.lock().unwrap();
+        drop(self.changed.wait_while(version, |version| *version == seen));
+    }
+}
+
+/// Добавляет в `groups` группы файлов записей `lines`.
+fn add_groups(groups: &mut Vec<usize>, lines: &[LogString]) {
+    let mut last = None;
+    for line in lines {
+        // Записи одного файла идут подряд, группа узнается один раз на файл
+        if last == Some(line.buffer) {
+            continue;
+        }
+        last = Some(line.buffer);
+        if let Some(group) = buffer_group(line.buffer) {
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+    }
+}
+
+/// Псевдополя из `RECORD_FIELDS`, которые проверяет запрос `query`.
+fn record_fields(query: &Query) -> Vec<&'static str> {
+    let fields = query.fields();
+    RECORD_FIELDS
+        .into_iter()
+        .filter(|name| fields.contains(name))
+        .collect()
+}
+
+/// Закрывает файлы записей `lines`, которые не попадут в коллекцию.
+fn release_groups(lines: &[LogString]) {
+    let mut groups = Vec::new();
+    add_groups(&mut groups, lines);
+    groups.into_iter().for_each(release_group);
+}
+
 struct Inner {
-    lines: Vec<LogString>,
+    lines: Lines,
+    cache: Mutex<FieldsCache>,
+    /// Отдельный кэш для таблицы, чтобы сканирование фильтром не вытесняло видимые строки.
+    view_cache: Mutex<FieldsCache>,
     filter: Option<Query>,
+    /// Псевдополя из `RECORD_FIELDS`, которые проверяет `filter`.
+    filter_fields: Vec<&'static str>,
     mapping: Vec<usize>,
+    /// Количество схлопнутых подряд идущих записей для каждой строки `mapping`.
+    repeats: Vec<usize>,
+    dedup: Option<DedupFields>,
     notifier: Mutex<Sender<Option<Query>>>,
+    changes: Arc<Changes>,
+    /// Количество отправленных фильтров. Если поток сканирования получил меньше,
+    /// текущая проверка устарела и прерывается.
+    generation: AtomicUsize,
+    /// Номер последнего фильтра, который поток сканирования начал проверять по всем строкам
+    /// или полностью применил к `mapping`. Пока он меньше `generation`, загрузка не завершена.
+    applied: AtomicUsize,
+    ingesting: AtomicBool,
+    /// Коллекция заменена новой (`LogCollection::reload`): потоки загрузки и сканирования завершаются.
+    stopped: AtomicBool,
+    /// Группы файлов, из которых загружены записи. Закрываются в `stop`.
+    groups: Mutex<Vec<usize>>,
+    max_lines: Option<usize>,
+    /// Загрузка остановлена по ограничению `max_lines`.
+    truncated: AtomicBool,
+    scanned: AtomicUsize,
+    time_format: Option<String>,
+    /// Показывать в колонке `time` час файла и время из записи как есть (`--raw-time`).
+    raw_time: bool,
+    human_durations: bool,
+    event_labels: HashMap<String, String>,
+    /// Виртуальные поля `--derive`, показываются колонками после основных.
+    derived: Vec<DerivedField>,
+    /// Каталоги `-d`. Если их несколько, у записей есть поле `dir` с каталогом записи.
+    roots: Vec<String>,
+    /// Основные колонки `--columns`.
+    columns: Columns,
+    source: String,
+    /// Часовой пояс логов: от него отсчитывается `now` в фильтрах.
+    zone: LogZone,
 }
 
 impl Inner {
@@ -31,19 +238,223 @@ impl Inner {
         };
 
         if let Some(filter) = &self.filter {
-            let mut map = FieldMap::new();
-            let iter = Fields::new(line.to_string());
-            while let Some((k, v)) = iter.parse_field() {
-                map.insert(k, Value::from(v))
-            }
-            return filter.accept(&map);
+            return filter.accept_line(&|| line.to_string(), &|| {
+                self.query_fields(row, &line, &self.filter_fields)
+            });
         }
 
         // Когда фильтр не указан, то строку принимаем всегда
         true
     }
+
+    fn fields(&self, row: usize, line: &LogString) -> Arc<FieldMap<'static>> {
+        if let Some(fields) = self.cache.lock().unwrap().get(row) {
+            return fields;
+        }
+
+        let mut map = FieldMap::new();
+        let complete = match line.read() {
+            Ok(text) => {
+                let iter = Fields::new(text);
+                while let Some((k, v)) = iter.parse_field() {
+                    match k.as_ref() {
+                        "time" => map.insert("time", Value::DateTime(line.time)),
+                        _ => map.insert(k.to_string(), Value::from(v.to_string())),
+                    }
+                }
+                iter.is_complete()
+            }
+            // Файл обрезан или заменен: запись остается в таблице с пометкой
+            Err(_) => {
+                map.insert("time", Value::DateTime(line.time));
+                map.insert("event", Value::from(UNAVAILABLE));
+                false
+            }
+        };
+        map.insert(
+            PARSE_OK_FIELD,
+            Value::from(if complete { "true" } else { "false" }),
+        );
+        if self.roots.len() > 1 {
+            if let Some(root) = self.roots.get(buffer_root(line.buffer)) {
+                map.insert(DIR_FIELD, Value::from(root.clone()));
+            }
+        }
+        // Виртуальные поля вычисляются при разборе строки и кэшируются вместе с ней
+        for derived in &self.derived {
+            if RECORD_FIELDS.contains(&derived.source.as_str())
+                && map.get(&derived.source).is_none()
+            {
+                if let Some(value) = line.get(&derived.source) {
+                    map.insert(derived.source.clone(), value);
+                }
+            }
+            if let Some(value) = derived.extract(&map) {
+                map.insert(derived.name.clone(), value);
+            }
+        }
+
+        let map = Arc::new(map);
+        self.cache.lock().unwrap().insert(row, map.clone());
+        map
+    }
+
+    /// Поля строки для проверки запросом: к разобранным полям добавляются псевдополя `names`
+    /// из `RECORD_FIELDS`, которые проверяет запрос. В кэш они не попадают.
+    fn query_fields(
+        &self,
+        row: usize,
+        line: &LogString,
+        names: &[&'static str],
+    ) -> Arc<FieldMap<'static>> {
+        let fields = self.fields(row, line);
+        if names.is_empty() {
+            return fields;
+        }
+
+        let mut map = FieldMap::clone(&fields);
+        for &name in names {
+            if let Some(value) = line.get(name) {
+                map.insert(name, value);
+            }
+        }
+        Arc::new(map)
+    }
+
+    /// Имена колонок таблицы: основные, виртуальные поля и каталог записи,
+    /// если каталогов несколько.
+    fn column_names(&self) -> impl Iterator<Item = &str> {
+        let (fixed, default) = match &self.columns {
+            Columns::Fixed(columns) => (columns.as_slice(), &[][..]),
+            Columns::Auto => (&[][..], &DEFAULT_COLUMNS[..]),
+        };
+
+        fixed
+            .iter()
+            .map(String::as_str)
+            .chain(default.iter().copied())
+            .chain(self.derived.iter().map(|derived| derived.name.as_str()))
+            .chain((self.roots.len() > 1).then_some(DIR_FIELD))
+    }
+
+    /// Колонки вместо `Columns::Auto` по частоте полей, когда загружено
+    /// `COLUMNS_SAMPLE` записей или загрузка завершена.
+    fn auto_columns(&self) -> Option<Vec<String>> {
+        let ready = self.lines.len() >= COLUMNS_SAMPLE
+            || (!self.ingesting.load(Ordering::Relaxed) && !self.lines.is_empty());
+        if self.columns != Columns::Auto || !ready {
+            return None;
+        }
+
+        let skipped = |name: &str| {
+            name.starts_with("__")
+                || name == DIR_FIELD
+                || self.derived.iter().any(|derived| derived.name == name)
+        };
+        // IndexMap сохраняет порядок первого появления, им и разрешаются равные частоты
+        let mut counts = IndexMap::<String, usize>::new();
+        for (row, line) in self
+            .lines
+            .snapshot()
+            .iter()
+            .enumerate()
+            .take(COLUMNS_SAMPLE)
+        {
+            for name in self.fields(row, line).keys() {
+                if !skipped(name) {
+                    *counts.entry(name.to_string()).or_default() += 1;
+                }
+            }
+        }
+
+        let mut counts = counts
+            .into_iter()
+            .filter(|(name, _)| name != "time" && name != "event")
+            .collect::<Vec<_>>();
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        let mut columns = vec![String::from("time"), String::from("event")];
+        columns.extend(
+            counts
+                .into_iter()
+                .map(|(name, _)| name)
+                .take(AUTO_COLUMNS - columns.len()),
+        );
+        Some(columns)
+    }
+
+    /// Является ли запись `row` повтором записи `prev` по полям `dedup`.
+    fn is_repeat(&self, prev: usize, row: usize) -> bool {
+        let dedup = match &self.dedup {
+            Some(dedup) => dedup,
+            None => return false,
+        };
+
+        let lines = self.lines.snapshot();
+        let (prev_line, row_line) = (&lines[prev], &lines[row]);
+        let (prev, row) = (self.fields(prev, prev_line), self.fields(row, row_line));
+        match dedup {
+            DedupFields::AllExceptTiming => {
+                let compared = |(key, _): &(&str, &Value)| !["time", "duration"].contains(key);
+                // Записи разных файлов повторами не считаются
+                prev_line.buffer == row_line.buffer
+                    && prev.iter().filter(compared).eq(row.iter().filter(compared))
+            }
+            DedupFields::Only(names) => names.iter().all(|name| {
+                if RECORD_FIELDS.contains(&name.as_str()) {
+                    return prev_line.get(name) == row_line.get(name);
+                }
+                match (prev.get(name), row.get(name)) {
+                    (Some(a), Some(b)) => a.iter().eq(b.iter()),
+                    (a, b) => a.is_none() && b.is_none(),
+                }
+            }),
+        }
+    }
+
+    /// Поля строки для отображения: файл читается только при промахе обоих кэшей.
+    fn view_fields(&self, row: usize, line: &LogString) -> Arc<FieldMap<'static>> {
+        if let Some(fields) = self.view_cache.lock().unwrap().get(row) {
+            return fields;
+        }
+
+        let fields = self.fields(row, line);
+        self.view_cache.lock().unwrap().insert(row, fields.clone());
+        fields
+    }
+
+    fn field(&self, row: usize, name: &str) -> Value<'static> {
+        let line = self.lines.get(row).unwrap();
+        match name {
+            "time" => line.get(name).unwrap_or_default(),
+            _ if RECORD_FIELDS.contains(&name) => line.get(name).unwrap_or_default(),
+            _ => self
+                .view_fields(row, &line)
+                .get(name)
+                .map(|value| value[0].clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Сведения о загрузке для окна информации.
+pub struct Stats {
+    pub source: String,
+    /// Количество открытых файлов логов.
+    pub files: usize,
+    pub records: usize,
+    /// Количество строк, прошедших фильтр.
+    pub matched: usize,
+    /// Количество разобранных записей в кэшах.
+    pub cached: usize,
+    /// Примерный объем индекса записей в байтах.
+    pub index_bytes: usize,
+    pub filter: Option<Query>,
 }
 
+/// Поле с каталогом записи при чтении нескольких каталогов.
+const DIR_FIELD: &str = "dir";
+
 pub struct LogCollection(Arc<RwLock<Inner>>);
 
 impl Clone for LogCollection {
@@ -54,91 +465,493 @@ impl Clone for LogCollection {
 
 impl LogCollection {
     pub fn new(receiver: Receiver<LogString>) -> LogCollection {
+        LogCollection::with_max_lines(receiver, None)
+    }
+
+    /// Коллекция, которая перестает принимать записи после `max_lines` строк.
+    /// Получатель при этом закрывается, и парсер останавливается.
+    pub fn with_max_lines(receiver: Receiver<LogString>, max_lines: Option<usize>) -> Self {
         let (notifier, rx) = std::sync::mpsc::channel();
         let this = LogCollection(Arc::new(RwLock::new(Inner {
-            lines: vec![],
+            lines: Lines::default(),
+            cache: Mutex::new(FieldsCache::new(CACHE_CAPACITY)),
+            view_cache: Mutex::new(FieldsCache::new(VIEW_CACHE_CAPACITY)),
             filter: None,
+            filter_fields: vec![],
             mapping: vec![],
+            repeats: vec![],
+            dedup: None,
             notifier: Mutex::new(notifier),
+            changes: Arc::new(Changes::default()),
+            generation: AtomicUsize::new(0),
+            applied: AtomicUsize::new(0),
+            ingesting: AtomicBool::new(true),
+            stopped: AtomicBool::new(false),
+            groups: Mutex::new(Vec::new()),
+            max_lines,
+            truncated: AtomicBool::new(false),
+            scanned: AtomicUsize::new(0),
+            time_format: None,
+            raw_time: false,
+            human_durations: false,
+            event_labels: HashMap::new(),
+            derived: Vec::new(),
+            roots: Vec::new(),
+            columns: Columns::default(),
+            source: String::new(),
+            zone: LogZone::Local,
         })));
 
+        let changes = this.inner().changes.clone();
         let this_cloned = this.clone();
+        let ingest_changes = changes.clone();
         std::thread::spawn(move || {
+            let mut pending = Vec::with_capacity(INGEST_BATCH);
             while let Ok(data) = receiver.recv() {
-                this_cloned.inner_mut().lines.push(data);
+                // Уже пришедшие записи публикуются вместе, одним новым списком кусков
+                pending.push(data);
+                pending.extend(receiver.try_iter().take(INGEST_BATCH - 1));
+
+                // Записи добавляются под блокировкой чтения: читатели коллекции не ждут загрузку
+                let read = this_cloned.inner();
+                {
+                    let mut groups = read.groups.lock().unwrap();
+                    // Закрытый получатель останавливает и парсер
+                    if read.stopped.load(Ordering::Relaxed) {
+                        release_groups(&pending);
+                        break;
+                    }
+                    add_groups(&mut groups, &pending);
+                }
+                let room = max_lines.map_or(usize::MAX, |max| max.saturating_sub(read.lines.len()));
+                let truncated = pending.len() > room;
+                pending.truncate(room);
+                read.lines.append(&mut pending);
+                read.truncated.store(truncated, Ordering::Relaxed);
+                drop(read);
+
+                this_cloned.resolve_columns();
+                if truncated {
+                    break;
+                }
+                ingest_changes.notify();
             }
+            drop(receiver);
+            this_cloned
+                .inner()
+                .ingesting
+                .store(false, Ordering::Relaxed);
+            this_cloned.resolve_columns();
+            ingest_changes.notify();
         });
 
         let this_cloned = this.clone();
         std::thread::spawn(move || {
             let mut row = 0;
+            let mut received = 0;
             loop {
-                match rx.try_recv() {
-                    Ok(filter) => {
+                // Версия запоминается до проверок, чтобы не пропустить изменение между ними
+                let seen = changes.version();
+                if this_cloned.inner().stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                // Из нескольких ожидающих фильтров важен только последний
+                let mut next = None;
+                loop {
+                    match rx.try_recv() {
+                        Ok(filter) => {
+                            received += 1;
+                            next = Some(filter);
+                        }
+                        Err(TryRecvError::Disconnected) => return,
+                        Err(TryRecvError::Empty) => break,
+                    }
+                }
+
+                if let Some(filter) = next {
+                    let narrowed = {
                         let mut write = this_cloned.inner_mut();
+                        // Схлопнутые повторы могут не пройти новый фильтр по-разному,
+                        // поэтому при дедупликации всегда сканируем заново.
+                        let refinement = match (&filter, &write.filter) {
+                            (Some(new), Some(old)) => {
+                                write.dedup.is_none() && new.is_refinement_of(old)
+                            }
+                            _ => false,
+                        };
+                        write.filter_fields = filter.as_ref().map_or(vec![], record_fields);
                         write.filter = filter;
-                        write.mapping.clear();
-                        row = 0;
-                    }
-                    Err(TryRecvError::Disconnected) => {
-                        break;
+
+                        // Уточненный фильтр может принять только уже принятые строки,
+                        // поэтому перепроверяем лишь текущий mapping.
+                        if refinement {
+                            Some(write.mapping.clone())
+                        } else {
+                            write.mapping.clear();
+                            write.repeats.clear();
+                            write.scanned.store(0, Ordering::Relaxed);
+                            write.applied.store(received, Ordering::Relaxed);
+                            row = 0;
+                            None
+                        }
+                    };
+
+                    if let Some(mapping) = narrowed {
+                        // Прерванная проверка оставляет прежний mapping: он шире нового,
+                        // поэтому следующий уточненный фильтр по-прежнему можно применить к нему.
+                        let mut accepted = Vec::with_capacity(mapping.len());
+                        let complete = mapping.into_iter().all(|row| {
+                            let read = this_cloned.inner();
+                            if read.generation.load(Ordering::Relaxed) != received
+                                || read.stopped.load(Ordering::Relaxed)
+                            {
+                                return false;
+                            }
+                            if read.accept_row(row) {
+                                accepted.push(row);
+                            }
+                            true
+                        });
+
+                        if complete {
+                            let mut write = this_cloned.inner_mut();
+                            write.repeats = vec![1; accepted.len()];
+                            write.mapping = accepted;
+                            write.applied.store(received, Ordering::Relaxed);
+                            drop(write);
+                            changes.notify();
+                        }
                     }
-                    _ => {}
                 }
 
-                let rows = this_cloned.inner().lines.len();
-                if row >= rows {
-                    std::thread::sleep(Duration::from_millis(100));
+                let read = this_cloned.inner();
+                let (total, mut last) = (read.lines.len(), read.mapping.last().copied());
+                drop(read);
+                if row >= total {
+                    changes.wait(seen);
                     continue;
                 }
 
-                let accept = this_cloned.inner().accept_row(row);
-                if accept {
-                    this_cloned.inner_mut().mapping.push(row)
+                // Проверка строки может читать файл, поэтому блокировка чтения берется
+                // на каждую строку, а принятые строки добавляются под одной блокировкой записи
+                let end = total.min(row + SCAN_BATCH);
+                let mut accepted = vec![];
+                let start = row;
+                while row < end {
+                    let read = this_cloned.inner();
+                    if read.generation.load(Ordering::Relaxed) != received {
+                        break;
+                    }
+                    if read.accept_row(row) {
+                        let repeat = last.is_some_and(|prev| read.is_repeat(prev, row));
+                        if !repeat {
+                            last = Some(row);
+                        }
+                        accepted.push((row, repeat));
+                    }
+                    row += 1;
                 }
 
-                row += 1;
+                let mut write = this_cloned.inner_mut();
+                // Пока пачка проверялась, поток загрузки мог только добавить строки.
+                // Если пришел новый фильтр, принятые строки перепроверятся или сбросятся
+                // при его применении
+                for (accepted, repeat) in accepted {
+                    match repeat {
+                        true => *write.repeats.last_mut().unwrap() += 1,
+                        false => {
+                            write.mapping.push(accepted);
+                            write.repeats.push(1);
+                        }
+                    }
+                }
+                write.scanned.store(row, Ordering::Relaxed);
+                drop(write);
+                if row > start {
+                    changes.notify();
+                }
             }
         });
 
         this
     }
 
-    pub fn set_filter(&self, filter: String) -> Result<(), ParseError> {
+    /// Новая коллекция записей из `receiver` с теми же настройками и фильтром.
+    /// Потоки текущей коллекции останавливаются, ее файлы закрываются: поток загрузки
+    /// закрывает свой получатель на следующей записи, и парсер прекращает чтение.
+    pub fn reload(&self, receiver: Receiver<LogString>) -> LogCollection {
+        let read = self.inner();
+        let collection = LogCollection::with_max_lines(receiver, read.max_lines);
+        let mut write = collection.inner_mut();
+        write.time_format = read.time_format.clone();
+        write.raw_time = read.raw_time;
+        write.human_durations = read.human_durations;
+        write.event_labels = read.event_labels.clone();
+        write.derived = read.derived.clone();
+        write.roots = read.roots.clone();
+        write.columns = read.columns.clone();
+        write.source = read.source.clone();
+        write.zone = read.zone;
+        write.dedup = read.dedup.clone();
+        drop(write);
+        collection.send_filter(read.filter.clone());
+        drop(read);
+
+        self.stop();
+        collection
+    }
+
+    /// Останавливает потоки загрузки и сканирования.
+    pub fn stop(&self) {
+        let read = self.inner();
+        read.stopped.store(true, Ordering::Relaxed);
+        for group in read.groups.lock().unwrap().drain(..) {
+            release_group(group);
+        }
+        read.changes.notify();
+    }
+
+    pub fn set_filter(&self, filter: String) -> Result<Option<Query>, ParseError> {
         if filter.trim().is_empty() {
-            self.inner_mut()
-                .notifier
-                .lock()
-                .unwrap()
-                .send(None)
-                .unwrap();
-            return Ok(());
+            self.set_query(None);
+            return Ok(None);
         }
 
+        let zone = self.inner().zone;
+        let filter = Compiler::with_zone(zone).compile(filter.as_str())?;
+        self.set_query(Some(filter.clone()));
+        Ok(Some(filter))
+    }
+
+    /// Устанавливает уже скомпилированный фильтр (например, объединение нескольких строк поиска).
+    pub fn set_query(&self, query: Option<Query>) {
         let current = self.inner().filter.clone();
-        match Compiler::new().compile(filter.as_str()) {
-            Ok(filter) => {
-                if current.is_none() || current.unwrap() != filter {
-                    self.inner_mut()
-                        .notifier
-                        .lock()
-                        .unwrap()
-                        .send(Some(filter))
-                        .unwrap();
-                }
-
-                Ok(())
-            }
-            Err(e) => Err(e),
+        match (current, query) {
+            (Some(current), Some(query)) if current == query => {}
+            (_, query) => self.send_filter(query),
         }
     }
 
     pub fn line(&self, row: usize) -> Option<LogString> {
         let this = self.inner();
+        this.mapping.get(row).and_then(|i| this.lines.get(*i))
+    }
+
+    /// Формат (strftime) отображения времени в таблице.
+    pub fn set_time_format(&self, format: Option<String>) {
+        self.inner_mut().time_format = format;
+    }
+
+    /// Показывать время как `2025-01-15 14h 05:12.345000`: час, полученный из имени файла,
+    /// и время из записи без изменений. Помогает заметить файл с неверным часом в имени.
+    pub fn set_raw_time(&self, raw: bool) {
+        self.inner_mut().raw_time = raw;
+    }
+
+    /// Показывать длительность в колонке `duration` как `4.5s`, `250ms`, `900µs`.
+    /// Фильтры по-прежнему сравнивают исходное число микросекунд.
+    pub fn set_human_durations(&self, human: bool) {
+        self.inner_mut().human_durations = human;
+    }
+
+    /// Подписи событий для колонки `event`. Фильтры по-прежнему
+    /// сравниваются с исходным кодом события.
+    pub fn set_event_labels(&self, labels: HashMap<String, String>) {
+        self.inner_mut().event_labels = labels;
+    }
+
+    /// Задает виртуальные поля. Разобранные ранее строки разбираются заново.
+    pub fn set_derived(&self, derived: Vec<DerivedField>) {
+        let mut this = self.inner_mut();
+        this.derived = derived;
+        this.cache.lock().unwrap().clear();
+        this.view_cache.lock().unwrap().clear();
+    }
+
+    /// Каталоги, из которых читаются записи, в порядке номеров `LogParser::parse_dirs`.
+    pub fn set_roots(&self, roots: Vec<String>) {
+        let mut this = self.inner_mut();
+        this.roots = roots;
+        this.cache.lock().unwrap().clear();
+        this.view_cache.lock().unwrap().clear();
+    }
+
+    /// Основные колонки таблицы, например `Columns::parse("+__file")`.
+    pub fn set_columns(&self, columns: Columns) {
+        self.inner_mut().columns = columns;
+        // Записи могли загрузиться раньше, чем колонки были заданы
+        self.resolve_columns();
+    }
+
+    /// Заменяет `Columns::Auto` колонками по частоте полей. Записи разбираются
+    /// под блокировкой чтения, выбранные колонки больше не меняются.
+    fn resolve_columns(&self) {
+        let columns = match self.inner().auto_columns() {
+            Some(columns) => columns,
+            None => return,
+        };
+
+        let mut write = self.inner_mut();
+        if write.columns == Columns::Auto {
+            write.columns = Columns::Fixed(columns);
+        }
+    }
+
+    /// Схлопывает подряд идущие записи, совпадающие по `fields`. `None` отключает.
+    pub fn set_dedup(&self, fields: Option<DedupFields>) {
+        let mut write = self.inner_mut();
+        write.dedup = fields;
+        // Без старого фильтра новый не считается уточнением, и сканирование начнется заново
+        let filter = write.filter.take();
+        drop(write);
+        self.send_filter(filter);
+    }
+
+    /// Передает фильтр потоку сканирования, не дожидаясь его.
+    /// Счетчик увеличивается до отправки, чтобы текущая проверка прервалась на следующей строке.
+    fn send_filter(&self, filter: Option<Query>) {
+        let read = self.inner();
+        read.generation.fetch_add(1, Ordering::Relaxed);
+        read.notifier.lock().unwrap().send(filter).unwrap();
+        read.changes.notify();
+    }
+
+    /// Номер изменения коллекции: растет при загрузке, сканировании и смене фильтра.
+    /// Если он не изменился, перерисовывать таблицу не нужно.
+    pub fn version(&self) -> usize {
+        self.inner().changes.version()
+    }
+
+    /// Часовой пояс логов (`--tz`), в котором отсчитывается `now` в фильтрах.
+    pub fn set_zone(&self, zone: LogZone) {
+        self.inner_mut().zone = zone;
+    }
+
+    pub fn zone(&self) -> LogZone {
+        self.inner().zone
+    }
+
+    /// Каталог, из которого читаются логи. Используется в пояснениях к пустой таблице.
+    pub fn set_source<T: Into<String>>(&self, source: T) {
+        self.inner_mut().source = source.into();
+    }
+
+    pub fn status(&self) -> Status {
+        if self.progress().is_some() {
+            return Status::Loading;
+        }
+
+        let this = self.inner();
+        match (this.lines.is_empty(), this.mapping.is_empty()) {
+            (true, _) => Status::NoFiles,
+            (false, true) => Status::NoMatches,
+            _ => Status::Ready,
+        }
+    }
+
+    /// Дожидается окончания загрузки и проверяет `query` на всех записях,
+    /// считая проверки условий, чтения полей и регулярные выражения (`--explain`).
+    pub fn profile(&self, query: &Query) -> Profile {
+        let changes = self.inner().changes.clone();
+        loop {
+            let seen = changes.version();
+            if !self.inner().ingesting.load(Ordering::Relaxed) {
+                break;
+            }
+            changes.wait(seen);
+        }
+
+        let this = self.inner();
+        let mut profile = Profile::new(query);
+        let names = record_fields(query);
+        for (row, line) in this.lines.snapshot().iter().enumerate() {
+            let accepted = query.accept_line_with(
+                &|| line.to_string(),
+                &|| this.query_fields(row, line, &names),
+                &mut profile,
+            );
+            profile.record(accepted);
+        }
+        profile
+    }
+
+    /// Время самой поздней загруженной записи.
+    pub fn last_time(&self) -> Option<NaiveDateTime> {
+        self.inner()
+            .lines
+            .snapshot()
+            .iter()
+            .map(LogString::time)
+            .max()
+    }
+
+    pub fn stats(&self) -> Stats {
+        let this = self.inner();
+        let cached = this.cache.lock().unwrap().len() + this.view_cache.lock().unwrap().len();
+        let files = buffers_count(&this.groups.lock().unwrap());
+        Stats {
+            source: this.source.clone(),
+            files,
+            records: this.lines.len(),
+            matched: this.mapping.len(),
+            cached,
+            index_bytes: this.lines.bytes()
+                + (this.mapping.capacity() + this.repeats.capacity())
+                    * std::mem::size_of::<usize>(),
+            filter: this.filter.clone(),
+        }
+    }
+
+    /// Имена полей первых `sample` записей с первым встреченным значением, по алфавиту.
+    pub fn field_names(&self, sample: usize) -> Vec<(String, String)> {
+        let this = self.inner();
+        let mut names = BTreeMap::new();
+        for line in this.lines.snapshot().iter().take(sample) {
+            let fields = line.fields();
+            for (key, value) in fields.iter() {
+                names
+                    .entry(key.to_string())
+                    .or_insert_with(|| single_line(value));
+            }
+        }
+        names.into_iter().collect()
+    }
+
+    /// Имена полей первых `sample` записей вместе с псевдополями и виртуальными полями.
+    pub fn known_fields(&self, sample: usize) -> Vec<String> {
+        let this = self.inner();
+        let mut names = std::collections::BTreeSet::new();
+        for (row, line) in this.lines.snapshot().iter().enumerate().take(sample) {
+            let fields = this.fields(row, line);
+            names.extend(fields.iter().map(|(key, _)| key.to_string()));
+        }
+        // Псевдополя записи есть у каждой записи, хотя в разобранных полях их нет
+        if !names.is_empty() {
+            names.extend(RECORD_FIELDS.map(String::from));
+        }
+        names.into_iter().collect()
+    }
+
+    /// Позиция первой отфильтрованной записи не раньше `time`,
+    /// ограниченная последней строкой.
+    pub fn find_time(&self, time: NaiveDateTime) -> usize {
+        let this = self.inner();
+        let lines = this.lines.snapshot();
+        // Записи поступают от парсера в порядке возрастания времени
+        let row = this
+            .mapping
+            .partition_point(|&line| lines[line].time() < time);
+        row.min(this.mapping.len().saturating_sub(1))
+    }
+
+    /// Позиция последней отфильтрованной записи не позже `time`, ограниченная первой строкой.
+    pub fn find_time_before(&self, time: NaiveDateTime) -> usize {
+        let this = self.inner();
+        let lines = this.lines.snapshot();
         this.mapping
-            .get(row)
-            .and_then(|i| this.lines.get(*i))
-            .cloned()
+            .partition_point(|&line| lines[line].time() <= time)
+            .saturating_sub(1)
     }
 
     fn inner(&self) -> RwLockReadGuard<'_, Inner> {
@@ -156,72 +969,763 @@ impl DataModel for LogCollection {
     }
 
     fn cols(&self) -> usize {
-        5
+        self.inner().column_names().count()
     }
 
-    fn header_index(&self, name: &str) -> Option<usize> {
-        match name {
-            "time" => Some(0),
-            "event" => Some(1),
-            "duration" => Some(2),
-            "process" => Some(3),
-            "OSThread" => Some(4),
-            _ => None,
+    fn display(&self, index: ModelIndex) -> Option<String> {
+        let (row, column) = (index.row(), index.column());
+        let value = self.data(index)?;
+        let this = self.inner();
+        let name = this.column_names().nth(column);
+        match (&value, &this.time_format) {
+            (Value::DateTime(time), _) if name == Some("time") && this.raw_time => {
+                let raw = this
+                    .mapping
+                    .get(row)
+                    .and_then(|&line| this.lines.get(line)?.raw_time())
+                    .unwrap_or_else(|| UNAVAILABLE.to_string());
+                Some(format!("{} {}", time.format("%Y-%m-%d %Hh"), raw))
+            }
+            (Value::DateTime(time), Some(format)) => Some(time.format(format).to_string()),
+            _ if name == Some("duration") && this.human_durations => Some(
+                value
+                    .as_number()
+                    .map(format_duration)
+                    .unwrap_or_else(|| value.to_string()),
+            ),
+            _ if name == Some("event") => {
+                let event = value.to_string();
+                let event = this.event_labels.get(&event).cloned().unwrap_or(event);
+                match this.repeats.get(row) {
+                    Some(&count) if count > 1 => Some(format!("{} ×{}", event, count)),
+                    _ => Some(event),
+                }
+            }
+            _ => Some(value.to_string()),
         }
     }
 
-    fn header_data(&self, column: usize) -> Option<Cow<'_, str>> {
-        match column {
-            0 => Some(Cow::Borrowed("time")),
-            1 => Some(Cow::Borrowed("event")),
-            2 => Some(Cow::Borrowed("duration")),
-            3 => Some(Cow::Borrowed("process")),
-            4 => Some(Cow::Borrowed("OSThread")),
-            _ => None,
+    fn row_id(&self, row: usize) -> Option<usize> {
+        self.inner().mapping.get(row).cloned()
+    }
+
+    fn find_row(&self, id: usize) -> Option<usize> {
+        // mapping всегда упорядочен по возрастанию номеров строк
+        self.inner().mapping.binary_search(&id).ok()
+    }
+
+    fn progress(&self) -> Option<Progress> {
+        let this = self.inner();
+        let progress = Progress {
+            scanned: this.scanned.load(Ordering::Relaxed),
+            total: this.lines.len(),
+        };
+
+        let pending =
+            this.applied.load(Ordering::Relaxed) != this.generation.load(Ordering::Relaxed);
+        match this.ingesting.load(Ordering::Relaxed) || pending || progress.scanned < progress.total
+        {
+            true => Some(progress),
+            false => None,
+        }
+    }
+
+    fn density(&self, buckets: usize) -> Option<Density> {
+        let this = self.inner();
+        let lines = this.lines.snapshot();
+        let times = this.mapping.iter().enumerate().map(|(row, &line)| {
+            let repeats = this.repeats.get(row).copied().unwrap_or(1);
+            (lines[line].time(), repeats as u64)
+        });
+        Density::new(times, buckets)
+    }
+
+    fn truncated(&self) -> bool {
+        self.inner().truncated.load(Ordering::Relaxed)
+    }
+
+    fn has_markers(&self) -> bool {
+        true
+    }
+
+    fn marker(&self, row: usize) -> Option<char> {
+        let this = self.inner();
+        let line = *this.mapping.get(row)?;
+        let parsed = this
+            .view_fields(line, &this.lines.get(line)?)
+            .get(PARSE_OK_FIELD)
+            .is_some_and(|value| value.to_string() == "true");
+        (!parsed).then_some('!')
+    }
+
+    /// Поиск `/` идет по исходному тексту записи, как `RAW`.
+    fn row_text(&self, row: usize) -> Option<String> {
+        self.line(row).map(|line| line.to_string())
+    }
+
+    fn empty_text(&self) -> Option<String> {
+        match self.status() {
+            Status::Loading => Some(String::from("Loading...")),
+            Status::NoFiles => Some(format!("No .log files found under {}", self.inner().source)),
+            Status::NoMatches => Some(String::from("No rows match the filter")),
+            Status::Ready => None,
         }
     }
 
+    fn header_index(&self, name: &str) -> Option<usize> {
+        self.inner()
+            .column_names()
+            .position(|column| column == name)
+    }
+
+    fn header_data(&self, column: usize) -> Option<Cow<'_, str>> {
+        self.inner()
+            .column_names()
+            .nth(column)
+            .map(|name| Cow::Owned(name.to_string()))
+    }
+
     fn data(&self, index: ModelIndex) -> Option<Value<'static>> {
         let this = self.inner();
-        let line = this.mapping.get(index.row());
-
-        match (line, index.column()) {
-            (Some(&line), 0) => Some(
-                this.lines
-                    .get(line)
-                    .unwrap()
-                    .get("time")
-                    .unwrap_or_default(),
-            ),
-            (Some(&line), 1) => Some(
-                this.lines
-                    .get(line)
-                    .unwrap()
-                    .get("event")
-                    .unwrap_or_default(),
-            ),
-            (Some(&line), 2) => Some(
-                this.lines
-                    .get(line)
-                    .unwrap()
-                    .get("duration")
-                    .unwrap_or_default(),
-            ),
-            (Some(&line), 3) => Some(
-                this.lines
-                    .get(line)
-                    .unwrap()
-                    .get("process")
-                    .unwrap_or_default(),
-            ),
-            (Some(&line), 4) => Some(
-                this.lines
-                    .get(line)
-                    .unwrap()
-                    .get("OSThread")
-                    .unwrap_or_default(),
-            ),
+        let line = *this.mapping.get(index.row())?;
+        let name = this.column_names().nth(index.column())?;
+        Some(this.field(line, name))
+    }
+}
+
+/// Исходный текст отфильтрованных записей одной колонкой, как в `less` (`V` в таблице).
+/// Строки те же, что у коллекции, поэтому выделение переносится между видами.
+pub struct RawLines(Rc<RefCell<LogCollection>>);
+
+impl RawLines {
+    /// Колонка с текстом записи.
+    pub const COLUMN: &'static str = "record";
+
+    pub fn new(collection: Rc<RefCell<LogCollection>>) -> Self {
+        RawLines(collection)
+    }
+}
+
+impl DataModel for RawLines {
+    fn rows(&self) -> usize {
+        self.0.borrow().rows()
+    }
+
+    fn cols(&self) -> usize {
+        1
+    }
+
+    fn header_index(&self, name: &str) -> Option<usize> {
+        (name == RawLines::COLUMN).then_some(0)
+    }
+
+    fn header_data(&self, column: usize) -> Option<Cow<'_, str>> {
+        (column == 0).then_some(Cow::Borrowed(RawLines::COLUMN))
+    }
+
+    fn data(&self, index: ModelIndex) -> Option<Value<'static>> {
+        match index.column() {
+            // Перевод строки в конце записи не показывается, внутренние - символом ⏎
+            0 => self
+                .row_text(index.row())
+                .map(|text| Value::from(text.trim_end_matches(['\r', '\n']).to_string())),
             _ => None,
         }
     }
+
+    fn row_id(&self, row: usize) -> Option<usize> {
+        self.0.borrow().row_id(row)
+    }
+
+    fn find_row(&self, id: usize) -> Option<usize> {
+        self.0.borrow().find_row(id)
+    }
+
+    fn progress(&self) -> Option<Progress> {
+        self.0.borrow().progress()
+    }
+
+    fn truncated(&self) -> bool {
+        self.0.borrow().truncated()
+    }
+
+    fn has_markers(&self) -> bool {
+        self.0.borrow().has_markers()
+    }
+
+    fn marker(&self, row: usize) -> Option<char> {
+        self.0.borrow().marker(row)
+    }
+
+    fn density(&self, buckets: usize) -> Option<Density> {
+        self.0.borrow().density(buckets)
+    }
+
+    fn row_text(&self, row: usize) -> Option<String> {
+        self.0.borrow().row_text(row)
+    }
+
+    fn empty_text(&self) -> Option<String> {
+        self.0.borrow().empty_text()
+    }
+}
+
+/// Час, к которому относятся записи тестовых файлов.
+#[cfg(test)]
+fn test_hour() -> NaiveDateTime {
+    chrono::NaiveDate::from_ymd_opt(2022, 8, 2)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap()
+}
+
+/// Записывает строки лога `lines` во временный файл и возвращает записи о них
+/// со временем из префикса строки, как их отправляет парсер.
+#[cfg(test)]
+fn test_records(lines: &[&str]) -> (Vec<LogString>, std::path::PathBuf) {
+    use crate::parser::buffers::{add_buffer, new_group};
+    use std::{fs::File, io::BufReader};
+
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "journal1c_test_{}_{}.log",
+        std::process::id(),
+        FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, format!("\u{feff}{}", lines.concat())).unwrap();
+    let buffer = add_buffer(
+        new_group(),
+        BufReader::new(File::open(&path).unwrap()),
+        0,
+        &path,
+    );
+
+    let mut begin = 0;
+    let records = lines
+        .iter()
+        .map(|line| {
+            let time = crate::util::parse_time(test_hour(), line);
+            let record = LogString::new(buffer, time, begin, line.len() as u64);
+            begin += line.len() as u64;
+            record
+        })
+        .collect();
+    (records, path)
+}
+
+/// Коллекция, в которую отправлены записи `lines` из временного файла.
+/// `before_send` настраивает коллекцию до начала загрузки.
+#[cfg(test)]
+fn collection_with(
+    lines: &[&str],
+    before_send: impl FnOnce(&LogCollection),
+) -> (LogCollection, std::path::PathBuf) {
+    let (records, path) = test_records(lines);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let collection = LogCollection::new(receiver);
+    before_send(&collection);
+    records
+        .into_iter()
+        .for_each(|record| sender.send(record).unwrap());
+    (collection, path)
+}
+
+#[cfg(test)]
+fn collection_from(lines: &[&str]) -> (LogCollection, std::path::PathBuf) {
+    collection_with(lines, |_| {})
+}
+
+/// Ждет до 5 секунд, пока не выполнится `cond`. Возвращает последний результат `cond`.
+#[cfg(test)]
+fn wait_until(cond: impl Fn() -> bool) -> bool {
+    for _ in 0..50 {
+        if cond() {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    cond()
+}
+
+#[test]
+fn test_filter_accepts_last_row() {
+    let (collection, path) = collection_from(&[
+        "00:01.000000-0,CALL,1,process=rphost\r\n",
+        "00:02.000000-0,CALL,1,process=rphost\r\n",
+        "00:03.000000-0,EXCP,1,process=rphost\r\n",
+    ]);
+
+    collection
+        .set_filter(r#"WHERE event = "EXCP""#.to_string())
+        .unwrap();
+    wait_until(|| collection.rows() == 1 && collection.progress().is_none());
+
+    assert_eq!(collection.rows(), 1);
+    assert_eq!(collection.status(), Status::Ready);
+    assert_eq!(
+        collection.line(0).and_then(|line| line.get("event")),
+        Some(Value::from("EXCP"))
+    );
+    assert_eq!(collection.row_id(0), Some(2));
+    assert_eq!(collection.find_row(2), Some(0));
+    assert_eq!(collection.find_row(1), None);
+    assert_eq!(collection.find_time(test_hour()), 0);
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_status_without_files() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let collection = LogCollection::new(receiver);
+    drop(sender);
+
+    wait_until(|| collection.status() != Status::Loading);
+    assert_eq!(collection.status(), Status::NoFiles);
+}
+
+#[test]
+fn test_dedup_collapses_repeats() {
+    let (collection, path) = collection_with(
+        &[
+            "00:01.000000-1,CALL,1,Context=a\r\n",
+            "00:02.000000-2,CALL,1,Context=a\r\n",
+            "00:03.000000-3,CALL,1,Context=b\r\n",
+            "00:04.000000-4,CALL,1,Context=a\r\n",
+        ],
+        |collection| collection.set_dedup(Some(DedupFields::AllExceptTiming)),
+    );
+
+    // Первые две записи отличаются только временем и длительностью
+    wait_until(|| collection.status() != Status::Loading);
+    assert_eq!(collection.rows(), 3);
+    let event = collection.header_index("event").unwrap();
+    let events = (0..3)
+        .map(|row| collection.display(ModelIndex::new(row, event)).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(events, ["CALL ×2", "CALL", "CALL"]);
+    assert_eq!(collection.row_id(1), Some(2));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_max_lines_stops_ingest() {
+    use std::sync::mpsc::channel;
+
+    let (sender, receiver) = channel();
+    let collection = LogCollection::with_max_lines(receiver, Some(2));
+    let time = test_hour();
+    for _ in 0..3 {
+        sender.send(LogString::new(0, time, 0, 0)).unwrap();
+    }
+
+    assert!(wait_until(|| collection.truncated()));
+    assert_eq!(collection.inner().lines.len(), 2);
+    // Получатель закрыт, парсер получит ошибку при отправке
+    assert!(sender.send(LogString::new(0, time, 0, 0)).is_err());
+}
+
+#[test]
+fn test_latest_filter_wins() {
+    let (collection, path) = collection_from(&[
+        "00:01.000000-0,CALL,1,process=rphost\r\n",
+        "00:02.000000-0,EXCP,1,process=rmngr\r\n",
+        "00:03.000000-0,EXCP,1,process=rphost\r\n",
+    ]);
+
+    for filter in [
+        r#"WHERE event = "EXCP""#,
+        r#"WHERE event = "EXCP" AND process = "rmngr""#,
+        r#"WHERE process = "rphost""#,
+    ] {
+        collection.set_filter(filter.to_string()).unwrap();
+    }
+    wait_until(|| collection.status() == Status::Ready && collection.rows() == 2);
+
+    assert_eq!(collection.rows(), 2);
+    assert_eq!(collection.row_id(0), Some(0));
+    assert_eq!(collection.row_id(1), Some(2));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_field_names() {
+    let input = std::io::Cursor::new(
+        "00:01.000000-1,CALL,1,Usr=admin\r\n00:02.000000-1,EXCP,1,Descr='a\r\nb',Usr=user\r\n",
+    );
+    let collection = LogCollection::new(crate::parser::LogParser::parse_reader(
+        input,
+        test_hour(),
+        None,
+    ));
+    wait_until(|| collection.status() != Status::Loading);
+
+    let names = collection.field_names(10);
+    assert_eq!(
+        names
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>(),
+        vec!["Descr", "Usr", "duration", "event", "time"]
+    );
+    assert_eq!(names[0].1, "a⏎b");
+    assert_eq!(names[1].1, "admin");
+    assert_eq!(collection.field_names(1).len(), 4);
+}
+
+#[test]
+fn test_scan_waits_for_new_lines() {
+    use std::{sync::mpsc::channel, time::Duration};
+
+    let (records, path) = test_records(&["00:01.000000-0,EXCP,1,process=rphost\r\n"]);
+    let (sender, receiver) = channel();
+    let collection = LogCollection::new(receiver);
+
+    sender.send(records[0].clone()).unwrap();
+    assert!(wait_until(|| collection.rows() == 1));
+
+    // Без новых записей потоки стоят и версия не меняется
+    let version = collection.version();
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(collection.version(), version);
+
+    sender.send(records[0].clone()).unwrap();
+    assert!(wait_until(|| collection.rows() == 2));
+    assert!(collection.version() > version);
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_file_column() {
+    use crate::parser::FILE_FIELD;
+
+    let (collection, path) = collection_with(
+        &["00:01.000000-0,EXCP,1,process=rphost\r\n"],
+        |collection| collection.set_columns(Columns::parse(&format!("+{}", FILE_FIELD))),
+    );
+
+    let file = Value::from(path.to_string_lossy().to_string());
+    assert_eq!(collection.cols(), 6);
+    assert_eq!(collection.header_index(FILE_FIELD), Some(5));
+    let filter = format!(
+        r#"WHERE __file LIKE "%{}""#,
+        path.file_name().unwrap().to_string_lossy()
+    );
+    collection.set_filter(filter).unwrap();
+    wait_until(|| collection.rows() == 1 && collection.progress().is_none());
+    assert_eq!(collection.data(ModelIndex::new(0, 5)), Some(file.clone()));
+    assert_eq!(
+        collection.line(0).and_then(|line| line.get(FILE_FIELD)),
+        Some(file)
+    );
+
+    // Путь подставляется только для фильтра и колонки, разобранные поля его не хранят
+    let this = collection.inner();
+    let line = this.lines.get(0).unwrap();
+    assert!(this.fields(0, &line).get(FILE_FIELD).is_none());
+    drop(this);
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_refined_filter_applies_on_scan_thread() {
+    let input = std::io::Cursor::new(
+        "00:01.000000-1,EXCP,1,process=rphost\r\n00:02.000000-1,EXCP,1,process=rmngr\r\n00:03.000000-1,CALL,1,process=rphost\r\n",
+    );
+    let collection = LogCollection::new(crate::parser::LogParser::parse_reader(
+        input,
+        test_hour(),
+        None,
+    ));
+    collection
+        .set_filter(r#"WHERE event = "EXCP""#.into())
+        .unwrap();
+    wait_until(|| collection.status() != Status::Loading);
+    assert_eq!(collection.rows(), 2);
+
+    // Фильтры перепроверяются потоком сканирования, а не в `set_filter`: начатая
+    // перепроверка по первому фильтру прерывается, и остается результат последнего
+    collection
+        .set_filter(r#"WHERE event = "EXCP" AND process = "rphost""#.into())
+        .unwrap();
+    collection
+        .set_filter(r#"WHERE event = "EXCP" AND process = "rmngr""#.into())
+        .unwrap();
+    wait_until(|| collection.status() != Status::Loading);
+    assert_eq!(collection.rows(), 1);
+    assert_eq!(
+        collection.line(0).unwrap().get("process"),
+        Some(Value::from("rmngr"))
+    );
+}
+
+#[test]
+fn test_reload_closes_replaced_files() {
+    use crate::parser::LogParser;
+
+    let dir = std::env::temp_dir().join(format!("journal1c_reload_files_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["22080214.log", "22080215.log"] {
+        std::fs::write(
+            dir.join(name),
+            "\u{feff}00:01.000000-0,EXCP,1,process=rphost\r\n",
+        )
+        .unwrap();
+    }
+    let parse = || LogParser::parse(dir.to_string_lossy().into(), None, None);
+
+    let mut collection = LogCollection::new(parse());
+    wait_until(|| collection.status() != Status::Loading);
+    assert_eq!(collection.stats().files, 2);
+    for _ in 0..2 {
+        let old = collection;
+        collection = old.reload(parse());
+        wait_until(|| collection.status() != Status::Loading);
+        assert_eq!(collection.stats().files, 2);
+        assert_eq!(old.stats().files, 0);
+    }
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_reload_keeps_filter_and_stops_threads() {
+    use std::sync::mpsc::channel;
+
+    let (records, path) = test_records(&[
+        "00:01.000000-0,EXCP,1,process=rphost\r\n",
+        "00:02.000000-0,CALL,1,process=rphost\r\n",
+    ]);
+
+    let (old_sender, receiver) = channel();
+    let old = LogCollection::new(receiver);
+    old.set_human_durations(true);
+    let filter = old.set_filter(r#"WHERE event = "EXCP""#.into()).unwrap();
+    // Фильтр сохраняется потоком сканирования
+    wait_until(|| old.stats().filter == filter);
+
+    let (sender, receiver) = channel();
+    let collection = old.reload(receiver);
+    records
+        .iter()
+        .for_each(|record| sender.send(record.clone()).unwrap());
+    drop(sender);
+    wait_until(|| collection.status() != Status::Loading);
+    assert_eq!(collection.rows(), 1);
+    assert!(collection.inner().human_durations);
+    assert_eq!(collection.stats().filter, filter);
+
+    // Поток загрузки прежней коллекции закрывает получатель на следующей записи
+    assert!(wait_until(|| old_sender.send(records[0].clone()).is_err()));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_size_and_offset_fields() {
+    use crate::parser::{OFFSET_FIELD, SIZE_FIELD};
+
+    let lines = [
+        "00:01.000000-0,EXCP,1,process=rphost\r\n",
+        "00:02.000000-0,CALL,1,process=rphost,Sql='SELECT 1 FROM Documents'\r\n",
+    ];
+    let (collection, path) = collection_with(&lines, |collection| {
+        collection.set_columns(Columns::parse(&format!("+{}", OFFSET_FIELD)))
+    });
+
+    collection
+        .set_filter(format!("WHERE __size > {}", lines[0].len()))
+        .unwrap();
+    wait_until(|| collection.status() == Status::Ready);
+    assert_eq!(collection.rows(), 1);
+    let offset = Value::Number((3 + lines[0].len()) as f64);
+    assert_eq!(collection.data(ModelIndex::new(0, 5)), Some(offset.clone()));
+    let line = collection.line(0).unwrap();
+    assert_eq!(line.get(OFFSET_FIELD), Some(offset));
+    assert_eq!(
+        line.get(SIZE_FIELD),
+        Some(Value::Number(lines[1].len() as f64))
+    );
+    assert!(collection
+        .field_names(10)
+        .iter()
+        .all(|(name, _)| !name.starts_with("__")));
+    assert!(collection
+        .known_fields(10)
+        .iter()
+        .any(|name| name == SIZE_FIELD));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_find_time_steps() {
+    use chrono::Duration as TimeStep;
+
+    let (collection, path) = collection_from(&[
+        "00:00.000000-0,EXCP,1,process=rphost\r\n",
+        "00:30.000000-0,EXCP,1,process=rphost\r\n",
+        "01:10.000000-0,EXCP,1,process=rphost\r\n",
+        "03:00.000000-0,EXCP,1,process=rphost\r\n",
+    ]);
+    let times = [0, 30, 70, 180].map(|seconds| test_hour() + TimeStep::seconds(seconds));
+    wait_until(|| collection.status() == Status::Ready);
+
+    let minute = TimeStep::minutes(1);
+    assert_eq!(collection.find_time(times[0] + minute), 2);
+    assert_eq!(collection.find_time(times[3] + minute), 3);
+    assert_eq!(collection.find_time_before(times[3] - minute), 2);
+    assert_eq!(collection.find_time_before(times[2] - minute), 0);
+    assert_eq!(collection.find_time_before(times[0] - minute), 0);
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_raw_time_column() {
+    let (collection, path) = collection_with(
+        &["05:12.345000-1,EXCP,1,process=rphost\r\n"],
+        |collection| collection.set_time_format(Some("%H:%M:%S".into())),
+    );
+    wait_until(|| collection.status() == Status::Ready);
+
+    let index = || ModelIndex::new(0, 0);
+    assert_eq!(collection.display(index()).as_deref(), Some("14:05:12"));
+    collection.set_raw_time(true);
+    assert_eq!(
+        collection.display(index()).as_deref(),
+        Some("2022-08-02 14h 05:12.345000")
+    );
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_columns_schema() {
+    let names = |columns: Columns| match columns {
+        Columns::Fixed(columns) => columns.join(","),
+        Columns::Auto => String::from("auto"),
+    };
+    assert_eq!(names(Columns::default()), DEFAULT_COLUMNS.join(","));
+    assert_eq!(names(Columns::parse(" auto ")), "auto");
+    assert_eq!(names(Columns::parse("time, Usr,")), "time,Usr");
+    assert_eq!(
+        names(Columns::parse("+Usr,__file")),
+        format!("{},Usr,__file", DEFAULT_COLUMNS.join(","))
+    );
+
+    // Повторяющийся ключ считается один раз на запись
+    let (collection, path) = collection_with(
+        &[
+            "00:01.000000-0,EXCP,1,process=rphost,Usr=a\r\n",
+            "00:02.000000-0,CALL,1,Usr=b,Sql='x',Sql='y',Sql='z'\r\n",
+            "00:03.000000-0,CALL,1,Usr=c,process=rphost\r\n",
+        ],
+        |collection| collection.set_columns(Columns::Auto),
+    );
+    wait_until(|| collection.status() == Status::Ready);
+
+    let headers = (0..collection.cols())
+        .filter_map(|column| collection.header_data(column))
+        .collect::<Vec<_>>();
+    assert_eq!(headers, ["time", "event", "duration", "Usr", "process"]);
+    assert_eq!(collection.header_index("Usr"), Some(3));
+    assert_eq!(
+        collection.display(ModelIndex::new(1, 1)),
+        Some(String::from("CALL"))
+    );
+    assert_eq!(
+        collection.data(ModelIndex::new(2, 4)),
+        Some(Value::from("rphost"))
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_parse_ok_field() {
+    let (collection, path) = collection_from(&[
+        "00:01.000000-0,EXCP,1,process=rphost\r\n",
+        "00:02.000000-0,EXCP,1,Descr='unterminated\r\n",
+    ]);
+
+    wait_until(|| collection.status() == Status::Ready);
+    assert_eq!(collection.marker(0), None);
+    assert_eq!(collection.marker(1), Some('!'));
+
+    collection
+        .set_filter(format!(r#"WHERE {} = "false""#, PARSE_OK_FIELD))
+        .unwrap();
+    wait_until(|| collection.status() == Status::Ready);
+    assert_eq!(collection.rows(), 1);
+    let line = collection.line(0).unwrap();
+    assert_eq!(line.get(PARSE_OK_FIELD), Some(Value::from("false")));
+    assert_eq!(line.get("Descr"), Some(Value::from("unterminated\r\n")));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_reads_during_heavy_ingest() {
+    use std::{
+        sync::mpsc::channel,
+        time::{Duration, Instant},
+    };
+
+    const LINES: usize = 100_000;
+
+    let (records, path) = test_records(&["00:01.000000-0,EXCP,1,process=rphost\r\n"]);
+    let (sender, receiver) = channel();
+    let collection = LogCollection::new(receiver);
+    let producer = std::thread::spawn(move || {
+        for _ in 0..LINES {
+            sender.send(records[0].clone()).unwrap();
+        }
+    });
+
+    // Чтения, как при отрисовке кадра, видят согласованные записи, пока загрузка
+    // публикует новые записи: прочитанные строки не меняются и не пропадают.
+    // Кадр при 10 fps - 100 мс, чтение не должно ждать загрузку дольше кадра
+    let (started, mut slowest) = (Instant::now(), Duration::ZERO);
+    let mut seen = 0;
+    while collection.rows() < LINES && started.elapsed() < Duration::from_secs(60) {
+        let read = Instant::now();
+        let rows = collection.rows();
+        assert!(rows >= seen);
+        assert!(collection.inner().lines.len() >= rows);
+        if rows > 0 {
+            let event = collection.header_index("event").unwrap();
+            let value = collection.data(ModelIndex::new(rows - 1, event));
+            assert_eq!(
+                value.map(|value| value.to_string()).as_deref(),
+                Some("EXCP")
+            );
+        }
+        slowest = slowest.max(read.elapsed());
+        seen = rows;
+    }
+
+    producer.join().unwrap();
+    assert_eq!(collection.rows(), LINES);
+    assert!(slowest < Duration::from_millis(100), "{:?}", slowest);
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_raw_lines() {
+    let input = std::io::Cursor::new(
+        "00:01.000000-1,CALL,1,Usr=admin\r\n00:02.000000-1,EXCP,1,Descr='a\r\nb'\r\n",
+    );
+    let collection = Rc::new(RefCell::new(LogCollection::new(
+        crate::parser::LogParser::parse_reader(input, test_hour(), None),
+    )));
+    wait_until(|| collection.borrow().status() != Status::Loading);
+    collection
+        .borrow()
+        .set_filter(String::from(r#"WHERE event = "EXCP""#))
+        .unwrap();
+    wait_until(|| collection.borrow().progress().is_none());
+
+    let raw = RawLines::new(collection.clone());
+    assert_eq!((raw.rows(), raw.cols()), (1, 1));
+    assert_eq!(raw.header_data(0).as_deref(), Some(RawLines::COLUMN));
+    assert_eq!(raw.row_id(0), Some(1));
+    assert_eq!(
+        raw.data(ModelIndex::new(0, 0))
+            .map(|value| value.to_string()),
+        Some(String::from("00:02.000000-1,EXCP,1,Descr='a\r\nb'"))
+    );
+    assert_eq!(raw.data(ModelIndex::new(0, 1)), None);
 }
@@ -1,26 +1,42 @@
 use crate::{
-    parser::buffers::{add_buffer, get_buffer},
+    parser::{
+        buffers::{add_buffer, buffer_path, get_buffer},
+        records::Records,
+    },
     util::parse_time,
 };
-use chrono::{NaiveDate, NaiveDateTime, Timelike};
-pub use compiler::{Compiler, Query};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, Timelike};
+pub use compiler::{Compiler, ConditionStats, ParseError, Probe, Profile, Query};
+pub use derived::{DeriveError, DerivedField};
 pub use fields::*;
 use indexmap::IndexMap;
+pub use records::{parse_file, LogRecords, Record};
 use std::{
     borrow::Cow,
-    fs::OpenOptions,
+    collections::VecDeque,
+    fmt::{Display, Formatter},
+    fs::{File, OpenOptions},
     io,
-    io::{BufReader, Read, Seek, SeekFrom},
-    sync::mpsc::{channel, Receiver, Sender},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, sync_channel, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
 };
 pub use value::*;
-use walkdir::{DirEntry, WalkDir};
+use walkdir::WalkDir;
 
 mod buffers;
+mod cache;
 mod compiler;
+mod derived;
 mod fields;
+mod lines;
 pub mod logdata;
+mod records;
 mod value;
+pub mod warnings;
 
 #[derive(Debug, Clone)]
 pub struct FieldMap<'a> {
@@ -53,25 +69,64 @@ impl<'a> FieldMap<'a> {
             .flat_map(|(a, b)| b.iter().map(|b| (a.as_ref(), b)))
     }
 
-    pub fn get(&self, name: impl AsRef<str>) -> Option<&Value> {
+    /// Имена полей без повторов, в порядке первого появления.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(|key| key.as_ref())
+    }
+
+    pub fn get(&self, name: impl AsRef<str>) -> Option<&Value<'a>> {
         self.values.get(name.as_ref())
     }
 
+    /// Запись с номером `index` в том же порядке, что и в `iter`:
+    /// каждое значение повторяющегося ключа считается отдельной записью.
     pub fn get_index(&self, index: usize) -> Option<(String, &Value)> {
-        let mut inner_index = 0;
-        for value in self.values.iter() {
-            if inner_index + value.1.len() > index {
-                inner_index = index - inner_index;
-                return Some((value.0.to_string(), &value.1[inner_index]));
+        self.iter()
+            .nth(index)
+            .map(|(key, value)| (key.to_string(), value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.iter().map(|(_, v)| v).map(Value::len).sum()
+    }
+
+    /// Копия, в которой поля `first` идут первыми в указанном порядке,
+    /// а остальные - следом в прежнем порядке.
+    pub fn reorder(&self, first: &[String]) -> FieldMap<'a> {
+        let mut values = IndexMap::with_capacity(self.values.len());
+        for key in first {
+            if let Some((key, value)) = self.values.get_key_value(key.as_str()) {
+                values.insert(key.clone(), value.clone());
             }
+        }
+        for (key, value) in self.values.iter() {
+            if !values.contains_key(key) {
+                values.insert(key.clone(), value.clone());
+            }
+        }
+        FieldMap { values }
+    }
 
-            inner_index += value.1.len();
+    /// Копия только с ключами, для которых `keep` вернул `true`.
+    pub fn filter_keys(&self, mut keep: impl FnMut(&str) -> bool) -> FieldMap<'a> {
+        FieldMap {
+            values: self
+                .values
+                .iter()
+                .filter(|(key, _)| keep(key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
         }
-        None
     }
 
-    pub fn len(&self) -> usize {
-        self.values.iter().map(|(_, v)| v).map(Value::len).sum()
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> Default for FieldMap<'a> {
+    fn default() -> Self {
+        FieldMap::new()
     }
 }
 
@@ -93,6 +148,11 @@ impl LogString {
         }
     }
 
+    #[inline]
+    pub fn time(&self) -> NaiveDateTime {
+        self.time
+    }
+
     #[inline]
     pub fn begin(&self) -> u64 {
         self.begin
@@ -103,13 +163,78 @@ impl LogString {
         self.size as usize
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Смещение записи от начала файла, с учетом BOM.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.begin + 3
+    }
+
+    /// Путь файла, из которого прочитана запись.
+    pub fn path(&self) -> Arc<str> {
+        buffer_path(self.buffer)
+    }
+
+    /// Поля записи. Если запись прочитать не удалось, полей нет.
     pub fn fields(&self) -> Fields {
-        Fields::new(self.to_string())
+        Fields::new(self.read().unwrap_or_default())
+    }
+
+    /// Поля записи без `time`. Если запись прочитать не удалось, `event` равно `<unavailable>`.
+    pub fn field_map(&self) -> FieldMap<'static> {
+        // В тексте записи время без часа, поэтому берется полное время записи
+        let mut map = FieldMap::new();
+        map.insert("time", Value::DateTime(self.time));
+        match self.read() {
+            Ok(text) => map.insert_fields(Fields::new(text)),
+            Err(_) => map.insert("event", Value::from(UNAVAILABLE)),
+        }
+        map
+    }
+
+    /// Время записи как оно записано в файле (`mm:ss.ffffff`), без часа из имени файла.
+    pub fn raw_time(&self) -> Option<String> {
+        let text = self.read().ok()?;
+        match Fields::new(text).parse_field() {
+            Some((key, value)) if key == "time" => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Запись прочитана и разобрана на поля без ошибок.
+    pub fn parse_ok(&self) -> bool {
+        match self.read() {
+            Ok(text) => {
+                let fields = Fields::new(text);
+                while fields.parse_field().is_some() {}
+                fields.is_complete()
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Текст записи из файла. Ошибка, если файл обрезан или заменен после чтения смещений.
+    pub fn read(&self) -> io::Result<String> {
+        let buffer = get_buffer(self.buffer)?;
+        let mut lock = buffer.lock().unwrap();
+        lock.seek(SeekFrom::Start(self.offset()))?;
+
+        let mut data = vec![0; self.len()];
+        lock.read_exact(&mut data)?;
+        String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     pub fn get(&self, name: &str) -> Option<Value<'static>> {
         match name {
             "time" => Some(Value::DateTime(self.time)),
+            FILE_FIELD => Some(Value::from(self.path().to_string())),
+            SIZE_FIELD => Some(Value::Number(self.size as f64)),
+            OFFSET_FIELD => Some(Value::Number(self.offset() as f64)),
+            PARSE_OK_FIELD => Some(Value::from(self.parse_ok().to_string())),
             _ => {
                 let f = self.fields();
                 f.iter()
@@ -120,68 +245,349 @@ impl LogString {
     }
 }
 
-impl ToString for LogString {
-    fn to_string(&self) -> String {
-        let buffer = get_buffer(self.buffer);
-        let mut lock = buffer.lock().unwrap();
-        lock.seek(SeekFrom::Start(self.begin() + 3)).unwrap();
+// Запись, которую не удалось прочитать, показывается как `<unavailable>`
+impl Display for LogString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.read() {
+            Ok(text) => write!(f, "{}", text),
+            Err(_) => write!(f, "{}", UNAVAILABLE),
+        }
+    }
+}
 
-        let mut data = vec![0; self.len()];
-        lock.read_exact(&mut data).unwrap();
-        unsafe { String::from_utf8_unchecked(data) }
+/// Заменяет запись, байты которой больше нельзя прочитать.
+pub const UNAVAILABLE: &str = "<unavailable>";
+
+/// Псевдополе с путем файла записи. В основные колонки не входит, добавляется через `--columns`.
+pub const FILE_FIELD: &str = "__file";
+
+/// Псевдополе с размером записи в байтах, для поиска слишком больших записей.
+pub const SIZE_FIELD: &str = "__size";
+
+/// Псевдополе со смещением записи в файле.
+pub const OFFSET_FIELD: &str = "__offset";
+
+/// Псевдополе `"true"`/`"false"`: запись разобрана на поля полностью (см. `Fields::is_complete`).
+pub const PARSE_OK_FIELD: &str = "__parse_ok";
+
+/// Псевдополя, которые берутся из самой записи без разбора ее текста. В разобранные поля
+/// строки они не входят и подставляются только там, где их запрашивают (`LogString::get`).
+pub const RECORD_FIELDS: [&str; 3] = [FILE_FIELD, SIZE_FIELD, OFFSET_FIELD];
+
+/// Откуда читаются логи.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Каталоги с файлами `.log`, включая подкаталоги. Записи всех каталогов сливаются по времени.
+    Directories(Vec<String>),
+    /// Стандартный ввод с записями одного часа.
+    Stdin(NaiveDateTime),
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Directories(dirs) => write!(f, "{}", dirs.join(", ")),
+            Source::Stdin(_) => write!(f, "stdin"),
+        }
     }
 }
 
+/// Сколько разобранных записей одной части может ждать отправки.
+const PART_BUFFER: usize = 4096;
+
+/// Наибольшее количество участков с неубывающим временем, которые сливаются по отдельности.
+/// Записи файла с большим количеством участков сортируются целиком.
+const MAX_RUNS: usize = 16;
+
+/// Путь записей, прочитанных из stdin.
+const STDIN_PATH: &str = "stdin";
+
+/// Записи участка файла с неубывающим временем.
+type Run = std::vec::IntoIter<LogString>;
+
 pub struct LogParser;
 
 impl LogParser {
-    pub fn parse(dir: String, date: Option<NaiveDateTime>) -> Receiver<LogString> {
+    pub fn parse(
+        dir: String,
+        date: Option<NaiveDateTime>,
+        assume_hour: Option<NaiveDateTime>,
+    ) -> Receiver<LogString> {
+        LogParser::parse_dirs(vec![dir], date, assume_hour)
+    }
+
+    /// Разбирает несколько каталогов как один: файлы одного часа из всех каталогов
+    /// сливаются вместе.
+    pub fn parse_dirs(
+        dirs: Vec<String>,
+        date: Option<NaiveDateTime>,
+        assume_hour: Option<NaiveDateTime>,
+    ) -> Receiver<LogString> {
         let (sender, receiver) = channel();
-        std::thread::spawn(move || LogParser::parse_dir(dir, date, sender));
+        let group = buffers::new_group();
+        std::thread::spawn(move || LogParser::parse_dir(group, dirs, date, assume_hour, sender));
+        receiver
+    }
+
+    pub fn parse_source(
+        source: Source,
+        date: Option<NaiveDateTime>,
+        assume_hour: Option<NaiveDateTime>,
+    ) -> Receiver<LogString> {
+        match source {
+            Source::Directories(dirs) => LogParser::parse_dirs(dirs, date, assume_hour),
+            Source::Stdin(hour) => LogParser::parse_reader(io::stdin(), hour, date),
+        }
+    }
+
+    /// Разбирает записи из `reader` как файл часа `hour`. Записи читаются потом по смещению,
+    /// поэтому данные сначала целиком читаются в память.
+    pub fn parse_reader<R: Read + Send + 'static>(
+        mut reader: R,
+        hour: NaiveDateTime,
+        date: Option<NaiveDateTime>,
+    ) -> Receiver<LogString> {
+        let (sender, receiver) = sync_channel(PART_BUFFER);
+        std::thread::spawn(move || match LogParser::read_input(&mut reader) {
+            Ok(data) => {
+                let group = buffers::new_group();
+                let open = || {
+                    let mut cursor = io::Cursor::new(data.clone());
+                    cursor.set_position(3);
+                    Ok(cursor)
+                };
+                match LogParser::open_runs(group, Path::new(STDIN_PATH), open, hour, 0, date) {
+                    Ok(runs) => LogParser::merge_runs(group, runs, sender),
+                    Err(e) => warnings::warn(format!("can't read input: {}", e)),
+                }
+            }
+            Err(e) => warnings::warn(format!("can't read input: {}", e)),
+        });
         receiver
     }
 
+    /// Читает `reader` целиком, добавляя BOM, если его нет:
+    /// смещения записей отсчитываются от конца BOM.
+    fn read_input<R: Read>(reader: &mut R) -> io::Result<Arc<[u8]>> {
+        let mut head = Vec::with_capacity(3);
+        reader.take(3).read_to_end(&mut head)?;
+
+        let mut data = Vec::new();
+        if head != "\u{feff}".as_bytes() {
+            data.extend_from_slice("\u{feff}".as_bytes());
+        }
+        data.extend_from_slice(&head);
+        reader.read_to_end(&mut data)?;
+        Ok(data.into())
+    }
+
+    /// Час, к которому относятся записи файла: из имени `ггммддчч.log`,
+    /// иначе `assume_hour` или час изменения файла (с предупреждением).
+    pub(super) fn file_hour(
+        path: &Path,
+        assume_hour: Option<NaiveDateTime>,
+    ) -> Option<NaiveDateTime> {
+        lazy_static::lazy_static! {
+            static ref NAME: regex::Regex = regex::Regex::new(r#"^\d{8}[.]log$"#).unwrap();
+        }
+
+        let name = path.file_name()?.to_string_lossy();
+        if NAME.is_match(&name) {
+            let year = 2000 + name[0..2].parse::<i32>().unwrap();
+            let month = name[2..4].parse::<u32>().unwrap();
+            let day = name[4..6].parse::<u32>().unwrap();
+            let hour = name[6..8].parse::<u32>().unwrap();
+
+            return NaiveDate::from_ymd_opt(year, month, day)?.and_hms_opt(hour, 0, 0);
+        }
+
+        let hour = LogParser::fallback_hour(path, assume_hour)?;
+        warnings::warn(format!(
+            "{}: name does not encode an hour, assuming {}",
+            path.display(),
+            hour.format("%Y-%m-%d %H:00")
+        ));
+        Some(hour)
+    }
+
+    /// Час начала файла, имя которого не в формате `ггммддчч.log`.
+    /// Берется `assume_hour`, либо час изменения файла, скорректированный так,
+    /// чтобы первая запись не оказалась позже времени изменения.
+    fn fallback_hour(path: &Path, assume_hour: Option<NaiveDateTime>) -> Option<NaiveDateTime> {
+        if assume_hour.is_some() {
+            return assume_hour;
+        }
+
+        let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+        let modified = DateTime::<Local>::from(modified).naive_local();
+        let hour = modified.date().and_hms_opt(modified.hour(), 0, 0)?;
+
+        let mut prefix = Vec::with_capacity(64);
+        OpenOptions::new()
+            .read(true)
+            .open(path)
+            .ok()?
+            .take(64)
+            .read_to_end(&mut prefix)
+            .ok()?;
+        let prefix = String::from_utf8_lossy(&prefix);
+        let time = prefix.trim_start_matches('\u{feff}').split('-').next()?;
+
+        let regex = regex::Regex::new(r#"^\d{2}:\d{2}[.]\d+$"#).unwrap();
+        if regex.is_match(time) && parse_time(hour, time) > modified {
+            Some(hour - Duration::hours(1))
+        } else {
+            Some(hour)
+        }
+    }
+
+    /// Сливает по времени записи файлов одного часа: путь, час и номер каталога.
+    fn merge_part(
+        group: usize,
+        part: Vec<(PathBuf, NaiveDateTime, usize)>,
+        date: Option<NaiveDateTime>,
+        sender: SyncSender<LogString>,
+    ) {
+        // Время внутри файла может идти назад (перевод часов), поэтому файлы читаются
+        // один раз с делением на участки с неубывающим временем, и сливаются участки всех файлов
+        let runs = part
+            .into_iter()
+            .flat_map(|(path, hour, root)| {
+                let open = || -> io::Result<BufReader<File>> {
+                    let mut file = OpenOptions::new().read(true).open(&path)?;
+                    file.seek(SeekFrom::Start(3))?;
+                    Ok(BufReader::new(file))
+                };
+                match LogParser::open_runs(group, &path, open, hour, root, date) {
+                    Ok(runs) => runs,
+                    Err(e) => {
+                        warnings::warn(format!("{}: {}", path.display(), e));
+                        vec![]
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        LogParser::merge_runs(group, runs, sender);
+    }
+
+    /// Сливает по времени участки с неубывающим временем записей.
+    fn merge_runs(group: usize, mut part: Vec<Run>, sender: SyncSender<LogString>) {
+        let mut lines = part.iter_mut().map(Iterator::next).collect::<Vec<_>>();
+
+        while let Some(min) = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(index, line)| line.as_ref().map(|line| (index, line.time())))
+            .min_by_key(|(_, time)| *time)
+            .map(|(index, _)| index)
+        {
+            let next = part[min].next();
+            let line = std::mem::replace(&mut lines[min], next);
+            if sender.send(line.unwrap()).is_err() {
+                buffers::release_group(group);
+                return;
+            }
+        }
+    }
+
+    /// Записи файла `path`, разделенные на участки с неубывающим временем: участок
+    /// заканчивается перед записью, время которой меньше времени предыдущей.
+    /// `open` открывает данные файла после BOM.
+    fn open_runs<R: BufRead + Seek + Send + 'static>(
+        group: usize,
+        path: &Path,
+        open: impl Fn() -> io::Result<R>,
+        hour: NaiveDateTime,
+        root: usize,
+        date: Option<NaiveDateTime>,
+    ) -> io::Result<Vec<Run>> {
+        let mut records = Records::new(open()?, 0);
+        // Отдельный файл для чтения записей по смещению
+        let buffer = add_buffer(group, open()?, root, path);
+
+        let mut runs: Vec<Vec<LogString>> = vec![];
+        while let Some(line) = LogParser::next_line(buffer, &mut records, hour, date) {
+            match runs.last_mut() {
+                Some(run) if run.last().is_some_and(|last| last.time() <= line.time()) => {
+                    run.push(line)
+                }
+                _ => runs.push(vec![line]),
+            }
+        }
+
+        if runs.len() > MAX_RUNS {
+            // Время скачет постоянно: слияние стольких участков медленнее сортировки.
+            // Устойчивая сортировка сохраняет порядок записей с одинаковым временем
+            let mut lines = runs.concat();
+            lines.sort_by_key(LogString::time);
+            runs = vec![lines];
+        }
+        Ok(runs.into_iter().map(Vec::into_iter).collect())
+    }
+
+    /// Следующая запись файла не раньше `date`.
+    fn next_line<R: BufRead>(
+        buffer: usize,
+        records: &mut Records<R>,
+        hour: NaiveDateTime,
+        date: Option<NaiveDateTime>,
+    ) -> Option<LogString> {
+        loop {
+            let (begin, record) = match records.next_record() {
+                Ok(record) => record?,
+                Err(e) => {
+                    warnings::warn(format!("can't read log record: {}", e));
+                    return None;
+                }
+            };
+
+            let size = record.len() as u64;
+            let time = match Fields::new(record).parse_field() {
+                Some((key, value)) if key == "time" => parse_time(hour, value),
+                _ => continue,
+            };
+            match date {
+                Some(date) if time < date => continue,
+                _ => return Some(LogString::new(buffer, time, begin, size)),
+            }
+        }
+    }
+
     // А может сделать итератор, который парсит
     fn parse_dir(
-        path: String,
+        group: usize,
+        paths: Vec<String>,
         date: Option<NaiveDateTime>,
+        assume_hour: Option<NaiveDateTime>,
         sender: Sender<LogString>,
     ) -> io::Result<()> {
-        let walk = WalkDir::new(path)
-            .follow_links(true)
-            .into_iter()
-            .filter_map(Result::ok)
-            .filter(|e| {
-                !e.file_type().is_dir() && e.file_name().to_string_lossy().ends_with(".log")
-            });
+        let walk = paths.into_iter().enumerate().flat_map(|(root, path)| {
+            WalkDir::new(path)
+                .follow_links(true)
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|e| {
+                    !e.file_type().is_dir() && e.file_name().to_string_lossy().ends_with(".log")
+                })
+                .map(move |e| (e, root))
+        });
 
         let hour_date = date.map(|date| NaiveDate::from(date.date()).and_hms(date.hour(), 0, 0));
-        let regex = regex::Regex::new(r#"^\d{8}[.]log$"#).unwrap();
         let mut files = walk
-            .filter_map(|e| {
-                let name = e.file_name().to_string_lossy().to_string();
-                if regex.is_match(&name) {
-                    let year = 2000 + name[0..2].parse::<i32>().unwrap();
-                    let month = name[2..4].parse::<u32>().unwrap();
-                    let day = name[4..6].parse::<u32>().unwrap();
-                    let hour = name[6..8].parse::<u32>().unwrap();
-
-                    let date_time = NaiveDate::from_ymd(year, month, day).and_hms(hour, 0, 0);
-                    match hour_date {
-                        Some(hour_date) if date_time < hour_date => None,
-                        _ => Some((e, date_time)),
-                    }
-                } else {
-                    None
+            .filter_map(|(e, root)| {
+                let date_time = LogParser::file_hour(e.path(), assume_hour)?;
+                match hour_date {
+                    Some(hour_date) if date_time < hour_date => None,
+                    _ => Some((e.into_path(), date_time, root)),
                 }
             })
             .collect::<Vec<_>>();
 
-        files.sort_by(|(_, name), (_, name2)| name.cmp(name2));
+        files.sort_by(|(_, name, _), (_, name2, _)| name.cmp(name2));
 
         let parts = files.into_iter().fold(
-            Vec::<Vec<(DirEntry, NaiveDateTime)>>::new(),
-            |mut acc, (entry, time)| {
+            Vec::<Vec<(PathBuf, NaiveDateTime, usize)>>::new(),
+            |mut acc, (entry, time, root)| {
                 if acc.is_empty() {
                     acc.push(vec![]);
                 } else if acc.last().unwrap().is_empty()
@@ -190,92 +596,405 @@ impl LogParser {
                     acc.push(vec![]);
                 }
 
-                acc.last_mut().unwrap().push((entry, time));
+                acc.last_mut().unwrap().push((entry, time, root));
                 acc
             },
         );
 
+        // Части - файлы разных часов, поэтому их записи не пересекаются по времени.
+        // Части разбираются параллельно, а результаты передаются дальше строго по порядку частей.
+        let mut receivers = Vec::with_capacity(parts.len());
+        let mut jobs = VecDeque::with_capacity(parts.len());
         for part in parts {
-            let rows = part
-                .into_iter()
-                .map(|(entry, time)| {
-                    let mut file = OpenOptions::new().read(true).open(entry.path()).unwrap();
-                    file.seek(SeekFrom::Start(3)).unwrap();
-                    let mut data = String::with_capacity(1024 * 30);
-                    file.read_to_string(&mut data).unwrap();
-
-                    (add_buffer(BufReader::new(file)), data, time)
-                })
-                .filter(|(_, data, _)| !data.is_empty())
-                .collect::<Vec<_>>();
-
-            let mut part = rows
-                .into_iter()
-                .map(|(buf, data, hour)| (buf, Fields::new(data), hour))
-                .collect::<Vec<_>>();
-
-            let mut lines = vec![None; part.len()];
-            loop {
-                for (index, (buffer, data, hour)) in part.iter_mut().enumerate() {
-                    if lines[index].is_some() {
-                        continue;
-                    }
+            let (part_sender, part_receiver) = sync_channel(PART_BUFFER);
+            receivers.push(part_receiver);
+            jobs.push_back((part, part_sender));
+        }
 
-                    loop {
-                        let begin = data.current() as u64;
-                        match data.parse_field() {
-                            Some((key, value)) if key == "time" => {
-                                let time = parse_time(*hour, &value);
-                                match date {
-                                    Some(date) if time < date => {}
-                                    _ => {
-                                        while let Some(_) = data.parse_field() {}
-                                        let end = data.current() as u64;
-
-                                        let line =
-                                            LogString::new(*buffer, time, begin, end - begin);
-                                        lines[index] = Some(line);
-                                        break;
-                                    }
-                                }
-                            }
-                            Some(_) => unreachable!(),
-                            None => break,
-                        }
+        let workers = std::thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .min(jobs.len());
+        let jobs = Arc::new(Mutex::new(jobs));
+        for _ in 0..workers {
+            let jobs = jobs.clone();
+            std::thread::spawn(move || loop {
+                // Части берутся по порядку, поэтому текущая читаемая часть всегда в работе
+                let job = jobs.lock().unwrap().pop_front();
+                match job {
+                    Some((part, part_sender)) => {
+                        LogParser::merge_part(group, part, date, part_sender)
                     }
+                    None => break,
                 }
+            });
+        }
 
-                let min = lines
-                    .iter()
-                    .enumerate()
-                    .filter_map(|(index, value)| {
-                        if let Some(value) = value.as_ref() {
-                            Some((index, value))
-                        } else {
-                            None
-                        }
-                    })
-                    .min_by(|(_, value1), (_, value2)| {
-                        value1
-                            .get("time")
-                            .unwrap()
-                            .partial_cmp(&value2.get("time").unwrap())
-                            .unwrap()
-                    })
-                    .map(|(index, _)| index);
-
-                if lines.iter().all(Option::is_none) {
-                    break;
-                }
-
-                if let Some(min) = min {
-                    let mut tmp = None;
-                    std::mem::swap(&mut lines[min], &mut tmp);
-                    sender.send(tmp.unwrap()).unwrap()
+        let mut sent = false;
+        for receiver in receivers {
+            for line in receiver {
+                if sender.send(line).is_err() {
+                    // Коллекция заменена: оставшиеся части не разбираются, файлы закрываются
+                    jobs.lock().unwrap().clear();
+                    buffers::release_group(group);
+                    return Ok(());
                 }
+                sent = true;
             }
         }
 
+        // Ни одна запись не попала в коллекцию, поэтому и закрыть файлы будет некому
+        if !sent {
+            buffers::release_group(group);
+        }
         Ok(())
     }
 }
+
+#[test]
+fn test_assumed_hour_for_unnamed_file() {
+    let dir = std::env::temp_dir().join(format!("journal1c_fallback_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("cluster.log"),
+        "\u{feff}05:12.345000-1,EXCP,2,process=rphost\r\n",
+    )
+    .unwrap();
+
+    let hour = NaiveDate::from_ymd_opt(2025, 1, 15)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap();
+    let receiver = LogParser::parse(dir.to_string_lossy().to_string(), None, Some(hour));
+    let line = receiver.recv().unwrap();
+    assert_eq!(
+        line.time(),
+        hour + Duration::milliseconds(5 * 60_000 + 12_345)
+    );
+    assert!(warnings::take_warnings()
+        .iter()
+        .any(|warning| warning.contains("cluster.log")));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_get_index_inside_multi_value() {
+    let mut map = FieldMap::new();
+    map.insert("event", Value::from("EXCP"));
+    map.insert("Context", Value::from("first"));
+    map.insert("Context", Value::from("second"));
+    map.insert("process", Value::from("rphost"));
+
+    assert_eq!(map.len(), 4);
+    assert_eq!(
+        map.get_index(2),
+        Some((String::from("Context"), &Value::from("second")))
+    );
+    assert_eq!(
+        map.get_index(3),
+        Some((String::from("process"), &Value::from("rphost")))
+    );
+    assert_eq!(map.get_index(4), None);
+    for (index, (key, value)) in map.iter().enumerate() {
+        assert_eq!(map.get_index(index), Some((key.to_string(), value)));
+    }
+}
+
+#[test]
+fn test_merge_streamed_files() {
+    let dir = std::env::temp_dir().join(format!("journal1c_merge_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("rphost")).unwrap();
+    std::fs::create_dir_all(dir.join("rmngr")).unwrap();
+    std::fs::write(
+        dir.join("rphost").join("25011514.log"),
+        "\u{feff}00:01.000000-1,CALL,1,Sql='a\r\nb'\r\n00:03.000000-1,CALL,1\r\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("rmngr").join("25011514.log"),
+        "\u{feff}00:02.000000-1,EXCP,1\r\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("rmngr").join("25011513.log"),
+        "\u{feff}59:59.000000-1,CONN,1\r\n",
+    )
+    .unwrap();
+
+    let receiver = LogParser::parse(dir.to_string_lossy().to_string(), None, None);
+    let lines = receiver.iter().collect::<Vec<_>>();
+    assert_eq!(
+        lines
+            .iter()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>(),
+        vec![
+            "59:59.000000-1,CONN,1\r\n",
+            "00:01.000000-1,CALL,1,Sql='a\r\nb'\r\n",
+            "00:02.000000-1,EXCP,1\r\n",
+            "00:03.000000-1,CALL,1\r\n",
+        ]
+    );
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_parse_reader_without_bom() {
+    let hour = NaiveDate::from_ymd_opt(2025, 1, 15)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap();
+    let input =
+        io::Cursor::new("00:01.000000-1,CALL,1\r\n00:02.000000-1,EXCP,1,Descr='a\r\nb'\r\n");
+    let lines = LogParser::parse_reader(input, hour, None)
+        .iter()
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        lines.iter().map(LogString::time).collect::<Vec<_>>(),
+        vec![hour + Duration::seconds(1), hour + Duration::seconds(2)]
+    );
+    assert_eq!(
+        lines[1].to_string(),
+        "00:02.000000-1,EXCP,1,Descr='a\r\nb'\r\n"
+    ); // Данные остаются в памяти, временный файл не создается
+    assert_eq!(lines[0].path().as_ref(), STDIN_PATH);
+}
+
+#[test]
+fn test_merge_time_jump_inside_file() {
+    let dir = std::env::temp_dir().join(format!("journal1c_jump_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("rphost")).unwrap();
+    std::fs::create_dir_all(dir.join("rmngr")).unwrap();
+    // Часы переведены назад после 00:05
+    std::fs::write(
+        dir.join("rphost").join("25011514.log"),
+        "\u{feff}00:01.000000-1,CALL,1\r\n00:05.000000-1,CALL,2\r\n00:02.000000-1,CALL,3\r\n00:06.000000-1,CALL,4\r\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("rmngr").join("25011514.log"),
+        "\u{feff}00:03.000000-1,EXCP,1\r\n",
+    )
+    .unwrap();
+
+    let receiver = LogParser::parse(dir.to_string_lossy().to_string(), None, None);
+    let lines = receiver
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![
+            "00:01.000000-1,CALL,1\r\n",
+            "00:02.000000-1,CALL,3\r\n",
+            "00:03.000000-1,EXCP,1\r\n",
+            "00:05.000000-1,CALL,2\r\n",
+            "00:06.000000-1,CALL,4\r\n",
+        ]
+    );
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_open_runs() {
+    let lines = [
+        "00:01.000000-1,CALL,1\r\n",
+        "00:05.000000-1,CALL,2\r\n",
+        "00:02.000000-1,CALL,3\r\n",
+        "00:02.000000-1,CALL,4\r\n",
+        "00:01.000000-1,CALL,5,Sql='a\r\nb'\r\n",
+    ];
+    let data = Arc::<[u8]>::from(format!("\u{feff}{}", lines.concat()).into_bytes());
+    let open = || {
+        let mut cursor = io::Cursor::new(data.clone());
+        cursor.set_position(3);
+        Ok(cursor)
+    };
+    let hour = NaiveDate::from_ymd_opt(2025, 1, 15)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap();
+
+    // Участки заканчиваются перед шагом времени назад, равное время участок продолжает
+    let runs = LogParser::open_runs(
+        buffers::new_group(),
+        Path::new(STDIN_PATH),
+        open,
+        hour,
+        0,
+        None,
+    )
+    .unwrap()
+    .into_iter()
+    .map(|run| {
+        run.map(|line| (line.begin(), line.to_string()))
+            .collect::<Vec<_>>()
+    })
+    .collect::<Vec<_>>();
+    let record = |index: usize| {
+        (
+            lines[..index].concat().len() as u64,
+            lines[index].to_string(),
+        )
+    };
+    assert_eq!(
+        runs,
+        vec![
+            vec![record(0), record(1)],
+            vec![record(2), record(3)],
+            vec![record(4)],
+        ]
+    );
+
+    // Записи раньше `date` не начинают участков
+    let runs = LogParser::open_runs(
+        buffers::new_group(),
+        Path::new(STDIN_PATH),
+        open,
+        hour,
+        0,
+        Some(hour + Duration::seconds(2)),
+    )
+    .unwrap();
+    assert_eq!(
+        runs.into_iter().map(|run| run.len()).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+}
+
+#[test]
+fn test_from_skips_whole_part() {
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let dir = std::env::temp_dir().join(format!("journal1c_skip_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("rphost")).unwrap();
+    std::fs::create_dir_all(dir.join("rmngr")).unwrap();
+    // Все записи часа 14 раньше `date`, часть целиком отфильтровывается
+    std::fs::write(
+        dir.join("rphost").join("25011514.log"),
+        "\u{feff}00:01.000000-1,CALL,1\r\n10:00.000000-1,CALL,2\r\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("rmngr").join("25011514.log"),
+        "\u{feff}20:00.000000-1,EXCP,1\r\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("rphost").join("25011515.log"),
+        "\u{feff}00:01.000000-1,CALL,3\r\n",
+    )
+    .unwrap();
+
+    let collect = |date: NaiveDateTime| {
+        let receiver = LogParser::parse(dir.to_string_lossy().to_string(), Some(date), None);
+        let mut lines = vec![];
+        loop {
+            match receiver.recv_timeout(std::time::Duration::from_secs(10)) {
+                Ok(line) => lines.push(line.to_string()),
+                Err(RecvTimeoutError::Disconnected) => break lines,
+                Err(RecvTimeoutError::Timeout) => panic!("merge did not finish"),
+            }
+        }
+    };
+
+    let hour = NaiveDate::from_ymd_opt(2025, 1, 15)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap();
+    assert_eq!(
+        collect(hour + Duration::minutes(30)),
+        vec!["00:01.000000-1,CALL,3\r\n"]
+    );
+    assert!(collect(hour + Duration::minutes(90)).is_empty());
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_merge_directories() {
+    let dir = std::env::temp_dir().join(format!("journal1c_roots_{}", std::process::id()));
+    for (root, text) in [
+        (
+            "first",
+            "\u{feff}00:01.000000-1,CALL,1\r\n00:03.000000-1,CALL,1\r\n",
+        ),
+        ("second", "\u{feff}00:02.000000-1,EXCP,1\r\n"),
+    ] {
+        std::fs::create_dir_all(dir.join(root)).unwrap();
+        std::fs::write(dir.join(root).join("25011514.log"), text).unwrap();
+    }
+
+    let roots = ["first", "second"].map(|root| dir.join(root).to_string_lossy().to_string());
+    let lines = LogParser::parse_dirs(roots.to_vec(), None, None)
+        .iter()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines
+            .iter()
+            .map(|line| (line.to_string(), buffers::buffer_root(line.buffer)))
+            .collect::<Vec<_>>(),
+        vec![
+            ("00:01.000000-1,CALL,1\r\n".to_string(), 0),
+            ("00:02.000000-1,EXCP,1\r\n".to_string(), 1),
+            ("00:03.000000-1,CALL,1\r\n".to_string(), 0),
+        ]
+    );
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_truncated_file_record() {
+    use crate::{parser::logdata::LogCollection, ui::model::DataModel};
+
+    let path = std::env::temp_dir().join(format!("journal1c_truncated_{}.log", std::process::id()));
+    let line = "00:01.000000-1,EXCP,1,process=rphost\r\n";
+    std::fs::write(&path, format!("\u{feff}{}", line)).unwrap();
+    let buffer = add_buffer(
+        buffers::new_group(),
+        BufReader::new(File::open(&path).unwrap()),
+        0,
+        &path,
+    );
+    let time = NaiveDate::from_ymd_opt(2025, 1, 15)
+        .unwrap()
+        .and_hms_opt(14, 0, 1)
+        .unwrap();
+    let record = LogString::new(buffer, time, 0, line.len() as u64);
+    assert_eq!(record.to_string(), line);
+
+    // Файл обрезан после того, как смещения записей прочитаны
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(10)
+        .unwrap();
+    assert!(record.read().is_err());
+    assert_eq!(record.to_string(), UNAVAILABLE);
+    assert_eq!(record.get("event"), None);
+    assert_eq!(
+        record.field_map().get("event"),
+        Some(&Value::from(UNAVAILABLE))
+    );
+
+    let (sender, receiver) = channel();
+    let collection = LogCollection::new(receiver);
+    sender.send(record).unwrap();
+    drop(sender);
+    for _ in 0..50 {
+        if collection.rows() == 1 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert_eq!(
+        collection.display(crate::ui::index::ModelIndex::new(0, 1)),
+        Some(UNAVAILABLE.to_string())
+    );
+    let _ = std::fs::remove_file(path);
+}
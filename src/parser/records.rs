@@ -0,0 +1,208 @@
+use crate::{
+    parser::{syntax, FieldMap, Fields, LogParser, Syntax, Value},
+    util::parse_time,
+};
+use chrono::NaiveDateTime;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Запись журнала с разобранными полями.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub time: NaiveDateTime,
+    /// Все поля записи, включая `time`, `event` и `duration`.
+    pub fields: FieldMap<'static>,
+}
+
+impl Record {
+    fn parse(hour: NaiveDateTime, text: String) -> Option<Record> {
+        let iter = Fields::new(text);
+        let time = match iter.parse_field()? {
+            (key, value) if key == "time" => parse_time(hour, value),
+            _ => return None,
+        };
+
+        let mut fields = FieldMap::new();
+        fields.insert("time", Value::DateTime(time));
+        while let Some((key, value)) = iter.parse_field() {
+            fields.insert(key.to_string(), Value::from(value.to_string()));
+        }
+        Some(Record { time, fields })
+    }
+}
+
+/// Последовательное чтение записей одного файла журнала.
+pub struct LogRecords {
+    records: Records<BufReader<File>>,
+    hour: NaiveDateTime,
+}
+
+impl Iterator for LogRecords {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (_, text) = match self.records.next_record() {
+                Ok(record) => record?,
+                Err(e) => return Some(Err(e)),
+            };
+
+            // Строки без времени в начале (обрезанная запись) пропускаются
+            if let Some(record) = Record::parse(self.hour, text) {
+                return Some(Ok(record));
+            }
+        }
+    }
+}
+
+/// Открывает файл журнала для чтения записей.
+/// Час записей берется из имени `ггммддчч.log`, иначе из времени изменения файла.
+pub fn parse_file<P: AsRef<Path>>(path: P) -> io::Result<LogRecords> {
+    let path = path.as_ref();
+    let hour = LogParser::file_hour(path, None).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: can't determine the hour of records", path.display()),
+        )
+    })?;
+
+    let mut file = File::open(path)?;
+    let mut bom = [0; 3];
+    let read = file.read(&mut bom)?;
+    if read < 3 || bom != [0xEF, 0xBB, 0xBF] {
+        file.seek(SeekFrom::Start(0))?;
+    }
+
+    Ok(LogRecords {
+        records: Records::new(BufReader::new(file), 0),
+        hour,
+    })
+}
+
+/// Читает записи журнала по одной, не загружая файл в память целиком.
+/// Запись заканчивается переводом строки вне значения в кавычках.
+pub(super) struct Records<R> {
+    reader: R,
+    offset: u64,
+    syntax: Syntax,
+}
+
+impl<R: BufRead> Records<R> {
+    /// `offset` - позиция `reader` относительно начала данных (после BOM).
+    pub fn new(reader: R, offset: u64) -> Self {
+        Records {
+            reader,
+            offset,
+            syntax: syntax(),
+        }
+    }
+
+    /// Следующая запись и ее смещение.
+    pub fn next_record(&mut self) -> io::Result<Option<(u64, String)>> {
+        let begin = self.offset;
+        let mut record = Vec::new();
+        let mut quote = None;
+        loop {
+            let start = record.len();
+            let read = self.reader.read_until(b'\n', &mut record)?;
+            if read == 0 {
+                break;
+            }
+
+            self.offset += read as u64;
+            quote = scan_quotes(&record, start, quote, &self.syntax);
+            if quote.is_none() && record.ends_with(b"\n") {
+                break;
+            }
+        }
+
+        if record.is_empty() {
+            return Ok(None);
+        }
+
+        String::from_utf8(record)
+            .map(|record| Some((begin, record)))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Обновляет открытую кавычку по байтам записи, начиная с `start`.
+/// Значение в кавычках начинается сразу после `=`, удвоенная кавычка внутри него экранирована.
+fn scan_quotes(record: &[u8], start: usize, mut quote: Option<u8>, syntax: &Syntax) -> Option<u8> {
+    let mut index = start;
+    while index < record.len() {
+        let byte = record[index];
+        match quote {
+            None if syntax.is_quote(byte) && index > 0 && record[index - 1] == b'=' => {
+                quote = Some(byte)
+            }
+            Some(q) if byte == q => match record.get(index + 1) {
+                Some(&next) if next == q => index += 1,
+                _ => quote = None,
+            },
+            _ => {}
+        }
+        index += 1;
+    }
+    quote
+}
+
+#[test]
+fn test_records_with_quoted_newlines() {
+    let data = "00:01.000000-1,CALL,1,Sql='SELECT 1\r\nFROM ''T'''\r\n00:02.000000-2,EXCP,1,Descr=\"a\nb\"\r\n";
+    let mut records = Records::new(data.as_bytes(), 3);
+
+    let (begin, first) = records.next_record().unwrap().unwrap();
+    assert_eq!(begin, 3);
+    assert_eq!(
+        first,
+        "00:01.000000-1,CALL,1,Sql='SELECT 1\r\nFROM ''T'''\r\n"
+    );
+
+    let (begin, second) = records.next_record().unwrap().unwrap();
+    assert_eq!(begin, 3 + first.len() as u64);
+    assert_eq!(second, "00:02.000000-2,EXCP,1,Descr=\"a\nb\"\r\n");
+
+    assert!(records.next_record().unwrap().is_none());
+}
+
+#[test]
+fn test_parse_file() {
+    let dir = std::env::temp_dir().join(format!("journal1c_records_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("25011514.log");
+    std::fs::write(
+        &path,
+        "\u{feff}00:01.000000-5,CALL,1,Sql='a\r\nb'\r\n00:03.000000-1,EXCP,1,Descr=x\r\n",
+    )
+    .unwrap();
+
+    let records = parse_file(&path)
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(
+        records[0].time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        "2025-01-15 14:00:01"
+    );
+    assert_eq!(records[0].fields.get("Sql"), Some(&Value::from("a\r\nb")));
+    assert_eq!(records[1].fields.get("event"), Some(&Value::from("EXCP")));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_scan_custom_quotes() {
+    let syntax = Syntax::parse(";", "|").unwrap();
+    let record = b"00:01.000000-1;CALL;1;Sql=|SELECT 'a\r\n";
+    assert_eq!(scan_quotes(record, 0, None, &syntax), Some(b'|'));
+    assert_eq!(
+        scan_quotes(b"FROM ||T|||\r\n", 0, Some(b'|'), &syntax),
+        None
+    );
+    assert_eq!(scan_quotes(record, 0, None, &Syntax::default()), None);
+}
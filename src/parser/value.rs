@@ -23,6 +23,19 @@ impl<'a> Value<'a> {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Числовое значение, в том числе строки, которая разбирается как число.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
     pub fn iter(&self) -> Box<dyn Iterator<Item = &Value> + '_> {
         match self {
             Value::MultiValue(arr) => Box::new(arr.iter()),
@@ -68,7 +81,15 @@ impl<'a> Display for Value<'a> {
             Value::String(s) => write!(f, "{}", s),
             Value::Number(n) => write!(f, "{}", n),
             Value::DateTime(dt) => write!(f, "{}", dt),
-            Value::MultiValue(arr) => write!(f, "{:?}", arr),
+            Value::MultiValue(arr) => {
+                for (index, value) in arr.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -115,19 +136,14 @@ impl<'a> PartialOrd<String> for Value<'a> {
 
 impl<'a> PartialEq<f64> for Value<'a> {
     fn eq(&self, other: &f64) -> bool {
-        match self {
-            Value::Number(n) => n == other,
-            _ => false,
-        }
+        self.as_number() == Some(*other)
     }
 }
 
+// Строковое значение, похожее на число, сравнивается как число
 impl<'a> PartialOrd<f64> for Value<'a> {
     fn partial_cmp(&self, other: &f64) -> Option<std::cmp::Ordering> {
-        match self {
-            Value::Number(n) => n.partial_cmp(other),
-            _ => None,
-        }
+        self.as_number()?.partial_cmp(other)
     }
 }
 
@@ -148,3 +164,17 @@ impl<'a> PartialOrd<NaiveDateTime> for Value<'a> {
         }
     }
 }
+
+#[test]
+fn test_multi_value_display() {
+    let value = Value::MultiValue(vec![Value::from("a"), Value::from("b"), Value::from("c")]);
+    assert_eq!(value.to_string(), "a\nb\nc");
+}
+
+#[test]
+fn test_numeric_string_comparison() {
+    let value = Value::String(Cow::from(" 150 "));
+    assert!(value > 100.0);
+    assert!(value == 150.0);
+    assert_eq!(Value::from("abc").partial_cmp(&100.0), None);
+}
@@ -0,0 +1,15 @@
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+    static ref WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+/// Запоминает предупреждение парсера. Во время работы интерфейса вывод
+/// в stderr не виден, поэтому предупреждения печатаются после выхода.
+pub fn warn<T: Into<String>>(message: T) {
+    WARNINGS.lock().unwrap().push(message.into());
+}
+
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap())
+}
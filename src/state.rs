@@ -0,0 +1,283 @@
+use crate::util::{config_dir, parse_key_values};
+use chrono::NaiveDateTime;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::PathBuf,
+};
+
+/// Состояние интерфейса, которое сохраняется между запусками.
+#[derive(Debug, Default, PartialEq)]
+pub struct UiState {
+    pub filter: String,
+    pub search_visible: bool,
+    /// Номер выделенной записи в исходных данных (не в отфильтрованных).
+    pub selected: Option<usize>,
+    /// Ширины колонок таблицы в процентах, измененные клавишей `W`.
+    pub widths: Vec<u16>,
+}
+
+impl UiState {
+    /// Загружает сохраненное состояние. При любой ошибке возвращает состояние по умолчанию.
+    pub fn load() -> Self {
+        state_path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|data| Self::parse(&data))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = state_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "config dir not found"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.serialize())
+    }
+
+    fn parse(data: &str) -> Self {
+        let values = parse_key_values(data).unwrap_or_default();
+        UiState {
+            filter: values.get("filter").cloned().unwrap_or_default(),
+            search_visible: values.get("search_visible").map(String::as_str) == Some("true"),
+            selected: values.get("selected").and_then(|value| value.parse().ok()),
+            widths: values
+                .get("widths")
+                .and_then(|value| {
+                    value
+                        .split(',')
+                        .map(|width| width.trim().parse().ok())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn serialize(&self) -> String {
+        let mut data = format!(
+            "filter={}\nsearch_visible={}\n",
+            self.filter.trim(),
+            self.search_visible
+        );
+        if let Some(selected) = self.selected {
+            data.push_str(&format!("selected={}\n", selected));
+        }
+        if !self.widths.is_empty() {
+            let widths = self.widths.iter().map(u16::to_string).collect::<Vec<_>>();
+            data.push_str(&format!("widths={}\n", widths.join(",")));
+        }
+        data
+    }
+}
+
+fn state_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("state"))
+}
+
+fn queries_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("queries"))
+}
+
+fn watermarks_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("watermarks"))
+}
+
+fn load_queries() -> BTreeMap<String, String> {
+    queries_path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|data| parse_key_values(&data).ok())
+        .map(|queries| queries.into_iter().collect())
+        .unwrap_or_default()
+}
+
+/// Строки `key=value` файлов `queries` и `watermarks`.
+fn serialize_values(values: &BTreeMap<String, String>) -> String {
+    values
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
+/// Запрос, сохраненный командой `:save <name>`.
+pub fn saved_query(name: &str) -> Option<String> {
+    load_queries().remove(name.trim())
+}
+
+/// Переменная окружения с фильтром по умолчанию, важнее ключа `default_filter`.
+const DEFAULT_FILTER_VAR: &str = "JOURNAL1C_FILTER";
+
+/// Фильтр, с которым программа запускается, если нет сохраненного:
+/// переменная `JOURNAL1C_FILTER` или ключ `default_filter` файла `config`.
+pub fn default_filter() -> Option<String> {
+    let config = config_dir()
+        .and_then(|dir| std::fs::read_to_string(dir.join("config")).ok())
+        .unwrap_or_default();
+    parse_default_filter(std::env::var(DEFAULT_FILTER_VAR).ok(), &config)
+}
+
+fn parse_default_filter(env: Option<String>, config: &str) -> Option<String> {
+    let filter = match env {
+        Some(filter) => filter,
+        None => parse_key_values(config).ok()?.remove("default_filter")?,
+    };
+    // Значение в файле можно взять в апострофы: default_filter = 'WHERE event != "PING"'
+    let filter = filter.trim();
+    let filter = match filter.strip_prefix('\'').and_then(|f| f.strip_suffix('\'')) {
+        Some(filter) => filter.trim(),
+        None => filter,
+    };
+    (!filter.is_empty()).then(|| filter.to_string())
+}
+
+/// Порядок полей в панели информации по событиям: ключи `fields.<событие>`
+/// файла `config`, например `fields.SDBL = Sql,Rows,Context`.
+pub fn field_order() -> HashMap<String, Vec<String>> {
+    let config = config_dir()
+        .and_then(|dir| std::fs::read_to_string(dir.join("config")).ok())
+        .unwrap_or_default();
+    parse_field_order(&config)
+}
+
+fn parse_field_order(config: &str) -> HashMap<String, Vec<String>> {
+    parse_key_values(config)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let event = key.strip_prefix("fields.")?.trim();
+            let fields = value
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(String::from)
+                .collect();
+            (!event.is_empty()).then(|| (event.to_string(), fields))
+        })
+        .collect()
+}
+
+/// Сохраняет запрос под именем `name`, заменяя ранее сохраненный.
+pub fn save_query(name: &str, query: &str) -> io::Result<()> {
+    let name = name.trim();
+    if name.is_empty() || name.contains('=') || name.starts_with('#') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid query name `{}`", name),
+        ));
+    }
+
+    let path = queries_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "config dir not found"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut queries = load_queries();
+    queries.insert(name.to_string(), query.trim().to_string());
+    std::fs::write(path, serialize_values(&queries))
+}
+
+/// Формат времени в файле `watermarks`, с микросекундами, как в записях журнала.
+const WATERMARK_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+
+/// Время последней записи, загруженной в прошлый запуск с `--since-last`
+/// из того же источника (`Source::to_string`).
+pub fn load_watermark(source: &str) -> Option<NaiveDateTime> {
+    let data = watermarks_path().and_then(|path| std::fs::read_to_string(path).ok())?;
+    parse_watermark(&data, source)
+}
+
+fn parse_watermark(data: &str, source: &str) -> Option<NaiveDateTime> {
+    let value = parse_key_values(data).ok()?.remove(source.trim())?;
+    NaiveDateTime::parse_from_str(&value, WATERMARK_FORMAT).ok()
+}
+
+/// Сохраняет время последней загруженной записи источника `source`, не трогая остальные.
+pub fn save_watermark(source: &str, time: NaiveDateTime) -> io::Result<()> {
+    let path = watermarks_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "config dir not found"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut watermarks = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|data| parse_key_values(&data).ok())
+        .map(|values| values.into_iter().collect::<BTreeMap<_, _>>())
+        .unwrap_or_default();
+    watermarks.insert(
+        source.trim().to_string(),
+        time.format(WATERMARK_FORMAT).to_string(),
+    );
+    std::fs::write(path, serialize_values(&watermarks))
+}
+
+#[test]
+fn test_state_round_trip() {
+    let state = UiState {
+        filter: r#"WHERE event = "EXCP" AND process = "a=b""#.to_string(),
+        search_visible: true,
+        selected: Some(42),
+        widths: vec![20, 50, 30],
+    };
+    assert_eq!(UiState::parse(&state.serialize()), state);
+    assert!(UiState::parse("widths=20,x").widths.is_empty());
+    assert_eq!(UiState::parse("garbage"), UiState::default());
+}
+
+#[test]
+fn test_queries_round_trip() {
+    let mut queries = BTreeMap::new();
+    queries.insert(
+        String::from("excp"),
+        String::from(r#"WHERE event = "EXCP""#),
+    );
+    queries.insert(
+        String::from("slow"),
+        String::from("WHERE duration >= 1000000"),
+    );
+
+    let parsed = parse_key_values(&serialize_values(&queries)).unwrap();
+    assert_eq!(parsed.into_iter().collect::<BTreeMap<_, _>>(), queries);
+}
+
+#[test]
+fn test_default_filter() {
+    let config = "# noise\ndefault_filter = 'WHERE event != \"PING\"'\n";
+    assert_eq!(
+        parse_default_filter(None, config).as_deref(),
+        Some(r#"WHERE event != "PING""#)
+    );
+    assert_eq!(
+        parse_default_filter(Some("WHERE time > 'now-1h'".into()), config).as_deref(),
+        Some("WHERE time > 'now-1h'")
+    );
+    assert_eq!(parse_default_filter(Some(" ".into()), config), None);
+    assert_eq!(parse_default_filter(None, "theme=dark"), None);
+}
+
+#[test]
+fn test_watermark() {
+    let time =
+        NaiveDateTime::parse_from_str("2022-08-02 14:05:12.345678", WATERMARK_FORMAT).unwrap();
+    let mut watermarks = BTreeMap::new();
+    watermarks.insert(
+        String::from("/var/log/1c"),
+        time.format(WATERMARK_FORMAT).to_string(),
+    );
+    watermarks.insert(String::from("stdin"), String::from("garbage"));
+
+    let data = serialize_values(&watermarks);
+    assert_eq!(parse_watermark(&data, "/var/log/1c"), Some(time));
+    assert_eq!(parse_watermark(&data, "stdin"), None);
+    assert_eq!(parse_watermark(&data, "/var/log/other"), None);
+}
+
+#[test]
+fn test_field_order() {
+    let config =
+        "default_filter = 'WHERE event = \"SDBL\"'\nfields.SDBL = Sql, Rows,,Context\nfields.=x\n";
+    let order = parse_field_order(config);
+    assert_eq!(order.len(), 1);
+    assert_eq!(order["SDBL"], ["Sql", "Rows", "Context"]);
+    assert!(parse_field_order("broken").is_empty());
+}
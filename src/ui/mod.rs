@@ -1,3 +1,4 @@
 pub mod index;
 pub mod model;
+pub mod theme;
 pub mod widgets;
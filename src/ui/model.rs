@@ -1,7 +1,68 @@
 use crate::{parser::Value, ui::index::ModelIndex};
+use chrono::{Duration, NaiveDateTime};
 use std::{any::Any, borrow::Cow, fmt::Display};
 use tui::text::Text;
 
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub scanned: usize,
+    pub total: usize,
+}
+
+/// Количество записей в равных интервалах времени от `from` до `to` включительно.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Density {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub counts: Vec<u64>,
+}
+
+impl Density {
+    /// Распределяет пары (время, количество записей), упорядоченные по времени,
+    /// по `buckets` интервалам.
+    pub fn new(
+        times: impl DoubleEndedIterator<Item = (NaiveDateTime, u64)> + Clone,
+        buckets: usize,
+    ) -> Option<Density> {
+        let from = times.clone().next()?.0;
+        let to = times.clone().next_back()?.0;
+        if buckets == 0 {
+            return None;
+        }
+
+        let mut density = Density {
+            from,
+            to,
+            counts: vec![0; buckets],
+        };
+        for (time, weight) in times {
+            let bucket = density.bucket(time);
+            density.counts[bucket] += weight;
+        }
+        Some(density)
+    }
+
+    fn span(&self) -> i128 {
+        (self.to - self.from).num_microseconds().unwrap_or(i64::MAX) as i128 + 1
+    }
+
+    /// Номер интервала, в который попадает `time`.
+    pub fn bucket(&self, time: NaiveDateTime) -> usize {
+        let offset = (time - self.from).num_microseconds().unwrap_or(0).max(0) as i128;
+        let bucket = offset * self.counts.len() as i128 / self.span();
+        (bucket as usize).min(self.counts.len().saturating_sub(1))
+    }
+
+    /// Время начала интервала `bucket`.
+    pub fn bucket_time(&self, bucket: usize) -> NaiveDateTime {
+        let buckets = self.counts.len().max(1) as i128;
+        let bucket = bucket.min(self.counts.len().saturating_sub(1)) as i128;
+        // Округление вверх: первая микросекунда, которая попадает в интервал
+        let offset = (bucket * self.span() + buckets - 1) / buckets;
+        self.from + Duration::microseconds(offset as i64)
+    }
+}
+
 #[derive(Default)]
 pub struct Column<'a> {
     pub text: Text<'a>,
@@ -16,7 +77,67 @@ pub trait DataModel {
 
     fn header_data(&self, column: usize) -> Option<Cow<'_, str>>;
 
-    fn data(&self, index: ModelIndex) -> Option<Value>;
+    fn data(&self, index: ModelIndex) -> Option<Value<'static>>;
+
+    /// Текст ячейки для отображения в таблице.
+    fn display(&self, index: ModelIndex) -> Option<String> {
+        self.data(index).map(|value| value.to_string())
+    }
+
+    /// Идентификатор записи, не меняющийся при смене фильтра.
+    fn row_id(&self, row: usize) -> Option<usize> {
+        (row < self.rows()).then_some(row)
+    }
+
+    /// Текущая позиция записи с идентификатором `id`.
+    fn find_row(&self, id: usize) -> Option<usize> {
+        (id < self.rows()).then_some(id)
+    }
+
+    /// Состояние фоновой загрузки. `None`, если модель загружена полностью.
+    fn progress(&self) -> Option<Progress> {
+        None
+    }
+
+    /// Загружены не все данные из-за ограничения на количество строк.
+    fn truncated(&self) -> bool {
+        false
+    }
+
+    /// Оставлять ли слева от таблицы поле под пометки строк.
+    fn has_markers(&self) -> bool {
+        false
+    }
+
+    /// Пометка строки в левом поле таблицы, например для записи, разобранной не полностью.
+    fn marker(&self, _row: usize) -> Option<char> {
+        None
+    }
+
+    /// Распределение записей по `buckets` интервалам времени.
+    fn density(&self, _buckets: usize) -> Option<Density> {
+        None
+    }
+
+    /// Текст строки для поиска (`/` в таблице). По умолчанию - ячейки через пробел.
+    fn row_text(&self, row: usize) -> Option<String> {
+        if row >= self.rows() {
+            return None;
+        }
+
+        let cells = (0..self.cols())
+            .map(|column| {
+                self.display(ModelIndex::new(row, column))
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        Some(cells.join(" "))
+    }
+
+    /// Пояснение, которое показывается вместо пустой таблицы.
+    fn empty_text(&self) -> Option<String> {
+        None
+    }
 
     fn as_any(&self) -> &dyn Any {
         &()
@@ -44,3 +165,26 @@ impl<T: Display> DataModel for Vec<T> {
         self.get(index.row()).map(|s| Value::from(s.to_string()))
     }
 }
+
+#[test]
+fn test_density_buckets() {
+    let from = chrono::NaiveDate::from_ymd_opt(2022, 8, 2)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap();
+    let times = [0, 1, 2, 59, 60, 119]
+        .map(|seconds| (from + Duration::seconds(seconds), 1))
+        .to_vec();
+
+    let density = Density::new(times.iter().copied(), 2).unwrap();
+    assert_eq!(density.counts, vec![4, 2]);
+    assert_eq!(density.bucket_time(0), from);
+    assert_eq!(density.bucket(density.bucket_time(1)), 1);
+    assert_eq!(
+        density.bucket(density.bucket_time(1) - Duration::microseconds(1)),
+        0
+    );
+
+    assert_eq!(Density::new(times[..0].iter().copied(), 2), None);
+    assert_eq!(Density::new(times.iter().copied(), 0), None);
+}
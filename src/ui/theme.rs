@@ -0,0 +1,187 @@
+use crate::{
+    parser::warnings::warn,
+    ui::widgets::TableViewStyle,
+    util::{config_dir, parse_key_values},
+};
+use std::collections::HashMap;
+use tui::style::{Color, Style};
+
+/// Цвета интерфейса. Читаются из файла `theme` в каталоге настроек
+/// (строки `ключ=цвет`); без файла используются цвета по умолчанию.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub header_fg: Color,
+    pub header_bg: Color,
+    pub selection_fg: Color,
+    pub selection_bg: Color,
+    pub highlight_fg: Color,
+    pub highlight_bg: Color,
+    pub bookmark_bg: Color,
+    /// Строка, найденная поиском `/` в таблице.
+    pub match_fg: Color,
+    pub match_bg: Color,
+    pub focus_border: Color,
+    /// Выделенное поле в панели информации.
+    pub info_selection: Color,
+    /// Подписи в строке состояния и клавиши в подсказках и справке.
+    pub label_fg: Color,
+    /// Значения в строке состояния, действия клавиш и имена полей в окнах.
+    pub value_fg: Color,
+    /// Цвет текста строк таблицы по событию (`event.EXCP=light_red`).
+    pub event_colors: HashMap<String, Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            header_fg: Color::Black,
+            header_bg: Color::Green,
+            selection_fg: Color::Black,
+            selection_bg: Color::White,
+            highlight_fg: Color::Black,
+            highlight_bg: Color::Yellow,
+            bookmark_bg: Color::Blue,
+            match_fg: Color::Black,
+            match_bg: Color::LightCyan,
+            focus_border: Color::LightYellow,
+            info_selection: Color::LightMagenta,
+            label_fg: Color::White,
+            value_fg: Color::LightCyan,
+            event_colors: [
+                ("EXCP", Color::LightRed),
+                ("QERR", Color::LightRed),
+                ("TDEADLOCK", Color::LightRed),
+                ("TTIMEOUT", Color::Yellow),
+                ("ATTN", Color::Yellow),
+            ]
+            .into_iter()
+            .map(|(event, color)| (event.to_string(), color))
+            .collect(),
+        }
+    }
+}
+
+impl Theme {
+    /// Тема из каталога настроек. Ошибки в файле выводятся предупреждением.
+    pub fn load() -> Theme {
+        let path = match config_dir() {
+            Some(dir) => dir.join("theme"),
+            None => return Theme::default(),
+        };
+
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(_) => return Theme::default(),
+        };
+        match Theme::parse(&data) {
+            Ok(theme) => theme,
+            Err(e) => {
+                warn(format!("{}: {}", path.display(), e));
+                Theme::default()
+            }
+        }
+    }
+
+    /// Разбирает строки `ключ=цвет`. Не указанные ключи берутся из темы по умолчанию.
+    pub fn parse(data: &str) -> Result<Theme, String> {
+        let mut theme = Theme::default();
+        for (key, value) in parse_key_values(data).map_err(|e| e.to_string())? {
+            let color = parse_color(&value).ok_or(format!("invalid color `{}`", value))?;
+            match key.as_str() {
+                "header_fg" => theme.header_fg = color,
+                "header_bg" => theme.header_bg = color,
+                "selection_fg" => theme.selection_fg = color,
+                "selection_bg" => theme.selection_bg = color,
+                "highlight_fg" => theme.highlight_fg = color,
+                "highlight_bg" => theme.highlight_bg = color,
+                "bookmark_bg" => theme.bookmark_bg = color,
+                "match_fg" => theme.match_fg = color,
+                "match_bg" => theme.match_bg = color,
+                "focus_border" => theme.focus_border = color,
+                "info_selection" => theme.info_selection = color,
+                "label_fg" => theme.label_fg = color,
+                "value_fg" => theme.value_fg = color,
+                _ if key.starts_with("event.") && key.len() > "event.".len() => {
+                    theme
+                        .event_colors
+                        .insert(key["event.".len()..].to_string(), color);
+                }
+                _ => return Err(format!("unknown key `{}`", key)),
+            }
+        }
+        Ok(theme)
+    }
+
+    pub fn table_style(&self) -> TableViewStyle {
+        TableViewStyle::default()
+            .header_style(Style::default().fg(self.header_fg).bg(self.header_bg))
+            .selected_row_style(Style::default().fg(self.selection_fg).bg(self.selection_bg))
+            .highlight_style(Style::default().fg(self.highlight_fg).bg(self.highlight_bg))
+            .bookmark_style(Style::default().bg(self.bookmark_bg))
+            .match_style(Style::default().fg(self.match_fg).bg(self.match_bg))
+            .focus_style(self.focus_style())
+            .event_colors(self.event_colors.clone())
+    }
+
+    /// Рамка виджета в фокусе.
+    pub fn focus_style(&self) -> Style {
+        Style::default().fg(self.focus_border)
+    }
+}
+
+/// Цвет по имени (`lightyellow`, `light_yellow`), номеру палитры (`208`) или `#rrggbb`.
+fn parse_color(value: &str) -> Option<Color> {
+    let value = value.trim().to_lowercase().replace(['_', '-', ' '], "");
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    if let Ok(index) = value.parse::<u8>() {
+        return Some(Color::Indexed(index));
+    }
+
+    Some(match value.as_str() {
+        "reset" | "default" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    })
+}
+
+#[test]
+fn test_theme_parse() {
+    let theme = Theme::parse("# light terminal\nheader_fg=White\nheader_bg=dark_gray\nfocus_border=#0000ff\nselection_bg=250\n").unwrap();
+    assert_eq!(theme.header_fg, Color::White);
+    assert_eq!(theme.header_bg, Color::DarkGray);
+    assert_eq!(theme.focus_border, Color::Rgb(0, 0, 255));
+    assert_eq!(theme.selection_bg, Color::Indexed(250));
+    assert_eq!(theme.highlight_bg, Theme::default().highlight_bg);
+    assert_eq!(Theme::parse("value_fg=blue").unwrap().value_fg, Color::Blue);
+
+    let theme = Theme::parse("event.SDBL=cyan\nevent.EXCP=default\n").unwrap();
+    assert_eq!(theme.event_colors["SDBL"], Color::Cyan);
+    assert_eq!(theme.event_colors["EXCP"], Color::Reset);
+    assert_eq!(theme.event_colors["TDEADLOCK"], Color::LightRed);
+    assert!(Theme::parse("event.=red").is_err());
+
+    assert!(Theme::parse("header_fg=purple").is_err());
+    assert!(Theme::parse("header=white").is_err());
+    assert!(Theme::parse("focus_border=#12345").is_err());
+}
@@ -0,0 +1,104 @@
+use crate::ui::{model::DataModel, widgets::WidgetExt};
+use chrono::NaiveDateTime;
+use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
+use std::{cell::RefCell, rc::Rc};
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Style},
+    widgets::{Sparkline, Widget},
+};
+
+/// Однострочная гистограмма плотности отфильтрованных записей по времени.
+pub struct DensityView {
+    model: Option<Rc<RefCell<dyn DataModel>>>,
+
+    width: u16,
+    height: u16,
+
+    on_bucket_clicked: Box<dyn FnMut(NaiveDateTime) + 'static>,
+}
+
+impl DensityView {
+    pub fn new() -> Self {
+        DensityView {
+            model: None,
+            width: 0,
+            height: 0,
+            on_bucket_clicked: Box::new(|_| {}),
+        }
+    }
+
+    pub fn set_model(&mut self, model: Rc<RefCell<dyn DataModel>>) {
+        self.model = Some(model);
+    }
+
+    pub fn widget(&self) -> impl Widget + '_ {
+        Renderer(self)
+    }
+
+    // Events
+    /// Щелчок по столбцу гистограммы. Передается время начала интервала.
+    pub fn on_bucket_clicked<F: FnMut(NaiveDateTime) + 'static>(&mut self, f: F) {
+        self.on_bucket_clicked = Box::new(f);
+    }
+
+    fn emit_bucket_clicked(&mut self, time: NaiveDateTime) {
+        (self.on_bucket_clicked)(time);
+    }
+}
+
+impl Default for DensityView {
+    fn default() -> Self {
+        DensityView::new()
+    }
+}
+
+impl WidgetExt for DensityView {
+    fn mouse_event(&mut self, event: MouseEvent) {
+        if let MouseEventKind::Down(MouseButton::Left) = event.kind {
+            let density = self
+                .model
+                .as_ref()
+                .and_then(|model| model.borrow().density(self.width as usize));
+            if let Some(density) = density {
+                self.emit_bucket_clicked(density.bucket_time(event.column as usize));
+            }
+        }
+    }
+
+    fn resize(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+    }
+
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn height(&self) -> u16 {
+        self.height
+    }
+}
+
+struct Renderer<'a>(&'a DensityView);
+
+impl<'a> Widget for Renderer<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.area() == 0 {
+            return;
+        }
+
+        let density = self
+            .0
+            .model
+            .as_ref()
+            .and_then(|model| model.borrow().density(area.width as usize));
+        if let Some(density) = density {
+            Sparkline::default()
+                .data(&density.counts)
+                .style(Style::default().fg(Color::LightCyan))
+                .render(area, buf);
+        }
+    }
+}
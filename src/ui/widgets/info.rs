@@ -1,18 +1,47 @@
 use crate::{
-    parser::{FieldMap, Value},
-    ui::widgets::WidgetExt,
+    parser::{FieldMap, LogString, Value},
+    ui::widgets::{KeyBinding, WidgetExt},
     util::sub_strings,
 };
 use cli_clipboard::{ClipboardContext, ClipboardProvider};
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use std::{fmt::Debug, mem};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind};
+use std::{collections::HashMap, fmt::Debug, mem};
 use tui::{
     buffer::Buffer,
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Style},
-    widgets::{Block, Borders, Widget},
+    widgets::{Block, Borders, Paragraph, Widget, Wrap},
 };
 
+/// Количество строк, на которое смещается выделение при прокрутке колесом мыши.
+const SCROLL_ROWS: usize = 3;
+
+/// Длина строки, начиная с которой в фильтр добавляется поиск подстроки вместо равенства.
+const LONG_VALUE: usize = 64;
+
+/// Оператор условия, добавляемого в фильтр из информационной панели.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterOp {
+    /// `key = value`
+    Equal,
+    /// `key >= value`, для времени
+    GreaterOrEqual,
+    /// `key = /value/`, для длинных и многострочных строк
+    Contains,
+}
+
+impl FilterOp {
+    pub fn for_value(value: &Value) -> Self {
+        match value {
+            Value::DateTime(_) => FilterOp::GreaterOrEqual,
+            Value::String(s) if s.chars().count() > LONG_VALUE || s.contains(['\n', '"']) => {
+                FilterOp::Contains
+            }
+            _ => FilterOp::Equal,
+        }
+    }
+}
+
 struct State {
     pub offset: usize,
     pub index: usize,
@@ -41,15 +70,35 @@ impl Default for State {
 
 pub struct KeyValueView {
     state: State,
+    /// Показываемые поля: те поля `all`, имена которых содержат `key_filter`.
     data: FieldMap<'static>,
+    all: FieldMap<'static>,
+    key_filter: String,
+    /// Ввод фильтра имен полей после `/`.
+    editing_filter: bool,
+    line: Option<LogString>,
+    raw: Option<String>,
+    raw_scroll: u16,
+    /// Поля, которые показываются первыми у записей события (ключи `fields.<событие>`).
+    field_order: HashMap<String, Vec<String>>,
 
     focused: bool,
     visible: bool,
+    focus_style: Style,
+    selection_style: Style,
 
     width: u16,
     height: u16,
 
-    on_add_to_filter: Box<dyn FnMut((String, &Value)) + 'static>,
+    on_add_to_filter: Box<dyn FnMut((String, &Value, FilterOp)) + 'static>,
+    /// Открыть значение во внешней программе (`O`).
+    on_open: Box<dyn FnMut(String) + 'static>,
+}
+
+impl Default for KeyValueView {
+    fn default() -> Self {
+        KeyValueView::new()
+    }
 }
 
 impl KeyValueView {
@@ -57,15 +106,31 @@ impl KeyValueView {
         Self {
             state: State::default(),
             data: FieldMap::new(),
+            all: FieldMap::new(),
+            key_filter: String::new(),
+            editing_filter: false,
+            line: None,
+            raw: None,
+            raw_scroll: 0,
+            field_order: HashMap::new(),
             focused: false,
             visible: false,
+            focus_style: Style::default().fg(Color::LightYellow),
+            selection_style: Style::default().fg(Color::LightMagenta),
             width: 0,
             height: 0,
 
             on_add_to_filter: Box::new(|_| {}),
+            on_open: Box::new(|_| {}),
         }
     }
 
+    /// Стили рамки в фокусе и выделенного поля.
+    pub fn set_style(&mut self, focus: Style, selection: Style) {
+        self.focus_style = focus;
+        self.selection_style = selection;
+    }
+
     fn calculate_row_bounds(&mut self) {
         let offset = self.state.offset.min(self.data.len().saturating_sub(1));
         let inner_height = self.height.saturating_sub(3) as usize;
@@ -101,52 +166,138 @@ impl KeyValueView {
         self.calculate_row_bounds();
     }
 
-    pub fn update_state(&mut self) {
+    /// Ширина колонки значений, совпадающая с разметкой в `Renderer`.
+    fn value_width(&self) -> u16 {
         let rects = Layout::default()
             .constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref())
             .direction(Direction::Horizontal)
             .split(Rect {
                 x: 1,
                 y: 1,
-                width: self.width.saturating_sub(1),
-                height: self.height.saturating_sub(1),
+                width: self.width.saturating_sub(2),
+                height: self.height.saturating_sub(2),
             });
+        rects[1].width
+    }
 
-        for (_, v) in self.data.iter() {
-            let v = v.to_string();
-            let splits = sub_strings(v.as_str(), rects[1].width as usize);
-            self.state.rows_size.push(splits.len().max(1));
+    /// Пересчитывает высоту каждой строки для текущей ширины виджета.
+    pub fn update_state(&mut self) {
+        let width = self.value_width() as usize;
+        self.state.rows_size = self
+            .data
+            .iter()
+            .map(|(_, v)| match width {
+                // Виджет еще не размещен, высоты пересчитаются в resize
+                0 => 1,
+                _ => sub_strings(v.to_string().as_str(), width).len().max(1),
+            })
+            .collect();
+    }
+
+    /// Порядок полей по событиям. Поля, которых нет в списке, показываются следом
+    /// в порядке записи.
+    pub fn set_field_order(&mut self, order: HashMap<String, Vec<String>>) {
+        self.field_order = order;
+    }
+
+    pub fn set_data(&mut self, data: FieldMap<'static>, line: Option<LogString>) {
+        let order = data
+            .get("event")
+            .and_then(|event| self.field_order.get(&event.to_string()));
+        self.all = match order {
+            Some(order) => data.reorder(order),
+            None => data,
+        };
+        self.line = line;
+        self.raw_scroll = 0;
+        if self.raw.is_some() {
+            self.raw = Some(
+                self.line
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+            );
         }
+
+        self.apply_key_filter();
     }
 
-    pub fn set_data(&mut self, data: FieldMap<'static>) {
-        self.data = data;
+    /// Оставляет поля, имена которых содержат `filter` без учета регистра.
+    /// Пустая строка показывает все поля. Фильтр сохраняется при смене записи.
+    pub fn set_key_filter(&mut self, filter: String) {
+        self.key_filter = filter;
+        self.apply_key_filter();
+    }
+
+    /// Вводится фильтр имен полей: остальные клавиши не обрабатываются.
+    pub fn editing_filter(&self) -> bool {
+        self.editing_filter
+    }
 
-        self.state.rows_size.clear();
+    fn apply_key_filter(&mut self) {
+        let filter = self.key_filter.to_lowercase();
+        self.data = self
+            .all
+            .filter_keys(|key| key.to_lowercase().contains(&filter));
         self.state.offset = 0;
         self.state.index = 0;
-
         self.update_state();
+        self.calculate_row_bounds();
+    }
+
+    /// Переключает отображение между разобранными полями и исходной строкой лога.
+    pub fn toggle_raw(&mut self) {
+        self.raw_scroll = 0;
+        self.raw = match self.raw {
+            Some(_) => None,
+            None => Some(
+                self.line
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+            ),
+        };
     }
 
     pub fn widget(&self) -> impl Widget + '_ {
         Renderer(&self)
     }
 
-    pub fn on_add_to_filter(&mut self, callback: impl FnMut((String, &Value)) + 'static) {
+    pub fn on_add_to_filter(&mut self, callback: impl FnMut((String, &Value, FilterOp)) + 'static) {
         self.on_add_to_filter = Box::new(callback);
     }
 
+    pub fn on_open(&mut self, callback: impl FnMut(String) + 'static) {
+        self.on_open = Box::new(callback);
+    }
+
+    /// Передает выделенное значение (или всю запись в режиме `R`) в `on_open`.
+    fn emit_open(&mut self) {
+        let value = match &self.raw {
+            Some(raw) => Some(raw.clone()),
+            None => self
+                .data
+                .get_index(self.state.index)
+                .map(|(_, value)| value.to_string()),
+        };
+        if let Some(value) = value {
+            (self.on_open)(value);
+        }
+    }
+
     fn emit_add_to_filter(&mut self) {
         let mut on_add_to_filter = mem::replace(&mut self.on_add_to_filter, Box::new(|_| {}));
-        on_add_to_filter(self.data.get_index(self.state.index).unwrap());
+        if let Some((key, value)) = self.data.get_index(self.state.index) {
+            on_add_to_filter((key, value, FilterOp::for_value(value)));
+        }
         self.on_add_to_filter = on_add_to_filter;
     }
 }
 
 impl WidgetExt for KeyValueView {
     fn set_focus(&mut self, focus: bool) {
-        self.focused = focus
+        self.focused = focus;
+        self.editing_filter &= focus;
     }
 
     fn focused(&self) -> bool {
@@ -161,23 +312,111 @@ impl WidgetExt for KeyValueView {
         self.visible = visible
     }
 
-    fn key_press_event(&mut self, event: KeyEvent) {
+    fn key_bindings(&self) -> &'static [KeyBinding] {
+        const KEYS: &[KeyBinding] = &[
+            KeyBinding::new("Up/Down", "Select field"),
+            KeyBinding::hint("/", "Filter fields"),
+            KeyBinding::new("Esc", "Clear fields filter"),
+            KeyBinding::hint("C", "Copy"),
+            KeyBinding::hint("F", "Add to filter"),
+            KeyBinding::hint("R", "Raw line"),
+            KeyBinding::hint("O", "Open in pager"),
+            KeyBinding::hint("PageUp", "Go to begin"),
+            KeyBinding::hint("PageDown", "Go to end"),
+        ];
+        KEYS
+    }
+
+    fn key_press_event(&mut self, event: KeyEvent) -> bool {
+        if self.editing_filter {
+            match event.code {
+                KeyCode::Char(c) => {
+                    let mut filter = mem::take(&mut self.key_filter);
+                    filter.push(c);
+                    self.set_key_filter(filter);
+                }
+                KeyCode::Backspace => {
+                    let mut filter = mem::take(&mut self.key_filter);
+                    filter.pop();
+                    self.set_key_filter(filter);
+                }
+                KeyCode::Enter => self.editing_filter = false,
+                KeyCode::Esc => {
+                    self.editing_filter = false;
+                    self.set_key_filter(String::new());
+                }
+                _ => return false,
+            }
+            return true;
+        }
+
+        if self.raw.is_some() {
+            match event {
+                KeyEvent {
+                    code: KeyCode::Char('r'),
+                    modifiers: KeyModifiers::NONE,
+                    ..
+                } => self.toggle_raw(),
+                KeyEvent {
+                    code: KeyCode::Down,
+                    modifiers: KeyModifiers::NONE,
+                    ..
+                } => self.raw_scroll = self.raw_scroll.saturating_add(1),
+                KeyEvent {
+                    code: KeyCode::Up,
+                    modifiers: KeyModifiers::NONE,
+                    ..
+                } => self.raw_scroll = self.raw_scroll.saturating_sub(1),
+                KeyEvent {
+                    code: KeyCode::PageUp,
+                    modifiers: KeyModifiers::NONE,
+                    ..
+                } => self.raw_scroll = 0,
+                KeyEvent {
+                    code: KeyCode::Char('o'),
+                    modifiers: KeyModifiers::NONE,
+                    ..
+                } => self.emit_open(),
+                _ => return false,
+            }
+            return true;
+        }
+
         match event {
+            KeyEvent {
+                code: KeyCode::Char('r'),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => {
+                self.toggle_raw();
+            }
             KeyEvent {
                 code: KeyCode::Down,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
                 self.next();
             }
             KeyEvent {
                 code: KeyCode::Up,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
                 self.prev();
             }
+            KeyEvent {
+                code: KeyCode::Char('/'),
+                ..
+            } => self.editing_filter = true,
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: KeyModifiers::NONE,
+                ..
+            } if !self.key_filter.is_empty() => self.set_key_filter(String::new()),
             KeyEvent {
                 code: KeyCode::Char('c'),
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
                 if let Ok(mut ctx) = ClipboardContext::new() {
                     if let Some((_, value)) = self.data.get_index(self.state.index) {
@@ -188,14 +427,21 @@ impl WidgetExt for KeyValueView {
             KeyEvent {
                 code: KeyCode::Char('f'),
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
-                if self.data.len() > 0 {
+                if !self.data.is_empty() {
                     self.emit_add_to_filter();
                 }
             }
+            KeyEvent {
+                code: KeyCode::Char('o'),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.emit_open(),
             KeyEvent {
                 code: KeyCode::PageUp,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
                 self.state.index = 0;
                 self.state.offset = 0;
@@ -204,18 +450,31 @@ impl WidgetExt for KeyValueView {
             KeyEvent {
                 code: KeyCode::PageDown,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
                 self.state.index = self.data.len().saturating_sub(1);
                 self.calculate_row_bounds();
             }
-            _ => {}
+            _ => return false,
+        }
+        true
+    }
+
+    fn mouse_event(&mut self, event: MouseEvent) {
+        let code = match event.kind {
+            MouseEventKind::ScrollUp => KeyCode::Up,
+            MouseEventKind::ScrollDown => KeyCode::Down,
+            _ => return,
+        };
+
+        for _ in 0..SCROLL_ROWS {
+            self.key_press_event(KeyEvent::new(code, KeyModifiers::NONE));
         }
     }
 
     fn resize(&mut self, width: u16, height: u16) {
         self.width = width;
         self.height = height;
-        self.state.rows_size.clear();
         self.update_state();
         self.calculate_row_bounds();
     }
@@ -238,13 +497,20 @@ impl<'a> Widget for Renderer<'a> {
         }
 
         let block_style = match self.0.focused() {
-            true => Style::default().fg(Color::LightYellow),
+            true => self.0.focus_style,
             false => Style::default(),
         };
         let block = Block::default()
             .borders(Borders::ALL)
             .border_style(block_style)
-            .title("Info");
+            .title(match (&self.0.raw, self.0.editing_filter) {
+                (Some(_), _) => String::from("Info (raw)"),
+                (None, true) => format!("Info | Fields: {}_", self.0.key_filter),
+                (None, false) if !self.0.key_filter.is_empty() => {
+                    format!("Info | Fields: {}", self.0.key_filter)
+                }
+                (None, false) => String::from("Info"),
+            });
 
         let area = {
             let inner_area = block.inner(area);
@@ -252,6 +518,14 @@ impl<'a> Widget for Renderer<'a> {
             inner_area
         };
 
+        if let Some(raw) = &self.0.raw {
+            Paragraph::new(raw.as_str())
+                .wrap(Wrap { trim: false })
+                .scroll((self.0.raw_scroll, 0))
+                .render(area, buf);
+            return;
+        }
+
         let rects = Layout::default()
             .constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref())
             .direction(Direction::Horizontal)
@@ -275,7 +549,7 @@ impl<'a> Widget for Renderer<'a> {
             }
 
             let style = if i == self.0.state.index {
-                Style::default().fg(Color::LightMagenta)
+                self.0.selection_style
             } else {
                 Style::default()
             };
@@ -306,3 +580,79 @@ impl<'a> Widget for Renderer<'a> {
         }
     }
 }
+
+#[test]
+fn test_row_heights_after_first_resize() {
+    let mut view = KeyValueView::new();
+    let mut data = FieldMap::new();
+    data.insert("Context", Value::from("x".repeat(100)));
+    data.insert("process", Value::from("rphost"));
+    view.set_data(data, None);
+    assert_eq!(view.state.rows_size, vec![1, 1]);
+
+    view.resize(40, 10);
+    let width = view.value_width() as usize;
+    assert!(width > 0);
+    assert_eq!(view.state.rows_size, vec![100_usize.div_ceil(width), 1]);
+}
+
+#[test]
+fn test_filter_field_names() {
+    let mut view = KeyValueView::new();
+    let mut data = FieldMap::new();
+    data.insert("Context", Value::from("x"));
+    data.insert("process", Value::from("rphost"));
+    data.insert("processName", Value::from("db"));
+    view.set_data(data, None);
+    view.resize(40, 10);
+
+    let press = |view: &mut KeyValueView, code| {
+        view.key_press_event(KeyEvent::new(code, KeyModifiers::NONE))
+    };
+    press(&mut view, KeyCode::Char('/'));
+    for c in "PROC".chars() {
+        press(&mut view, KeyCode::Char(c));
+    }
+    press(&mut view, KeyCode::Enter);
+    assert_eq!(view.data.len(), 2);
+    assert_eq!(view.state.rows_size.len(), 2);
+
+    // Выделение не выходит за отфильтрованные поля
+    press(&mut view, KeyCode::PageDown);
+    assert_eq!(view.state.index, 1);
+    assert_eq!(
+        view.data.get_index(1).map(|(key, _)| key),
+        Some("processName".into())
+    );
+
+    press(&mut view, KeyCode::Esc);
+    assert_eq!(view.data.len(), 3);
+    assert_eq!(view.state.index, 0);
+}
+
+#[test]
+fn test_field_order() {
+    let mut view = KeyValueView::new();
+    view.set_field_order(HashMap::from([(
+        String::from("SDBL"),
+        vec![
+            String::from("Rows"),
+            String::from("Sql"),
+            String::from("Trans"),
+        ],
+    )]));
+    let record = |event: &'static str| {
+        let mut data = FieldMap::new();
+        data.insert("event", Value::from(event));
+        data.insert("Sql", Value::from("SELECT 1"));
+        data.insert("Context", Value::from("x"));
+        data.insert("Rows", Value::from("1"));
+        data
+    };
+    let keys = |view: &KeyValueView| view.data.keys().map(String::from).collect::<Vec<_>>();
+
+    view.set_data(record("SDBL"), None);
+    assert_eq!(keys(&view), ["Rows", "Sql", "event", "Context"]);
+    view.set_data(record("CALL"), None);
+    assert_eq!(keys(&view), ["event", "Sql", "Context", "Rows"]);
+}
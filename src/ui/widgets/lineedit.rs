@@ -1,4 +1,5 @@
-use crate::ui::widgets::WidgetExt;
+use crate::ui::widgets::{KeyBinding, WidgetExt};
+use cli_clipboard::{ClipboardContext, ClipboardProvider};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use std::{cell::RefCell, mem};
 use tui::{
@@ -14,6 +15,7 @@ pub struct LineEdit {
     text: String,
     cwp: RefCell<(u16, u16, usize)>,
     style: Style,
+    focus_style: Style,
     border_text: String,
 
     visible: bool,
@@ -32,6 +34,7 @@ impl LineEdit {
             text: String::new(),
             cwp: RefCell::new((0, 0, 0)),
             style: Style::default(),
+            focus_style: Style::default().fg(Color::LightYellow),
             border_text: String::new(),
 
             visible: false,
@@ -61,23 +64,20 @@ impl LineEdit {
 
     pub fn scroll_to_end(&self) {
         let width = self.width().saturating_sub(2);
-        let cursor = if self.text.len() as u16 > width {
+        let len = self.text.chars().count();
+        let cursor = if len > width as usize {
             width
         } else {
-            self.text.len() as u16
+            len as u16
         };
-        *self.cwp.borrow_mut() = (
-            cursor,
-            width,
-            self.text.len().saturating_sub(width as usize),
-        );
+        *self.cwp.borrow_mut() = (cursor, width, len.saturating_sub(width as usize));
     }
 
     pub fn scroll(&self, right: bool) {
         let (mut cursor, width, mut position) = *self.cwp.borrow();
         if right {
             // go forward
-            if (cursor as usize + position) < self.text.len() {
+            if (cursor as usize + position) < self.text.chars().count() {
                 if cursor.saturating_add(1) >= width {
                     position = position.saturating_add(1);
                 } else {
@@ -94,6 +94,68 @@ impl LineEdit {
         *self.cwp.borrow_mut() = (cursor, width, position);
     }
 
+    /// Позиция курсора в символах от начала текста.
+    fn index(&self) -> usize {
+        let (cursor, _, position) = *self.cwp.borrow();
+        cursor as usize + position
+    }
+
+    /// Ставит курсор на символ `index`, прокручивая текст только при выходе курсора за края.
+    fn move_to(&self, index: usize) {
+        let (_, width, mut position) = *self.cwp.borrow();
+        let index = index.min(self.text.chars().count());
+        let last = width.saturating_sub(1) as usize;
+        if index < position {
+            position = index;
+        } else if index > position + last {
+            position = index - last;
+        }
+        *self.cwp.borrow_mut() = ((index - position) as u16, width, position);
+    }
+
+    /// Начало предыдущего слова.
+    fn prev_word(&self) -> usize {
+        let chars = self.text.chars().take(self.index()).collect::<Vec<_>>();
+        let end = chars
+            .iter()
+            .rposition(|c| c.is_alphanumeric())
+            .map_or(0, |i| i + 1);
+        chars[..end]
+            .iter()
+            .rposition(|c| !c.is_alphanumeric())
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Конец следующего слова.
+    fn next_word(&self) -> usize {
+        let index = self.index();
+        let mut chars = self.text.chars().skip(index).peekable();
+        let mut next = index;
+        while chars.next_if(|c| !c.is_alphanumeric()).is_some() {
+            next += 1;
+        }
+        while chars.next_if(|c| c.is_alphanumeric()).is_some() {
+            next += 1;
+        }
+        next
+    }
+
+    /// Смещение в байтах символа `index` (или конец текста).
+    fn byte_index(&self, index: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(index)
+            .map_or(self.text.len(), |(byte, _)| byte)
+    }
+
+    /// Вставляет строку в позицию курсора и ставит курсор после нее.
+    pub fn insert_str(&mut self, text: &str) {
+        let index = self.index();
+        self.text.insert_str(self.byte_index(index), text);
+        self.move_to(index + text.chars().count());
+        self.emit_on_changed();
+    }
+
     pub fn widget(&self) -> impl Widget + '_ {
         Renderer(self)
     }
@@ -102,9 +164,9 @@ impl LineEdit {
         self.style = style;
     }
 
-    #[allow(dead_code)]
-    pub fn style(&self) -> Style {
-        self.style
+    /// Стиль рамки в фокусе.
+    pub fn set_focus_style(&mut self, style: Style) {
+        self.focus_style = style;
     }
 
     pub fn set_border_text(&mut self, text: String) {
@@ -148,25 +210,46 @@ impl WidgetExt for LineEdit {
         self.set_visible(false);
     }
 
-    fn key_press_event(&mut self, event: KeyEvent) {
+    fn key_bindings(&self) -> &'static [KeyBinding] {
+        const KEYS: &[KeyBinding] = &[
+            KeyBinding::hint("Ctrl+Backspace", "Clear"),
+            KeyBinding::hint("Ctrl+Y", "Copy"),
+            KeyBinding::new("Left/Right", "Move cursor"),
+            KeyBinding::new("Ctrl+Left/Ctrl+Right", "Previous/next word"),
+            KeyBinding::new("Home/End", "Line start/end"),
+            KeyBinding::new("Backspace/Delete", "Delete char"),
+        ];
+        KEYS
+    }
+
+    fn key_press_event(&mut self, event: KeyEvent) -> bool {
         match event {
+            KeyEvent {
+                code: KeyCode::Char('y'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => {
+                if let Ok(mut ctx) = ClipboardContext::new() {
+                    let _ = ctx.set_contents(self.text.clone());
+                }
+            }
             KeyEvent {
                 code: KeyCode::Char(char),
                 ..
             } => {
-                let (cursor, _, position) = *self.cwp.borrow();
-                self.text.insert(cursor as usize + position, char);
+                let byte = self.byte_index(self.index());
+                self.text.insert(byte, char);
                 self.scroll(true);
                 self.emit_on_changed();
             }
             KeyEvent {
                 code: KeyCode::Backspace,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
-                let (cursor, _, position) = *self.cwp.borrow();
-                let index = cursor as usize + position;
-                if index.saturating_sub(1) != index {
-                    self.text.remove(index - 1);
+                let index = self.index();
+                if index > 0 {
+                    self.text.remove(self.byte_index(index - 1));
                     self.scroll(false);
                     self.emit_on_changed();
                 }
@@ -174,32 +257,59 @@ impl WidgetExt for LineEdit {
             KeyEvent {
                 code: KeyCode::Delete,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
-                let (cursor, _, position) = *self.cwp.borrow();
-                let index = cursor as usize + position;
-                if index < self.text.len() {
-                    self.text.remove(index);
+                let index = self.index();
+                if index < self.text.chars().count() {
+                    self.text.remove(self.byte_index(index));
                     self.emit_on_changed();
                 }
             }
             KeyEvent {
                 code: KeyCode::Right,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => self.scroll(true),
             KeyEvent {
                 code: KeyCode::Left,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => self.scroll(false),
+            KeyEvent {
+                code: KeyCode::Right,
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.move_to(self.next_word()),
+            KeyEvent {
+                code: KeyCode::Left,
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.move_to(self.prev_word()),
+            KeyEvent {
+                code: KeyCode::Home,
+                ..
+            } => self.move_to(0),
+            KeyEvent {
+                code: KeyCode::End, ..
+            } => self.move_to(usize::MAX),
             KeyEvent {
                 code: KeyCode::Backspace,
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 self.text.clear();
                 self.scroll_to_start();
                 self.emit_on_changed();
             }
-            _ => {}
+            _ => return false,
         }
+        true
+    }
+
+    fn paste_event(&mut self, text: &str) {
+        // Переводы строк в однострочном поле заменяются пробелами
+        let text = text.replace("\r\n", " ").replace(['\r', '\n'], " ");
+        self.insert_str(&text);
     }
 
     fn resize(&mut self, width: u16, height: u16) {
@@ -233,7 +343,7 @@ impl<'a> Widget for Renderer<'a> {
         };
 
         let block_style = match self.0.focused() {
-            true => Style::default().fg(Color::LightYellow),
+            true => self.0.focus_style,
             false => Style::default(),
         };
         let block = Block::default()
@@ -288,3 +398,52 @@ impl<'a> Widget for Renderer<'a> {
         *self.0.cwp.borrow_mut() = (cursor, width, position);
     }
 }
+
+#[test]
+fn test_word_jumps_and_paste() {
+    let mut edit = LineEdit::new(String::new());
+    edit.resize(12, 3);
+    edit.set_text(String::from("WHERE event"));
+    assert_eq!(edit.index(), 11);
+
+    edit.key_press_event(KeyEvent::new(KeyCode::Left, KeyModifiers::CONTROL));
+    assert_eq!(edit.index(), 6);
+    edit.key_press_event(KeyEvent::new(KeyCode::Left, KeyModifiers::CONTROL));
+    assert_eq!(edit.index(), 0);
+    edit.key_press_event(KeyEvent::new(KeyCode::Right, KeyModifiers::CONTROL));
+    assert_eq!(edit.index(), 5);
+
+    edit.key_press_event(KeyEvent::new(KeyCode::End, KeyModifiers::NONE));
+    edit.paste_event(" = \"EXCP\"\n");
+    assert_eq!(edit.text(), "WHERE event = \"EXCP\" ");
+    assert_eq!(edit.index(), 21);
+    // Курсор остается в видимой части поля
+    assert_eq!(*edit.cwp.borrow(), (9, 10, 12));
+
+    edit.key_press_event(KeyEvent::new(KeyCode::Home, KeyModifiers::NONE));
+    assert_eq!(*edit.cwp.borrow(), (0, 10, 0));
+}
+
+#[test]
+fn test_edit_cyrillic_in_the_middle() {
+    let mut edit = LineEdit::new(String::new());
+    edit.resize(12, 3);
+    edit.set_text(String::from("Usr = \"Пётр\""));
+    assert_eq!(*edit.cwp.borrow(), (10, 10, 2));
+
+    for _ in 0..3 {
+        edit.key_press_event(KeyEvent::new(KeyCode::Left, KeyModifiers::NONE));
+    }
+    edit.key_press_event(KeyEvent::new(KeyCode::Char('ё'), KeyModifiers::NONE));
+    assert_eq!(edit.text(), "Usr = \"Пёётр\"");
+
+    edit.key_press_event(KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE));
+    assert_eq!(edit.text(), "Usr = \"Пётр\"");
+    edit.key_press_event(KeyEvent::new(KeyCode::Delete, KeyModifiers::NONE));
+    assert_eq!(edit.text(), "Usr = \"Пёр\"");
+
+    edit.key_press_event(KeyEvent::new(KeyCode::End, KeyModifiers::NONE));
+    edit.key_press_event(KeyEvent::new(KeyCode::Right, KeyModifiers::NONE));
+    assert_eq!(edit.index(), 11);
+    assert_eq!(*edit.cwp.borrow(), (9, 10, 2));
+}
@@ -1,13 +1,41 @@
-use crossterm::event::KeyEvent;
+use crossterm::event::{KeyEvent, MouseEvent};
 
+mod density;
 mod info;
 mod lineedit;
 mod table;
 
+pub use density::*;
 pub use info::*;
 pub use lineedit::*;
 pub use table::*;
 
+/// Клавиша для окна справки `?` и строки подсказок внизу экрана.
+pub struct KeyBinding {
+    pub keys: &'static str,
+    pub action: &'static str,
+    /// Показывается в строке подсказок, а не только в справке.
+    pub hint: bool,
+}
+
+impl KeyBinding {
+    pub const fn new(keys: &'static str, action: &'static str) -> Self {
+        KeyBinding {
+            keys,
+            action,
+            hint: false,
+        }
+    }
+
+    pub const fn hint(keys: &'static str, action: &'static str) -> Self {
+        KeyBinding {
+            keys,
+            action,
+            hint: true,
+        }
+    }
+}
+
 pub trait WidgetExt {
     fn set_focus(&mut self, _focus: bool) {}
 
@@ -29,7 +57,21 @@ pub trait WidgetExt {
         self.set_visible(false)
     }
 
-    fn key_press_event(&mut self, _event: KeyEvent) {}
+    /// Обрабатывает клавишу. Возвращает `false`, если виджет ее не обрабатывает.
+    fn key_press_event(&mut self, _event: KeyEvent) -> bool {
+        false
+    }
+
+    /// Клавиши, которые обрабатывает `key_press_event`. Меняется вместе с ним.
+    fn key_bindings(&self) -> &'static [KeyBinding] {
+        &[]
+    }
+
+    /// Вставка из буфера обмена терминала (bracketed paste) одной строкой.
+    fn paste_event(&mut self, _text: &str) {}
+
+    /// Событие мыши. Координаты заданы относительно левого верхнего угла виджета.
+    fn mouse_event(&mut self, _event: MouseEvent) {}
 
     fn resize(&mut self, _width: u16, _height: u16) {}
 
@@ -1,17 +1,46 @@
-use crate::ui::{index::ModelIndex, model::DataModel, widgets::WidgetExt};
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use std::{cell::RefCell, mem, rc::Rc};
+use crate::{
+    parser::{Query, Value},
+    ui::{
+        index::ModelIndex,
+        model::DataModel,
+        widgets::{KeyBinding, WidgetExt},
+    },
+    util::{format_delta, single_line},
+};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use regex::Regex;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    mem,
+    rc::Rc,
+};
 use tui::{
     buffer::Buffer,
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Style},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
     widgets::{Block, Borders, Widget},
 };
 
+/// Количество строк, на которое смещается выделение при прокрутке колесом мыши.
+const SCROLL_ROWS: usize = 3;
+
+/// На сколько процентов ширины таблицы меняется колонка в режиме изменения ширины (`W`).
+const RESIZE_STEP: u16 = 2;
+
+/// Минимальная ширина колонки в процентах при изменении ширины.
+const MIN_COLUMN_PERCENT: u16 = 3;
+
+/// На сколько символов сдвигается текст ячеек клавишами `Left`/`Right`.
+const SCROLL_CHARS: usize = 8;
+
 #[derive(Default)]
 struct State {
     begin: usize,
     index: Option<usize>,
+    // Идентификатор выбранной записи в модели, не зависящий от фильтра
+    id: Option<usize>,
 }
 
 impl State {
@@ -27,50 +56,104 @@ impl State {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct TableViewStyle {
-    common: Style,
     selected_row_style: Style,
     header_style: Style,
+    highlight_style: Style,
+    bookmark_style: Style,
+    /// Строка, найденная поиском `/`.
+    match_style: Style,
+    focus_style: Style,
+    /// Цвет текста невыделенных строк по значению колонки `event`.
+    event_colors: HashMap<String, Color>,
     column_spacing: u16,
 }
 
 impl TableViewStyle {
-    #[allow(dead_code)]
-    pub fn common(mut self, style: Style) -> Self {
-        self.common = style;
-        self
-    }
-
-    #[allow(dead_code)]
     pub fn selected_row_style(mut self, style: Style) -> Self {
         self.selected_row_style = style;
         self
     }
 
-    #[allow(dead_code)]
     pub fn header_style(mut self, style: Style) -> Self {
         self.header_style = style;
         self
     }
+
+    pub fn highlight_style(mut self, style: Style) -> Self {
+        self.highlight_style = style;
+        self
+    }
+
+    pub fn bookmark_style(mut self, style: Style) -> Self {
+        self.bookmark_style = style;
+        self
+    }
+
+    pub fn match_style(mut self, style: Style) -> Self {
+        self.match_style = style;
+        self
+    }
+
+    /// Рамка таблицы в фокусе.
+    pub fn focus_style(mut self, style: Style) -> Self {
+        self.focus_style = style;
+        self
+    }
+
+    pub fn event_colors(mut self, colors: HashMap<String, Color>) -> Self {
+        self.event_colors = colors;
+        self
+    }
 }
 
 impl Default for TableViewStyle {
     fn default() -> Self {
         TableViewStyle {
-            common: Style::default(),
             selected_row_style: Style::default().bg(Color::White).fg(Color::Black),
             header_style: Style::default().bg(Color::Green).fg(Color::Black),
+            highlight_style: Style::default().bg(Color::Yellow).fg(Color::Black),
+            bookmark_style: Style::default().bg(Color::Blue),
+            match_style: Style::default().bg(Color::LightCyan).fg(Color::Black),
+            focus_style: Style::default().fg(Color::LightYellow),
+            event_colors: HashMap::new(),
             column_spacing: 1,
         }
     }
 }
 
+/// Следование за новыми записями, как `tail -f`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Follow {
+    #[default]
+    Off,
+    /// Выделение держится на последней строке.
+    Active,
+    /// Выделение сдвинуто вверх вручную; `End` возобновляет следование.
+    Paused,
+}
+
 pub struct TableView {
     state: State,
     model: Option<Rc<RefCell<dyn DataModel>>>,
     widths: Vec<Constraint>,
     style: TableViewStyle,
+    query: Option<Query>,
+    // Идентификаторы записей модели, поэтому переживают смену фильтра
+    bookmarks: HashSet<usize>,
+    // Идентификатор и время записи, от которой отсчитывается интервал
+    anchor: Option<(usize, Value<'static>)>,
+    follow: Follow,
+    // Запись, о которой последний раз сообщил `on_selection_changed`
+    shown: Option<usize>,
+    // Образец поиска `/` и идентификатор последней найденной записи
+    find: Option<Regex>,
+    found: Option<usize>,
+    // Колонка, ширина которой меняется стрелками (режим `W`)
+    resizing: Option<usize>,
+    // Сколько первых символов ячеек скрыто прокруткой вправо
+    scroll_x: usize,
 
     visible: bool,
     focus: bool,
@@ -87,6 +170,15 @@ impl TableView {
             model: None,
             widths,
             style: TableViewStyle::default(),
+            query: None,
+            bookmarks: HashSet::new(),
+            anchor: None,
+            follow: Follow::Off,
+            shown: None,
+            find: None,
+            found: None,
+            resizing: None,
+            scroll_x: 0,
             visible: true,
             focus: false,
             width: 0,
@@ -98,24 +190,79 @@ impl TableView {
 
     pub fn set_model(&mut self, model: Rc<RefCell<dyn DataModel>>) {
         self.state = State::default();
+        self.scroll_x = 0;
         self.model = Some(model);
     }
 
-    #[allow(dead_code)]
-    pub fn style(&self) -> TableViewStyle {
-        self.style
-    }
-
-    #[allow(dead_code)]
     pub fn set_style(&mut self, style: TableViewStyle) {
         self.style = style;
     }
 
+    pub fn set_query(&mut self, query: Option<Query>) {
+        self.query = query;
+    }
+
     pub fn reset_state(&mut self) {
         self.state.select(None);
         self.state.begin = 0;
         self.update_state();
         self.emit_selection_changed();
+        self.sync_selection();
+    }
+
+    /// Снимает выделение. В отличие от `reset_state`, забывает и выделенную запись,
+    /// поэтому `sync_selection` ее не восстановит.
+    pub fn clear_selection(&mut self) {
+        self.state.id = None;
+        self.reset_state();
+    }
+
+    fn remember_selection(&mut self) {
+        self.state.id = match (&self.model, self.state.selected()) {
+            (Some(model), Some(index)) => model.borrow().row_id(index),
+            _ => None,
+        };
+    }
+
+    /// Восстанавливает выделение на той же записи после изменения модели
+    /// (смена фильтра, появление новых строк). Если под выделенной строкой оказалась
+    /// другая запись, снова вызывает `on_selection_changed`.
+    pub fn sync_selection(&mut self) {
+        self.restore_selection();
+        if self.current_id() != self.shown {
+            self.emit_selection_changed();
+        }
+    }
+
+    fn restore_selection(&mut self) {
+        if self.follow == Follow::Active {
+            let rows = self.rows();
+            if rows > 0 && self.state.selected() != Some(rows - 1) {
+                self.select_row(rows - 1);
+            }
+            return;
+        }
+
+        let (model, id) = match (self.model.clone(), self.state.id) {
+            (Some(model), Some(id)) => (model, id),
+            _ => return,
+        };
+
+        let current = self
+            .state
+            .selected()
+            .and_then(|index| model.borrow().row_id(index));
+        if current == Some(id) {
+            return;
+        }
+
+        let index = model.borrow().find_row(id);
+        if index.is_some() || self.state.selected().is_some() {
+            self.state.select(index);
+            self.state.id = Some(id);
+            self.update_state();
+            self.emit_selection_changed();
+        }
     }
 
     fn update_state(&mut self) {
@@ -137,6 +284,7 @@ impl TableView {
         if let Some(model) = self.model.clone() {
             let i = self.next_inner(self.state.selected(), model.borrow().rows());
             self.state.select(i);
+            self.remember_selection();
             self.update_state();
             self.emit_selection_changed();
         }
@@ -163,11 +311,200 @@ impl TableView {
         if let Some(model) = self.model.clone() {
             let i = self.prev_inner(self.state.selected(), model.borrow().rows());
             self.state.select(i);
+            self.remember_selection();
             self.update_state();
             self.emit_selection_changed();
         }
     }
 
+    pub fn selected(&self) -> Option<usize> {
+        self.state.selected()
+    }
+
+    /// Запись, которая сейчас под выделенной строкой.
+    fn current_id(&self) -> Option<usize> {
+        let model = self.model.as_ref()?;
+        let index = self.state.selected()?;
+        let id = model.borrow().row_id(index);
+        id
+    }
+
+    /// Номер выделенной записи в исходных данных.
+    pub fn selected_id(&self) -> Option<usize> {
+        self.state.id
+    }
+
+    /// Выделяет запись по номеру в исходных данных, как только она появится в модели.
+    pub fn select_id(&mut self, id: usize) {
+        self.state.id = Some(id);
+        self.sync_selection();
+    }
+
+    /// Выделяет строку `index`, ограничивая его количеством строк модели.
+    pub fn select_row(&mut self, index: usize) {
+        let rows = self.rows();
+        if rows == 0 {
+            return;
+        }
+
+        self.state.select(Some(index.min(rows - 1)));
+        self.remember_selection();
+        self.update_state();
+        self.emit_selection_changed();
+    }
+
+    /// Перемещает выделение на один экран вниз или вверх, останавливаясь на краях.
+    pub fn page(&mut self, forward: bool) {
+        let page = (self.height.saturating_sub(4) as usize).max(1);
+        let index = match (self.state.selected(), forward) {
+            (Some(current), true) => current.saturating_add(page),
+            (Some(current), false) => current.saturating_sub(page),
+            (None, _) => 0,
+        };
+        self.select_row(index);
+    }
+
+    pub fn follow(&self) -> Follow {
+        self.follow
+    }
+
+    /// Включает следование за новыми записями (сразу переходя к последней) или выключает его.
+    pub fn toggle_follow(&mut self) {
+        self.follow = match self.follow {
+            Follow::Off => Follow::Active,
+            Follow::Active | Follow::Paused => Follow::Off,
+        };
+        self.sync_selection();
+    }
+
+    /// Ручная прокрутка вверх приостанавливает следование.
+    fn pause_follow(&mut self) {
+        if self.follow == Follow::Active {
+            self.follow = Follow::Paused;
+        }
+    }
+
+    pub fn toggle_bookmark(&mut self) {
+        if let Some(id) = self.state.id {
+            if !self.bookmarks.remove(&id) {
+                self.bookmarks.insert(id);
+            }
+        }
+    }
+
+    /// Запоминает время текущей записи для отображения интервала до выделенной.
+    /// Повторное нажатие на той же записи снимает привязку.
+    pub fn toggle_anchor(&mut self) {
+        let (model, id, index) = match (self.model.clone(), self.state.id, self.state.selected()) {
+            (Some(model), Some(id), Some(index)) => (model, id, index),
+            _ => return,
+        };
+
+        if matches!(self.anchor, Some((anchor, _)) if anchor == id) {
+            self.anchor = None;
+            return;
+        }
+
+        let model = model.borrow();
+        self.anchor = model
+            .header_index("time")
+            .and_then(|column| model.data(ModelIndex::new(index, column)))
+            .map(|time| (id, time));
+    }
+
+    fn anchor_delta(&self, model: &dyn DataModel) -> Option<String> {
+        let (_, anchor) = self.anchor.as_ref()?;
+        let current = model.data(ModelIndex::new(
+            self.state.selected()?,
+            model.header_index("time")?,
+        ))?;
+
+        match (anchor, current) {
+            (Value::DateTime(anchor), Value::DateTime(current)) => {
+                Some(format_delta(current - *anchor))
+            }
+            _ => None,
+        }
+    }
+
+    /// Образец поиска по тексту строк (`/`). Пока он задан, `n` и `Shift+N`
+    /// переходят к совпадениям, а не к закладкам.
+    pub fn set_find(&mut self, pattern: Option<Regex>) {
+        self.find = pattern;
+        self.found = None;
+    }
+
+    /// Переходит к следующей (или предыдущей) строке, текст которой совпадает
+    /// с образцом поиска, по кругу. Возвращает `false`, если совпадений нет.
+    pub fn find_next(&mut self, forward: bool) -> bool {
+        let (model, pattern) = match (self.model.clone(), self.find.clone()) {
+            (Some(model), Some(pattern)) => (model, pattern),
+            _ => return false,
+        };
+
+        let borrowed = model.borrow();
+        let rows = borrowed.rows();
+        let start = match (self.state.selected(), forward) {
+            (Some(current), _) => current.min(rows.saturating_sub(1)),
+            (None, true) => rows.saturating_sub(1),
+            (None, false) => 0,
+        };
+        // Текущая строка проверяется последней
+        let row = (1..=rows)
+            .map(|step| match forward {
+                true => (start + step) % rows,
+                false => (start + rows - step) % rows,
+            })
+            .find(|&row| {
+                borrowed
+                    .row_text(row)
+                    .is_some_and(|text| pattern.is_match(&text))
+            });
+        let found = row.and_then(|row| borrowed.row_id(row));
+        drop(borrowed);
+
+        match row {
+            Some(row) => {
+                self.pause_follow();
+                self.found = found;
+                self.select_row(row);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Переходит к следующей (или предыдущей) закладке с учетом текущего фильтра.
+    pub fn next_bookmark(&mut self, forward: bool) {
+        let model = match self.model.clone() {
+            Some(model) => model,
+            None => return,
+        };
+
+        let mut rows = self
+            .bookmarks
+            .iter()
+            .filter_map(|&id| model.borrow().find_row(id))
+            .collect::<Vec<_>>();
+        rows.sort_unstable();
+
+        let row = match (self.state.selected(), forward) {
+            (Some(current), true) => rows.iter().find(|&&row| row > current),
+            (Some(current), false) => rows.iter().rev().find(|&&row| row < current),
+            (None, _) => None,
+        };
+
+        // Переход по кругу
+        let row = match forward {
+            true => row.or(rows.first()),
+            false => row.or(rows.last()),
+        };
+
+        if let Some(&row) = row {
+            self.select_row(row);
+        }
+    }
+
     fn prev_inner(&mut self, current: Option<usize>, length: usize) -> Option<usize> {
         if length == 0 {
             return None;
@@ -189,14 +526,29 @@ impl TableView {
         Renderer(self)
     }
 
-    fn get_column_widths(&self, max_width: u16) -> Vec<u16> {
-        let mut constraints = Vec::with_capacity(self.widths.len() * 2);
-        for constraint in self.widths.iter() {
+    /// Ширины колонок по `widths`. Если модель показывает другое количество колонок,
+    /// ширина делится поровну.
+    fn get_column_widths(&self, max_width: u16, cols: usize) -> Vec<u16> {
+        if cols == 0 {
+            return vec![];
+        }
+
+        let equal;
+        let widths = match self.widths.len() == cols {
+            true => &self.widths,
+            false => {
+                equal = vec![Constraint::Ratio(1, cols.max(1) as u32); cols];
+                &equal
+            }
+        };
+
+        let mut constraints = Vec::with_capacity(widths.len() * 2);
+        for constraint in widths.iter() {
             constraints.push(*constraint);
             constraints.push(Constraint::Length(self.style.column_spacing));
         }
 
-        if !self.widths.is_empty() {
+        if !widths.is_empty() {
             constraints.pop();
         }
 
@@ -221,6 +573,7 @@ impl TableView {
     }
 
     pub fn emit_selection_changed(&mut self) {
+        self.shown = self.current_id();
         let mut on_selection_changed =
             mem::replace(&mut self.on_selection_changed, Box::new(|_, _| {}));
         on_selection_changed(self, self.state.index);
@@ -234,6 +587,73 @@ impl TableView {
             0
         }
     }
+
+    fn cols(&self) -> usize {
+        self.model.as_ref().map_or(0, |model| model.borrow().cols())
+    }
+
+    /// Ширины колонок в процентах для сохранения между запусками.
+    /// Пусто, если ширины заданы не процентами.
+    pub fn widths(&self) -> Vec<u16> {
+        self.widths
+            .iter()
+            .map(|constraint| match constraint {
+                Constraint::Percentage(percent) => Some(*percent),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default()
+    }
+
+    pub fn set_widths(&mut self, widths: Vec<Constraint>) {
+        self.widths = widths;
+    }
+
+    /// Включает или выключает режим изменения ширины колонок, начиная с первой колонки.
+    pub fn toggle_resizing(&mut self) {
+        self.resizing = match self.resizing {
+            Some(_) => None,
+            None => (self.cols() > 1).then_some(0),
+        };
+    }
+
+    /// Меняет ширину колонки `resizing` на `delta` процентов. Остаток делится
+    /// между остальными колонками пропорционально их прежней ширине.
+    fn resize_column(&mut self, delta: i16) {
+        let cols = self.cols();
+        let column = match self.resizing {
+            Some(column) if column < cols && cols > 1 => column,
+            _ => return,
+        };
+
+        let mut widths = self.widths();
+        if widths.len() != cols {
+            widths = vec![100 / cols as u16; cols];
+        }
+
+        let max = 100 - MIN_COLUMN_PERCENT * (cols as u16 - 1);
+        let width = (widths[column] as i16 + delta).clamp(MIN_COLUMN_PERCENT as i16, max as i16);
+        let others = widths.iter().sum::<u16>() - widths[column];
+        let mut rest = 100 - width as u16;
+        let last = (0..cols)
+            .rev()
+            .find(|&other| other != column)
+            .unwrap_or_default();
+        for other in (0..cols).filter(|&other| other != column) {
+            let share = match other == last {
+                // Последней колонке достается остаток от округления
+                true => rest,
+                false => {
+                    (widths[other] as u32 * (100 - width as u32) / others.max(1) as u32) as u16
+                }
+            };
+            widths[other] = share.max(MIN_COLUMN_PERCENT);
+            rest = rest.saturating_sub(share);
+        }
+        widths[column] = width as u16;
+
+        self.widths = widths.into_iter().map(Constraint::Percentage).collect();
+    }
 }
 
 impl WidgetExt for TableView {
@@ -261,36 +681,205 @@ impl WidgetExt for TableView {
         self.set_visible(false)
     }
 
-    fn key_press_event(&mut self, event: KeyEvent) {
+    fn key_bindings(&self) -> &'static [KeyBinding] {
+        const KEYS: &[KeyBinding] = &[
+            KeyBinding::new("Up/Down", "Select row"),
+            KeyBinding::hint("PageUp", "Go to begin"),
+            KeyBinding::hint("PageDown", "Go to end"),
+            KeyBinding::hint("Ctrl+D/Ctrl+U", "Page down/up"),
+            KeyBinding::new("End", "Last row, resume follow"),
+            KeyBinding::hint("M", "Bookmark"),
+            KeyBinding::hint("N/Shift+N", "Next/prev match or bookmark"),
+            KeyBinding::hint("Space", "Anchor time"),
+            KeyBinding::hint("Esc", "Clear find/Deselect"),
+            KeyBinding::hint("Shift+F", "Follow"),
+            KeyBinding::hint("W", "Resize columns"),
+            KeyBinding::new("Left/Right", "Scroll text; narrow/widen column (resize)"),
+            KeyBinding::new("Shift+Left/Right", "Prev/next column (resize)"),
+        ];
+        KEYS
+    }
+
+    fn key_press_event(&mut self, event: KeyEvent) -> bool {
+        if let Some(column) = self.resizing {
+            match (event.code, event.modifiers) {
+                (KeyCode::Left, KeyModifiers::NONE) => {
+                    self.resize_column(-(RESIZE_STEP as i16));
+                    return true;
+                }
+                (KeyCode::Right, KeyModifiers::NONE) => {
+                    self.resize_column(RESIZE_STEP as i16);
+                    return true;
+                }
+                (KeyCode::Left, KeyModifiers::SHIFT) => {
+                    self.resizing = Some(column.saturating_sub(1));
+                    return true;
+                }
+                (KeyCode::Right, KeyModifiers::SHIFT) => {
+                    self.resizing = Some((column + 1).min(self.cols().saturating_sub(1)));
+                    return true;
+                }
+                (KeyCode::Esc | KeyCode::Enter, _) => {
+                    self.resizing = None;
+                    return true;
+                }
+                _ => {}
+            }
+        }
+
         match event {
+            KeyEvent {
+                code: KeyCode::Left,
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.scroll_x = self.scroll_x.saturating_sub(SCROLL_CHARS),
+            KeyEvent {
+                code: KeyCode::Right,
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.scroll_x += SCROLL_CHARS,
+            KeyEvent {
+                code: KeyCode::Char('w'),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.toggle_resizing(),
             KeyEvent {
                 code: KeyCode::Up,
                 modifiers: KeyModifiers::NONE,
-            } => self.prev(),
+                ..
+            } => {
+                self.pause_follow();
+                self.prev();
+            }
             KeyEvent {
                 code: KeyCode::Down,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => self.next(),
             KeyEvent {
                 code: KeyCode::PageUp,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
+                self.pause_follow();
                 self.state.begin = 0;
                 self.state.index = if self.rows() > 0 { Some(0) } else { None };
+                self.remember_selection();
                 self.emit_selection_changed();
             }
             KeyEvent {
                 code: KeyCode::PageDown,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
                 self.state.select(if self.rows() > 0 {
                     Some(self.rows() - 1)
                 } else {
                     None
                 });
+                self.remember_selection();
                 self.update_state();
                 self.emit_selection_changed();
             }
+            KeyEvent {
+                code: KeyCode::Char('d'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.page(true),
+            KeyEvent {
+                code: KeyCode::Char('u'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pause_follow();
+                self.page(false);
+            }
+            KeyEvent {
+                code: KeyCode::End,
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => {
+                if self.follow == Follow::Paused {
+                    self.follow = Follow::Active;
+                }
+                self.select_row(usize::MAX);
+            }
+            KeyEvent {
+                code: KeyCode::Char('F'),
+                ..
+            } => self.toggle_follow(),
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: KeyModifiers::NONE,
+                ..
+            } if self.find.is_some() => self.set_find(None),
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.clear_selection(),
+            KeyEvent {
+                code: KeyCode::Char('m'),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.toggle_bookmark(),
+            KeyEvent {
+                code: KeyCode::Char(' '),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.toggle_anchor(),
+            KeyEvent {
+                code: KeyCode::Char('n'),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } if self.find.is_some() => {
+                self.find_next(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char('N'),
+                ..
+            } if self.find.is_some() => {
+                self.find_next(false);
+            }
+            KeyEvent {
+                code: KeyCode::Char('n'),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.next_bookmark(true),
+            KeyEvent {
+                code: KeyCode::Char('N'),
+                ..
+            } => self.next_bookmark(false),
+            _ => return false,
+        }
+        true
+    }
+
+    fn mouse_event(&mut self, event: MouseEvent) {
+        let current = self.state.selected().unwrap_or(0);
+        match event.kind {
+            MouseEventKind::ScrollUp => {
+                self.pause_follow();
+                self.select_row(current.saturating_sub(SCROLL_ROWS));
+            }
+            MouseEventKind::ScrollDown => match self.state.selected() {
+                Some(current) => self.select_row(current.saturating_add(SCROLL_ROWS)),
+                None => self.select_row(0),
+            },
+            MouseEventKind::Down(MouseButton::Left) => {
+                // Верхняя граница и заголовок таблицы
+                let rows_height = self.height.saturating_sub(3);
+                match event.row.checked_sub(2) {
+                    Some(offset) if offset < rows_height => {
+                        let index = self.state.begin + offset as usize;
+                        if index < self.rows() {
+                            self.pause_follow();
+                            self.select_row(index);
+                        }
+                    }
+                    _ => {}
+                }
+            }
             _ => {}
         }
     }
@@ -312,6 +901,18 @@ impl WidgetExt for TableView {
 
 struct Renderer<'a>(&'a TableView);
 
+/// Сообщение по центру области под заголовком таблицы.
+fn render_message(buf: &mut Buffer, area: Rect, text: &str) {
+    let width = (text.chars().count() as u16).min(area.width);
+    buf.set_stringn(
+        area.left() + (area.width - width) / 2,
+        area.top() + 1 + area.height.saturating_sub(1) / 2,
+        text,
+        width as usize,
+        Style::default().fg(Color::DarkGray),
+    );
+}
+
 impl<'a> Widget for Renderer<'a> {
     fn render(self, area: Rect, buf: &mut Buffer) {
         if area.area() == 0 || !self.0.visible() {
@@ -319,21 +920,69 @@ impl<'a> Widget for Renderer<'a> {
         }
 
         let block_style = match self.0.focused() {
-            true => Style::default().fg(Color::LightYellow),
+            true => self.0.style.focus_style,
             false => Style::default(),
         };
 
+        let mut title = format!(
+            "{}/{}",
+            self.0.state.selected().map_or(0, |i| i + 1),
+            self.0
+                .model
+                .as_ref()
+                .map_or(0, |model| model.borrow().rows())
+        );
+
+        match self
+            .0
+            .model
+            .as_ref()
+            .and_then(|model| model.borrow().progress())
+        {
+            Some(progress) if progress.scanned < progress.total => title.push_str(&format!(
+                " (scanning {}%)",
+                progress.scanned * 100 / progress.total
+            )),
+            Some(_) => title.push_str(" (loading)"),
+            None => {}
+        }
+
+        if self
+            .0
+            .model
+            .as_ref()
+            .is_some_and(|model| model.borrow().truncated())
+        {
+            title.push_str(" (line limit reached)");
+        }
+
+        match self.0.follow {
+            Follow::Active => title.push_str(" [follow]"),
+            Follow::Paused => title.push_str(" [follow paused, End to resume]"),
+            Follow::Off => {}
+        }
+
+        if self.0.scroll_x > 0 {
+            title.push_str(&format!(" [→{}]", self.0.scroll_x));
+        }
+
+        if self.0.resizing.is_some() {
+            title.push_str(" [resize: Left/Right width, Shift+Left/Right column]");
+        }
+
+        if let Some(delta) = self
+            .0
+            .model
+            .as_ref()
+            .and_then(|model| self.0.anchor_delta(&*model.borrow()))
+        {
+            title.push_str(&format!(" Δ {}", delta));
+        }
+
         let block = Block::default()
             .borders(Borders::ALL)
             .border_style(block_style)
-            .title(format!(
-                "{}/{}",
-                self.0.state.selected().map_or(0, |i| i + 1),
-                self.0
-                    .model
-                    .as_ref()
-                    .map_or(0, |model| model.borrow().rows())
-            ));
+            .title(title);
 
         let model = match self.0.model {
             Some(ref model) => model.borrow(),
@@ -349,9 +998,19 @@ impl<'a> Widget for Renderer<'a> {
             inner_area
         };
 
+        if cols == 0 {
+            render_message(buf, table_area, "no columns configured");
+            return;
+        }
+
         let has_selection = self.0.state.selected().is_some();
         let rows_height = table_area.height.saturating_sub(1);
-        let column_widths = self.0.get_column_widths(table_area.width);
+        // Поле пометок строк слева от колонок
+        let gutter = model.has_markers() as u16;
+        let columns_left = table_area.left() + gutter;
+        let column_widths = self
+            .0
+            .get_column_widths(table_area.width.saturating_sub(gutter), cols);
         let mut current_height = 1;
         let (data_rows, data_columns) = (rows, cols);
 
@@ -365,7 +1024,7 @@ impl<'a> Widget for Renderer<'a> {
             self.0.style.header_style,
         );
 
-        let mut col = table_area.left();
+        let mut col = columns_left;
         for (&width, cell) in column_widths.iter().zip(0..data_columns) {
             let header_data = model.header_data(cell).unwrap_or_default();
             buf.set_stringn(
@@ -375,14 +1034,35 @@ impl<'a> Widget for Renderer<'a> {
                 width as usize,
                 Style::default(),
             );
+            if self.0.resizing == Some(cell) {
+                buf.set_style(
+                    Rect::new(col, table_area.top(), width, table_area.height.min(1)),
+                    self.0.style.header_style.add_modifier(Modifier::REVERSED),
+                );
+            }
             col += width + 1;
         }
 
         // Render rows
         if data_rows == 0 {
+            if let Some(text) = model.empty_text() {
+                render_message(buf, table_area, &text);
+            }
             return;
         }
 
+        let highlights = (0..data_columns)
+            .map(|cell| match (&self.0.query, model.header_data(cell)) {
+                (Some(query), Some(name)) => query.highlights(&name),
+                _ => vec![],
+            })
+            .collect::<Vec<_>>();
+
+        let event_column = match self.0.style.event_colors.is_empty() {
+            true => None,
+            false => model.header_index("event"),
+        };
+
         let (start, end) = (
             self.0.state.begin,
             self.0.state.begin + rows_height as usize,
@@ -390,28 +1070,336 @@ impl<'a> Widget for Renderer<'a> {
         //self.0.state.offset = start;
 
         for index in (0..data_rows).skip(self.0.state.begin).take(end - start) {
-            let (row, mut col) = (table_area.top() + current_height, table_area.left());
+            let (row, mut col) = (table_area.top() + current_height, columns_left);
             current_height += 1;
             let table_row_area = Rect {
-                x: col,
+                x: table_area.left(),
                 y: row,
                 width: table_area.width,
                 height: 1,
             };
 
-            if has_selection && self.0.state.selected().unwrap() == index {
+            let found = self.0.found.is_some() && model.row_id(index) == self.0.found;
+            if found {
+                // Найденная строка выделяется и тогда, когда выделение ушло с нее
+                buf.set_style(table_row_area, self.0.style.match_style)
+            } else if has_selection && self.0.state.selected().unwrap() == index {
                 buf.set_style(table_row_area, self.0.style.selected_row_style)
+            } else {
+                // Выделенная строка не раскрашивается, чтобы текст оставался читаемым
+                let event_color = event_column
+                    .and_then(|column| model.data(ModelIndex::new(index, column)))
+                    .and_then(|event| self.0.style.event_colors.get(&event.to_string()));
+                if let Some(&color) = event_color {
+                    buf.set_style(table_row_area, Style::default().fg(color));
+                }
+                if model
+                    .row_id(index)
+                    .is_some_and(|id| self.0.bookmarks.contains(&id))
+                {
+                    buf.set_style(table_row_area, self.0.style.bookmark_style)
+                }
+            }
+
+            if let Some(marker) = model.marker(index).filter(|_| gutter > 0) {
+                buf.set_string(
+                    table_area.left(),
+                    row,
+                    marker.to_string(),
+                    Style::default().fg(Color::Yellow),
+                );
             }
 
             for (&width, cell) in column_widths.iter().zip(0..data_columns) {
                 let data = model
-                    .data(ModelIndex::new(index, cell))
-                    .map(|d| d.to_string())
+                    .display(ModelIndex::new(index, cell))
+                    .map(|data| single_line(&data))
                     .unwrap_or_default();
+                let data = match self.0.scroll_x {
+                    0 => data,
+                    skip => data.chars().skip(skip).collect(),
+                };
 
-                buf.set_stringn(col, row, data, width as usize, Style::default());
+                let regexes = highlights[cell].as_slice();
+                if regexes.is_empty() {
+                    buf.set_stringn(col, row, data, width as usize, Style::default());
+                } else {
+                    let spans = highlight_spans(&data, regexes, self.0.style.highlight_style);
+                    buf.set_spans(col, row, &spans, width);
+                }
                 col += width + 1;
             }
         }
     }
 }
+
+fn highlight_spans<'a>(text: &'a str, regexes: &[&Regex], style: Style) -> Spans<'a> {
+    let mut ranges = regexes
+        .iter()
+        .flat_map(|regex| regex.find_iter(text))
+        .filter(|m| !m.range().is_empty())
+        .map(|m| m.range())
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|range| range.start);
+
+    let mut spans = vec![];
+    let mut pos = 0;
+    for range in ranges {
+        if range.end <= pos {
+            continue;
+        }
+
+        let start = range.start.max(pos);
+        if start > pos {
+            spans.push(Span::raw(&text[pos..start]));
+        }
+        spans.push(Span::styled(&text[start..range.end], style));
+        pos = range.end;
+    }
+
+    if pos < text.len() {
+        spans.push(Span::raw(&text[pos..]));
+    }
+
+    Spans::from(spans)
+}
+
+#[test]
+fn test_follow_pauses_and_resumes() {
+    let model = Rc::new(RefCell::new(vec![1, 2, 3]));
+    let mut table = TableView::new(vec![Constraint::Percentage(100)]);
+    table.set_model(model.clone());
+    table.resize(20, 10);
+
+    table.key_press_event(KeyEvent::new(KeyCode::Char('F'), KeyModifiers::SHIFT));
+    assert_eq!(table.selected(), Some(2));
+    model.borrow_mut().push(4);
+    table.sync_selection();
+    assert_eq!(table.selected(), Some(3));
+
+    table.key_press_event(KeyEvent::new(KeyCode::Up, KeyModifiers::NONE));
+    assert_eq!(table.follow(), Follow::Paused);
+    model.borrow_mut().push(5);
+    table.sync_selection();
+    assert_eq!(table.selected(), Some(2));
+
+    table.key_press_event(KeyEvent::new(KeyCode::End, KeyModifiers::NONE));
+    assert_eq!(table.follow(), Follow::Active);
+    assert_eq!(table.selected(), Some(4));
+}
+
+#[test]
+fn test_selection_follows_filter() {
+    // Модель с номерами записей, как у отфильтрованной коллекции
+    struct Filtered(Vec<usize>);
+
+    impl DataModel for Filtered {
+        fn rows(&self) -> usize {
+            self.0.len()
+        }
+
+        fn cols(&self) -> usize {
+            1
+        }
+
+        fn header_index(&self, _name: &str) -> Option<usize> {
+            None
+        }
+
+        fn header_data(&self, _column: usize) -> Option<std::borrow::Cow<'_, str>> {
+            None
+        }
+
+        fn data(&self, index: ModelIndex) -> Option<Value<'static>> {
+            self.0.get(index.row()).map(|&id| Value::Number(id as f64))
+        }
+
+        fn row_id(&self, row: usize) -> Option<usize> {
+            self.0.get(row).copied()
+        }
+
+        fn find_row(&self, id: usize) -> Option<usize> {
+            self.0.iter().position(|&row| row == id)
+        }
+    }
+
+    let model = Rc::new(RefCell::new(Filtered(vec![0, 1, 2, 3])));
+    let pane = Rc::new(RefCell::new(None));
+    let mut table = TableView::new(vec![Constraint::Percentage(100)]);
+    table.set_model(model.clone());
+    table.resize(20, 10);
+    let (shown, rows) = (pane.clone(), model.clone());
+    table.on_selection_changed(move |_, index| {
+        *shown.borrow_mut() = index.and_then(|index| rows.borrow().row_id(index));
+    });
+
+    table.select_row(2);
+    assert_eq!(*pane.borrow(), Some(2));
+
+    // Фильтр пересобрал строки: та же запись под другим номером
+    model.borrow_mut().0 = vec![1, 2];
+    table.reset_state();
+    assert_eq!((table.selected(), *pane.borrow()), (Some(1), Some(2)));
+
+    // Записи больше нет в выборке
+    model.borrow_mut().0 = vec![0, 1, 3];
+    table.sync_selection();
+    assert_eq!((table.selected(), *pane.borrow()), (None, None));
+
+    // В режиме следования номер последней строки не изменился, а запись другая
+    table.key_press_event(KeyEvent::new(KeyCode::Char('F'), KeyModifiers::SHIFT));
+    assert_eq!(*pane.borrow(), Some(3));
+    model.borrow_mut().0 = vec![0, 1, 5];
+    table.sync_selection();
+    assert_eq!((table.selected(), *pane.borrow()), (Some(2), Some(5)));
+}
+
+#[test]
+fn test_no_columns() {
+    struct NoColumns;
+
+    impl DataModel for NoColumns {
+        fn rows(&self) -> usize {
+            2
+        }
+
+        fn cols(&self) -> usize {
+            0
+        }
+
+        fn header_index(&self, _name: &str) -> Option<usize> {
+            None
+        }
+
+        fn header_data(&self, _column: usize) -> Option<std::borrow::Cow<'_, str>> {
+            None
+        }
+
+        fn data(&self, _index: ModelIndex) -> Option<Value<'static>> {
+            None
+        }
+    }
+
+    let mut table = TableView::new(vec![]);
+    assert!(table.get_column_widths(40, 0).is_empty());
+    assert_eq!(table.get_column_widths(40, 2), vec![20, 19]);
+
+    table.set_model(Rc::new(RefCell::new(NoColumns)));
+    table.resize(40, 6);
+    let area = Rect::new(0, 0, 40, 6);
+    let mut buf = Buffer::empty(area);
+    table.widget().render(area, &mut buf);
+    let text = buf
+        .content()
+        .iter()
+        .map(|cell| cell.symbol.as_str())
+        .collect::<String>();
+    assert!(text.contains("no columns configured"));
+}
+
+#[test]
+fn test_find_next() {
+    let model = Rc::new(RefCell::new(vec!["call", "excp 1", "call", "EXCP 2"]));
+    let mut table = TableView::new(vec![Constraint::Percentage(100)]);
+    table.set_model(model);
+    table.resize(20, 10);
+
+    table.set_find(Regex::new("(?i)excp").ok());
+    assert!(table.find_next(true));
+    assert_eq!((table.selected(), table.found), (Some(1), Some(1)));
+    table.key_press_event(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE));
+    assert_eq!(table.selected(), Some(3));
+    // По кругу в обе стороны
+    table.key_press_event(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE));
+    assert_eq!(table.selected(), Some(1));
+    table.key_press_event(KeyEvent::new(KeyCode::Char('N'), KeyModifiers::SHIFT));
+    assert_eq!((table.selected(), table.found), (Some(3), Some(3)));
+
+    table.set_find(Regex::new("qerr").ok());
+    assert!(!table.find_next(true));
+    assert_eq!(table.selected(), Some(3));
+
+    // Esc снимает поиск, выделение остается
+    table.key_press_event(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+    assert_eq!((table.find.is_none(), table.selected()), (true, Some(3)));
+}
+
+#[test]
+fn test_resize_column() {
+    struct ThreeColumns;
+
+    impl DataModel for ThreeColumns {
+        fn rows(&self) -> usize {
+            1
+        }
+
+        fn cols(&self) -> usize {
+            3
+        }
+
+        fn header_index(&self, _name: &str) -> Option<usize> {
+            None
+        }
+
+        fn header_data(&self, _column: usize) -> Option<std::borrow::Cow<'_, str>> {
+            None
+        }
+
+        fn data(&self, _index: ModelIndex) -> Option<Value<'static>> {
+            None
+        }
+    }
+
+    let model = Rc::new(RefCell::new(vec!["a"]));
+    let mut table = TableView::new(vec![Constraint::Percentage(50); 2]);
+    table.set_model(model);
+    let press = |table: &mut TableView, code, modifiers| {
+        table.key_press_event(KeyEvent::new(code, modifiers));
+    };
+
+    // Одна колонка: менять нечего
+    press(&mut table, KeyCode::Char('w'), KeyModifiers::NONE);
+    assert_eq!(table.resizing, None);
+
+    let model = Rc::new(RefCell::new(ThreeColumns));
+    table.set_model(model);
+    press(&mut table, KeyCode::Char('w'), KeyModifiers::NONE);
+    // Ширины не совпадают с колонками: сначала делятся поровну
+    press(&mut table, KeyCode::Right, KeyModifiers::NONE);
+    assert_eq!(table.widths(), vec![35, 32, 33]);
+
+    press(&mut table, KeyCode::Right, KeyModifiers::SHIFT);
+    for _ in 0..50 {
+        press(&mut table, KeyCode::Right, KeyModifiers::NONE);
+    }
+    assert_eq!(table.widths(), vec![3, 94, 3]);
+    press(&mut table, KeyCode::Left, KeyModifiers::NONE);
+    assert_eq!(table.widths().iter().sum::<u16>(), 100);
+
+    press(&mut table, KeyCode::Esc, KeyModifiers::NONE);
+    press(&mut table, KeyCode::Left, KeyModifiers::NONE);
+    assert_eq!((table.resizing, table.widths()[1]), (None, 92));
+}
+
+#[test]
+fn test_horizontal_scroll() {
+    let model = Rc::new(RefCell::new(vec!["0123456789abcdef"]));
+    let mut table = TableView::new(vec![Constraint::Percentage(100)]);
+    table.set_model(model);
+    let area = Rect::new(0, 0, 20, 4);
+    table.resize(area.width, area.height);
+    let row = |table: &TableView| {
+        let mut buf = Buffer::empty(area);
+        table.widget().render(area, &mut buf);
+        (1..area.width - 1)
+            .map(|x| buf.get(x, 2).symbol.as_str())
+            .collect::<String>()
+    };
+
+    assert!(row(&table).starts_with("0123456789abcdef"));
+    table.key_press_event(KeyEvent::new(KeyCode::Right, KeyModifiers::NONE));
+    assert!(row(&table).starts_with("89abcdef "));
+    table.key_press_event(KeyEvent::new(KeyCode::Left, KeyModifiers::NONE));
+    table.key_press_event(KeyEvent::new(KeyCode::Left, KeyModifiers::NONE));
+    assert!(row(&table).starts_with("0123"));
+}
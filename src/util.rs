@@ -1,86 +1,515 @@
-use chrono::{Duration, Local, NaiveDateTime, NaiveTime, Timelike};
-use regex::Regex;
-use std::str::FromStr;
-
-pub fn parse_date(value: &str) -> Result<NaiveDateTime, regex::Error> {
-    let now = Local::now().naive_local();
-    let regex = Regex::new(r#"^now-(\d+)([smhdw])$"#)?;
-
-    match regex.captures(value) {
-        Some(captures) if captures.len() == 3 => match (captures.get(1), captures.get(2)) {
-            (Some(offset), Some(char)) => {
-                let offset = offset
-                    .as_str()
-                    .parse::<u64>()
-                    .map_err(|_| regex::Error::Syntax(String::from("Cannot parse number")))?;
-
-                match char.as_str() {
-                    "s" => Ok(now - Duration::seconds(offset as i64)),
-                    "m" => Ok(now - Duration::minutes(offset as i64)),
-                    "h" => Ok(now - Duration::hours(offset as i64)),
-                    "d" => Ok(now - Duration::days(offset as i64)),
-                    "w" => Ok(now - Duration::weeks(offset as i64)),
-                    _ => unreachable!(),
-                }
+use chrono::{
+    format::{Item, StrftimeItems},
+    Duration, FixedOffset, Local, NaiveDateTime, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum DateError {
+    #[error("Unexpected char: {0}")]
+    UnexpectedChar(char),
+    #[error("Unexpected end of input")]
+    UnexpectedEndOfInput,
+    #[error("Invalid date")]
+    InvalidDate,
+    #[error("Invalid time format: {0}")]
+    InvalidFormat(String),
+    #[error("Invalid time zone")]
+    InvalidZone,
+}
+
+/// Часовой пояс, в котором записаны логи: смещение `+03:00`, имя IANA `Europe/Moscow`
+/// или, по умолчанию, локальный пояс машины. Время записей и абсолютные даты в фильтрах
+/// остаются временем логов как есть, от пояса зависит только `now`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogZone {
+    #[default]
+    Local,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl LogZone {
+    pub fn now(&self) -> NaiveDateTime {
+        let now = Utc::now();
+        match self {
+            LogZone::Local => now.with_timezone(&Local).naive_local(),
+            LogZone::Fixed(offset) => now.with_timezone(offset).naive_local(),
+            LogZone::Named(tz) => now.with_timezone(tz).naive_local(),
+        }
+    }
+}
+
+impl FromStr for LogZone {
+    type Err = DateError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (sign, offset) = match value.chars().next() {
+            Some('+') => (1, &value[1..]),
+            Some('-') => (-1, &value[1..]),
+            _ => {
+                return value
+                    .parse::<Tz>()
+                    .map(LogZone::Named)
+                    .map_err(|_| DateError::InvalidZone)
             }
-            _ => Err(regex::Error::Syntax("Invalid captures".to_string())),
-        },
-        _ => Err(regex::Error::Syntax("Invalid value".to_string())),
+        };
+
+        // +03:00, +0300 или +03
+        let (hours, minutes) = match offset.split_once(':') {
+            Some(parts) => parts,
+            None if offset.len() == 4 => offset.split_at(2),
+            None => (offset, "0"),
+        };
+        let seconds = match (hours.parse::<i32>(), minutes.parse::<i32>()) {
+            (Ok(hours), Ok(minutes)) if minutes < 60 => hours * 3600 + minutes * 60,
+            _ => return Err(DateError::InvalidZone),
+        };
+        FixedOffset::east_opt(sign * seconds)
+            .map(LogZone::Fixed)
+            .ok_or(DateError::InvalidZone)
     }
 }
 
-pub fn parse_time(hour: NaiveDateTime, time: &str) -> NaiveDateTime {
-    let minutes_pos = time
-        .as_bytes()
-        .iter()
-        .position(|char| *char == b':')
-        .unwrap();
-    let seconds_pos = time
-        .as_bytes()
-        .iter()
-        .position(|char| *char == b'.')
-        .unwrap();
-
-    let minutes = match u32::from_str(&time[0..minutes_pos]) {
-        Ok(v) => v,
-        Err(_) => unreachable!(),
+impl Display for LogZone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogZone::Local => write!(f, "local"),
+            LogZone::Fixed(offset) => write!(f, "{}", offset),
+            LogZone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+/// Проверяет, что строка является корректным форматом strftime.
+pub fn validate_time_format(format: &str) -> Result<(), DateError> {
+    match StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        true => Err(DateError::InvalidFormat(format.to_string())),
+        false => Ok(()),
+    }
+}
+
+/// Разбирает час в формате `гггг-мм-дд чч` или `ггггммддчч`.
+pub fn parse_hour(value: &str) -> Result<NaiveDateTime, DateError> {
+    let value = format!("{}:00", value.trim());
+    NaiveDateTime::parse_from_str(&value, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(&value, "%Y%m%d%H:%M"))
+        .map_err(|_| DateError::InvalidDate)
+}
+
+/// Разбирает дату `--from`: `now` берется в поясе логов `zone`.
+pub fn parse_date(value: &str, zone: LogZone) -> Result<NaiveDateTime, DateError> {
+    parse_relative_date(zone.now(), value)
+}
+
+/// Разбирает дату относительно `now`: `now`, `now-1d12h`, `now+30s`.
+/// Смещение задается последовательностью `{digit}{s/m/h/d/w}`.
+pub fn parse_relative_date(now: NaiveDateTime, value: &str) -> Result<NaiveDateTime, DateError> {
+    let mut iter = value
+        .strip_prefix("now")
+        .ok_or(DateError::InvalidDate)?
+        .chars()
+        .peekable();
+
+    let negative = match iter.next() {
+        Some('-') => true,
+        Some('+') => false,
+        Some(c) => return Err(DateError::UnexpectedChar(c)),
+        None => return Ok(now),
+    };
+
+    let offset = parse_interval(&iter.collect::<String>())?;
+    match negative {
+        true => now.checked_sub_signed(offset),
+        false => now.checked_add_signed(offset),
+    }
+    .ok_or(DateError::InvalidDate)
+}
+
+/// Разбирает интервал из последовательности `{digit}{s/m/h/d/w}`: `30s`, `1h30m`.
+pub fn parse_interval(value: &str) -> Result<Duration, DateError> {
+    let mut iter = value.chars().peekable();
+    let mut seconds = 0i64;
+    loop {
+        let mut digits = String::new();
+        while let Some(&c) = iter.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            digits.push(c);
+            iter.next();
+        }
+
+        if digits.is_empty() {
+            return match iter.next() {
+                Some(c) => Err(DateError::UnexpectedChar(c)),
+                None => Err(DateError::UnexpectedEndOfInput),
+            };
+        }
+
+        let unit = match iter.next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            Some('w') => 7 * 24 * 60 * 60,
+            Some(c) => return Err(DateError::UnexpectedChar(c)),
+            None => return Err(DateError::UnexpectedEndOfInput),
+        };
+
+        seconds = digits
+            .parse::<i64>()
+            .ok()
+            .and_then(|offset| offset.checked_mul(unit))
+            .and_then(|offset| seconds.checked_add(offset))
+            .filter(|seconds| *seconds <= i64::MAX / 1000)
+            .ok_or(DateError::InvalidDate)?;
+
+        if iter.peek().is_none() {
+            break;
+        }
+    }
+
+    Ok(Duration::seconds(seconds))
+}
+
+/// Форматирует интервал со знаком и точностью до микросекунд: `+01:02:03.000004`.
+pub fn format_delta(delta: Duration) -> String {
+    let sign = if delta < Duration::zero() { '-' } else { '+' };
+    let micros = delta.num_microseconds().map_or(i64::MAX, i64::abs);
+    let seconds = micros / 1_000_000;
+
+    format!(
+        "{}{:02}:{:02}:{:02}.{:06}",
+        sign,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        micros % 1_000_000
+    )
+}
+
+/// Форматирует длительность в микросекундах: `4.5s`, `250ms`, `900µs`.
+pub fn format_duration(micros: f64) -> String {
+    let (value, unit) = match micros.abs() {
+        m if m >= 1_000_000.0 => (micros / 1_000_000.0, "s"),
+        m if m >= 1_000.0 => (micros / 1_000.0, "ms"),
+        _ => (micros, "µs"),
     };
-    let seconds = u32::from_str(&time[(minutes_pos + 1)..seconds_pos]).unwrap();
-    let nanos = &time[(seconds_pos + 1)..];
-    let nanos_count = nanos.chars().count();
-    let nanos = u32::from_str(nanos).unwrap();
-
-    match nanos_count {
-        0..=3 => NaiveDateTime::new(
-            hour.date(),
-            NaiveTime::from_hms_milli(hour.time().hour(), minutes, seconds, nanos),
-        ),
-        4..=6 => NaiveDateTime::new(
-            hour.date(),
-            NaiveTime::from_hms_micro(hour.time().hour(), minutes, seconds, nanos),
-        ),
-        _ => NaiveDateTime::new(
-            hour.date(),
-            NaiveTime::from_hms_nano(hour.time().hour(), minutes, seconds, nanos),
-        ),
+
+    let value = format!("{:.1}", value);
+    format!("{}{}", value.trim_end_matches(".0"), unit)
+}
+
+/// Число с разделителем разрядов: `58,991`.
+pub fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut result = String::with_capacity(digits.len() + digits.len() / 3);
+    for (index, c) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            result.push(',');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Читает подписи событий из файла со строками `код=подпись`.
+/// Пустые строки и строки, начинающиеся с `#`, пропускаются.
+pub fn load_event_labels<P: AsRef<Path>>(path: P) -> io::Result<HashMap<String, String>> {
+    parse_key_values(&std::fs::read_to_string(path)?)
+}
+
+/// Разбирает строки `ключ=значение`, пропуская пустые строки и комментарии `#`.
+pub fn parse_key_values(data: &str) -> io::Result<HashMap<String, String>> {
+    let mut values = HashMap::new();
+    for (number, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                values.insert(key.trim().to_string(), value.trim().to_string());
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: expected `key=value`", number + 1),
+                ))
+            }
+        }
     }
+    Ok(values)
+}
+
+/// Каталог настроек приложения: `%APPDATA%/journal1c` или `~/.config/journal1c`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("APPDATA") {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        },
+    };
+    Some(base.join("journal1c"))
+}
+
+/// Подставляет в час `hour` минуты, секунды и доли секунды из префикса строки лога (`мм:сс.дробь`).
+/// Недостающие или нечисловые части (`14:`, `05:12`) считаются нулевыми.
+pub fn parse_time(hour: NaiveDateTime, time: &str) -> NaiveDateTime {
+    let (minutes, rest) = time.split_once(':').unwrap_or((time, ""));
+    let (seconds, fraction) = rest.split_once('.').unwrap_or((rest, ""));
+    let number = |value: &str| value.trim().parse::<u32>().unwrap_or(0);
+
+    // Доли секунды - десятичная дробь: `345` - 345 мс, `345000` - 345000 мкс
+    let digits = fraction
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .take(9)
+        .collect::<String>();
+    let nanos = match digits.parse::<u32>() {
+        Ok(value) => value * 10u32.pow(9 - digits.len() as u32),
+        Err(_) => 0,
+    };
+
+    hour.date()
+        .and_hms_nano_opt(
+            hour.hour(),
+            number(minutes).min(59),
+            number(seconds).min(59),
+            nanos,
+        )
+        .unwrap_or(hour)
 }
 
+/// Делит строку на подстроки не длиннее `sub_len` символов (не байт) и по переводам строк.
+/// При нулевой ширине (виджет еще не размещен) подстрок нет.
 pub fn sub_strings(string: &str, sub_len: usize) -> Vec<&str> {
+    if sub_len == 0 {
+        return vec![];
+    }
+
     let mut subs = Vec::with_capacity(string.len() * 2 / sub_len);
     let mut iter = string.chars();
     let mut pos = 0;
 
     while pos < string.len() {
         let mut len = 0;
+        let mut newline = false;
         for ch in iter.by_ref().take(sub_len) {
             len += ch.len_utf8();
             if ch == '\n' {
+                newline = true;
                 break;
             }
         }
-        subs.push(&string[pos..pos + len]);
+
+        // Перевод строки не попадает в подстроку
+        let sub = &string[pos..pos + len];
+        subs.push(match newline {
+            true => sub.trim_end_matches('\n').trim_end_matches('\r'),
+            false => sub,
+        });
         pos += len;
     }
     subs
 }
+
+/// Однострочное представление значения: переводы строк заменяются на `⏎`.
+pub fn single_line(string: &str) -> String {
+    string.replace("\r\n", "⏎").replace('\n', "⏎")
+}
+
+/// Расстояние Левенштейна между строками (по символам).
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            row[j + 1] = (prev[j] + (ca != *cb) as usize)
+                .min(prev[j + 1] + 1)
+                .min(row[j] + 1);
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+#[test]
+fn test_relative_date_compound() {
+    let now = NaiveDateTime::parse_from_str("2022-08-02 14:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    assert_eq!(
+        parse_relative_date(now, "now-1d12h"),
+        Ok(now - Duration::hours(36))
+    );
+}
+
+#[test]
+fn test_relative_date_future() {
+    let now = NaiveDateTime::parse_from_str("2022-08-02 14:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    assert_eq!(
+        parse_relative_date(now, "now+30s"),
+        Ok(now + Duration::seconds(30))
+    );
+}
+
+#[test]
+fn test_relative_date_now() {
+    let now = NaiveDateTime::parse_from_str("2022-08-02 14:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    assert_eq!(parse_relative_date(now, "now"), Ok(now));
+    assert_eq!(
+        parse_relative_date(now, "now-"),
+        Err(DateError::UnexpectedEndOfInput)
+    );
+    assert_eq!(
+        parse_relative_date(now, "now-1x"),
+        Err(DateError::UnexpectedChar('x'))
+    );
+}
+
+#[test]
+fn test_format_delta() {
+    assert_eq!(
+        format_delta(Duration::seconds(3723) + Duration::microseconds(4)),
+        "+01:02:03.000004"
+    );
+    assert_eq!(
+        format_delta(-Duration::microseconds(1_500_000)),
+        "-00:00:01.500000"
+    );
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(4_500_000.0), "4.5s");
+    assert_eq!(format_duration(250_000.0), "250ms");
+    assert_eq!(format_duration(1_500.0), "1.5ms");
+    assert_eq!(format_duration(900.0), "900µs");
+    assert_eq!(format_duration(0.0), "0µs");
+}
+
+#[test]
+fn test_parse_zone() {
+    let offset = |seconds| LogZone::Fixed(FixedOffset::east_opt(seconds).unwrap());
+    assert_eq!("+03:00".parse(), Ok(offset(3 * 3600)));
+    assert_eq!("-0530".parse(), Ok(offset(-(5 * 3600 + 30 * 60))));
+    assert_eq!("+3".parse(), Ok(offset(3 * 3600)));
+    assert_eq!(
+        "Europe/Moscow".parse(),
+        Ok(LogZone::Named(chrono_tz::Europe::Moscow))
+    );
+    assert!("+03:75".parse::<LogZone>().is_err());
+    assert!("Mars/Olympus".parse::<LogZone>().is_err());
+
+    let zone = offset(3 * 3600);
+    let delta = zone.now() - Utc::now().naive_utc();
+    assert!((delta - Duration::hours(3)).num_seconds().abs() <= 1);
+}
+
+#[test]
+fn test_validate_time_format() {
+    assert!(validate_time_format("%H:%M:%S%.6f").is_ok());
+    assert!(validate_time_format("%Q").is_err());
+}
+
+#[test]
+fn test_parse_hour() {
+    assert_eq!(
+        parse_hour("2025-01-15 14"),
+        Ok(NaiveDateTime::parse_from_str("2025-01-15 14:00:00", "%Y-%m-%d %H:%M:%S").unwrap())
+    );
+    assert_eq!(parse_hour("2025011514"), parse_hour("2025-01-15 14"));
+    assert!(parse_hour("2025-01-15").is_err());
+}
+
+#[test]
+fn test_sub_strings_multiline() {
+    assert_eq!(
+        sub_strings("ab\r\ncd\n\nef", 10),
+        vec!["ab", "cd", "", "ef"]
+    );
+    assert_eq!(sub_strings("abcdef\nx", 4), vec!["abcd", "ef", "x"]);
+    assert_eq!(single_line("a\r\nb\nc"), "a⏎b⏎c");
+}
+
+#[test]
+fn test_sub_strings_multibyte() {
+    assert_eq!(sub_strings("Ошибка СУБД", 4), vec!["Ошиб", "ка С", "УБД"]);
+    assert_eq!(
+        sub_strings("Ж€\n😀ab", 1),
+        vec!["Ж", "€", "", "😀", "a", "b"]
+    );
+    assert!(sub_strings("Ошибка", 0).is_empty());
+    assert!(sub_strings("", 0).is_empty());
+}
+
+#[test]
+fn test_parse_key_values() {
+    let labels = parse_key_values("# comment\nEXCP = Exception\n\nCALL=Call\n").unwrap();
+    assert_eq!(labels.get("EXCP").map(String::as_str), Some("Exception"));
+    assert_eq!(labels.get("CALL").map(String::as_str), Some("Call"));
+    assert!(parse_key_values("EXCP").is_err());
+}
+
+#[test]
+fn test_format_count() {
+    assert_eq!(format_count(0), "0");
+    assert_eq!(format_count(999), "999");
+    assert_eq!(format_count(1_203), "1,203");
+    assert_eq!(format_count(58_991), "58,991");
+    assert_eq!(format_count(1_000_000), "1,000,000");
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("evnt", "event"), 1);
+    assert_eq!(edit_distance("context", "Context"), 1);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("Sql", "Sql"), 0);
+}
+
+#[test]
+fn test_parse_interval() {
+    assert_eq!(parse_interval("1m"), Ok(Duration::minutes(1)));
+    assert_eq!(parse_interval("1h30s"), Ok(Duration::seconds(3630)));
+    assert_eq!(parse_interval(""), Err(DateError::UnexpectedEndOfInput));
+    assert_eq!(parse_interval("5x"), Err(DateError::UnexpectedChar('x')));
+}
+
+#[test]
+fn test_parse_time_partial_prefix() {
+    let hour = NaiveDateTime::parse_from_str("2022-08-02 14:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    let time = |minutes, seconds, micros| {
+        hour + Duration::minutes(minutes)
+            + Duration::seconds(seconds)
+            + Duration::microseconds(micros)
+    };
+
+    assert_eq!(parse_time(hour, "05:12.345000"), time(5, 12, 345_000));
+    assert_eq!(parse_time(hour, "05:12.345"), time(5, 12, 345_000));
+    assert_eq!(
+        parse_time(hour, "05:12.345000123"),
+        time(5, 12, 345_000) + Duration::nanoseconds(123)
+    );
+    assert_eq!(parse_time(hour, "05:12"), time(5, 12, 0));
+    assert_eq!(parse_time(hour, "05:12."), time(5, 12, 0));
+    assert_eq!(parse_time(hour, "14:"), time(14, 0, 0));
+    assert_eq!(parse_time(hour, "garbage"), hour);
+    assert_eq!(
+        parse_time(hour, "05:12.3450000000001"),
+        time(5, 12, 345_000)
+    );
+}